@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use plume_core::{AnisetteConfiguration, DEFAULT_ANISETTE_SERVERS, FailoverProvider};
+
+use crate::{Error, get_data_path, network};
+
+const SERVERS_FILE: &str = "anisette_servers.txt";
+
+// Shared between accounts so a server that went down isn't tried first every
+// time, and so saving the servers reaches the accounts already signed in
+static PROVIDER: Mutex<Option<Arc<FailoverProvider>>> = Mutex::new(None);
+
+/// The configured anisette servers in order of preference, one URL per line on disk.
+pub fn load_servers() -> Vec<String> {
+    let servers: Vec<String> = servers_path()
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|contents| parse_servers(&contents))
+        .unwrap_or_default();
+
+    if servers.is_empty() {
+        DEFAULT_ANISETTE_SERVERS.iter().map(|s| s.to_string()).collect()
+    } else {
+        servers
+    }
+}
+
+pub fn save_servers(servers: &[String]) -> Result<(), Error> {
+    fs::write(servers_path()?, servers.join("\n"))?;
+
+    if let Some(provider) = PROVIDER.lock().unwrap().as_ref() {
+        provider.set_servers(load_servers());
+    }
+
+    Ok(())
+}
+
+pub fn parse_servers(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.trim().trim_end_matches('/'))
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
+
+pub fn anisette_provider() -> Result<Arc<FailoverProvider>, Error> {
+    let mut shared = PROVIDER.lock().unwrap();
+
+    if let Some(provider) = shared.as_ref() {
+        return Ok(provider.clone());
+    }
+
+    let provider = Arc::new(new_provider(load_servers())?);
+    *shared = Some(provider.clone());
+    Ok(provider)
+}
+
+/// A provider for the given servers, not shared with anything else.
+pub fn new_provider(servers: Vec<String>) -> Result<FailoverProvider, Error> {
+    let config = AnisetteConfiguration::default()
+        .set_configuration_path(get_data_path()?);
+
+    Ok(FailoverProvider::new(config, servers).with_client(network::load_options().client()?))
+}
+
+fn servers_path() -> Result<PathBuf, Error> {
+    Ok(get_data_path()?.join(SERVERS_FILE))
+}
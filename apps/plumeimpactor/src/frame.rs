@@ -7,7 +7,7 @@ use std::{
 };
 
 use plume_core::{
-    AnisetteConfiguration, CertificateIdentity, auth::Account, developer::DeveloperSession
+    CertificateIdentity, auth::{Account, LoginFlow, LoginState, TrustedPhoneNumber, anisette_data::AnisetteData}, developer::DeveloperSession
 };
 
 use idevice::{
@@ -18,12 +18,14 @@ use plume_utils::{Device, Package, PlistInfoTrait, Signer, SignerMode, get_devic
 
 use wxdragon::prelude::*;
 use futures::StreamExt;
-use tokio::{runtime::Builder, sync::mpsc};
+use tokio::{runtime::{Builder, Runtime}, sync::mpsc};
 
 use crate::{
+    anisette::{anisette_provider, load_servers, new_provider, parse_servers, save_servers},
     get_data_path,
     handlers::{PlumeFrameMessage, PlumeFrameMessageHandler},
     keychain::AccountCredentials,
+    network,
     pages::{
         DefaultPage, InstallPage, LoginDialog, SettingsDialog, WINDOW_SIZE, WorkPage, create_default_page, create_install_page, create_login_dialog, create_settings_dialog, create_work_page
     },
@@ -138,16 +140,18 @@ impl PlumeFrame {
 impl PlumeFrame {
     fn setup_event_handlers(&mut self) {
         let (sender, receiver) = mpsc::unbounded_channel::<PlumeFrameMessage>();
-        let message_handler = self.setup_idle_handler(receiver);
+        let message_handler = self.setup_idle_handler(sender.clone(), receiver);
         Self::spawn_background_threads(sender.clone());
         self.bind_widget_handlers(sender, message_handler);
     }
 
     fn setup_idle_handler(
         &self,
+        sender: mpsc::UnboundedSender<PlumeFrameMessage>,
         receiver: mpsc::UnboundedReceiver<PlumeFrameMessage>,
     ) -> Rc<RefCell<PlumeFrameMessageHandler>> {
         let message_handler = Rc::new(RefCell::new(PlumeFrameMessageHandler::new(
+            sender,
             receiver,
             unsafe { ptr::read(self) },
         )));
@@ -232,21 +236,12 @@ impl PlumeFrame {
 
     fn spawn_auto_login_thread(sender: mpsc::UnboundedSender<PlumeFrameMessage>) {
         thread::spawn(move || {
-            let creds = AccountCredentials;
-
-            let (email, password) = match (creds.get_email(), creds.get_password()) {
-                (Ok(email), Ok(password)) => (email, password),
-                _ => { return; }
-            };
-
-            match run_login_flow(sender.clone(), &email, &password) {
-                Ok(account) => {
-                    sender.send(PlumeFrameMessage::AccountLogin(account)).ok();
-                }
-                Err(e) => {
-                    sender.send(PlumeFrameMessage::AccountDeleted).ok();
-                    sender.send(PlumeFrameMessage::Error(format!("Login error: {}", e))).ok();
-                }
+            // Older versions signed in with a saved password at launch, do that
+            // one last time so it is swapped for a session
+            match (AccountCredentials::take_legacy_credentials(), AccountCredentials::active()) {
+                (_, Some(creds)) => switch_account(sender, creds),
+                (Some((email, password)), None) => start_login_flow(sender, email, password),
+                (None, None) => {}
             }
         });
     }
@@ -283,6 +278,18 @@ impl PlumeFrame {
             }
         });
         
+        self.settings_dialog.set_accounts(&AccountCredentials::list(), None);
+
+        self.settings_dialog.set_add_account_handler({
+            let login_dialog = self.login_dialog.clone();
+            move || login_dialog.dialog.show(true)
+        });
+
+        self.settings_dialog.set_switch_account_handler({
+            let sender = sender.clone();
+            move |creds| switch_account(sender.clone(), creds)
+        });
+
         self.settings_dialog.set_logout_handler({
             let message_handler = message_handler.clone();
             let sender = sender.clone();
@@ -296,6 +303,51 @@ impl PlumeFrame {
             }
         });
 
+        // MARK: Anisette Servers
+
+        self.settings_dialog.set_servers(&load_servers());
+
+        self.settings_dialog.set_check_servers_handler({
+            let sender = sender.clone();
+            let settings_dialog = self.settings_dialog.clone();
+            move || {
+                let servers = parse_servers(&settings_dialog.get_servers());
+                let sender = sender.clone();
+
+                thread::spawn(move || {
+                    let rt = Builder::new_current_thread().enable_all().build().unwrap();
+                    let msg = match new_provider(servers) {
+                        Ok(provider) => PlumeFrameMessage::AnisetteServersChecked(rt.block_on(provider.check_servers())),
+                        Err(e) => PlumeFrameMessage::Error(format!("Failed to check anisette servers: {}", e)),
+                    };
+                    sender.send(msg).ok();
+                });
+            }
+        });
+
+        // MARK: Network Settings
+
+        self.settings_dialog.set_network_options(&network::load_options());
+
+        self.settings_dialog.set_save_handler({
+            let sender = sender.clone();
+            let settings_dialog = self.settings_dialog.clone();
+            move || {
+                let servers = parse_servers(&settings_dialog.get_servers());
+
+                if let Err(e) = save_servers(&servers) {
+                    sender.send(PlumeFrameMessage::Error(format!("Failed to save anisette servers: {}", e))).ok();
+                    return;
+                }
+
+                settings_dialog.set_servers(&load_servers());
+
+                if let Err(e) = network::save_options(&settings_dialog.get_network_options()) {
+                    sender.send(PlumeFrameMessage::Error(format!("Failed to save network settings: {}", e))).ok();
+                }
+            }
+        });
+
         // MARK: File Drop/Open Handlers
 
         fn process_package_file(sender: mpsc::UnboundedSender<PlumeFrameMessage>, file_path: PathBuf) {
@@ -432,7 +484,7 @@ impl PlumeFrame {
 
                         let cert_identity = CertificateIdentity::new_with_session(
                             &session,
-                            get_data_path(),
+                            get_data_path().map_err(|e| e.to_string())?,
                             None,
                             team_id,
                         ).await.map_err(|e| e.to_string())?;
@@ -554,26 +606,7 @@ impl PlumeFrame {
                 login_dialog.clear_fields();
                 login_dialog.dialog.hide();
 
-                thread::spawn({
-                    let email = email.clone();
-                    let password = password.clone();
-                    let sender = sender.clone();
-                    move || {
-                        match run_login_flow(sender.clone(), &email, &password) {
-                            Ok(account) => {
-                                sender.send(PlumeFrameMessage::AccountLogin(account)).ok();
-
-                                if let Err(e) = AccountCredentials.set_credentials(email, password) {
-                                    sender.send(PlumeFrameMessage::Error(format!("Failed to save credentials: {}", e))).ok();
-                                    return;
-                                }
-                            },
-                            Err(e) => {
-                                sender.send(PlumeFrameMessage::Error(format!("Login failed: {}", e))).ok();
-                            },
-                        }
-                    }
-                });
+                start_login_flow(sender.clone(), email, password);
             }
         });
 
@@ -582,34 +615,171 @@ impl PlumeFrame {
 
 // MARK: - Login flow
 
-pub fn run_login_flow(
+pub enum LoginInput {
+    Code(String),
+    Resend,
+    SmsPhone(u32),
+    SwitchToSms,
+}
+
+enum LoginStep {
+    // Carries a notice to show above the code field, e.g. after a wrong code
+    NeedsCode(LoginFlow, Option<String>),
+    NeedsPhone(LoginFlow, Vec<TrustedPhoneNumber>),
+    Done(LoginFlow),
+}
+
+pub fn start_login_flow(
+    sender: mpsc::UnboundedSender<PlumeFrameMessage>,
+    email: String,
+    password: String,
+) {
+    thread::spawn(move || {
+        let rt = Builder::new_current_thread().enable_all().build().unwrap();
+
+        let result = rt.block_on(async {
+            let anisette = AnisetteData::with_provider(anisette_provider()?).await?;
+            let account = Account::with_network(anisette, &network::load_options())?;
+            let flow = LoginFlow::start_with_account(account, &email, &password).await?;
+            Ok::<_, crate::Error>(advance_login_flow(flow, false).await?)
+        });
+
+        finish_login_step(&rt, &sender, result);
+    });
+}
+
+/// Restores the saved session of another Apple ID and makes it the active one.
+pub fn switch_account(
+    sender: mpsc::UnboundedSender<PlumeFrameMessage>,
+    creds: AccountCredentials,
+) {
+    thread::spawn(move || {
+        let rt = Builder::new_current_thread().enable_all().build().unwrap();
+
+        match rt.block_on(async { creds.restore_session(anisette_provider()?).await }) {
+            Ok(Some(account)) => {
+                sender.send(PlumeFrameMessage::AccountLogin(creds, account)).ok();
+            }
+            Ok(None) => {
+                sender.send(PlumeFrameMessage::AccountsChanged).ok();
+            }
+            // Only a session that can't work anymore is deleted, being offline or
+            // a flaky anisette server shouldn't cost the user a 2FA prompt
+            Err(e @ (crate::Error::Core(plume_core::Error::Session(_)) | crate::Error::Keyring(keyring::Error::NoEntry))) => {
+                creds.delete_session().ok();
+                sender.send(PlumeFrameMessage::AccountsChanged).ok();
+                sender.send(PlumeFrameMessage::Error(format!("Saved session for {} could not be restored, please sign in again: {}", creds.apple_id(), e))).ok();
+            }
+            Err(e) => {
+                sender.send(PlumeFrameMessage::Error(format!("Could not restore the saved session for {}, try again later: {}", creds.apple_id(), e))).ok();
+            }
+        }
+    });
+}
+
+pub fn continue_login_flow(
     sender: mpsc::UnboundedSender<PlumeFrameMessage>,
-    email: &String,
-    password: &String,
-) -> Result<Account, String> {
-    let anisette_config = AnisetteConfiguration::default()
-        .set_configuration_path(get_data_path());
-
-    let rt = Builder::new_current_thread().enable_all().build().unwrap();
-    
-    let (code_tx, code_rx) = std::sync::mpsc::channel::<Result<String, String>>();
-
-    let account_result = rt.block_on(Account::login(
-        || Ok((email.clone(), password.clone())),
-        || {
-            if sender
-                .send(PlumeFrameMessage::AwaitingTwoFactorCode(code_tx.clone()))
-                .is_err()
-            {
-                return Err("Failed to send 2FA request to main thread.".to_string());
+    mut flow: LoginFlow,
+    input: LoginInput,
+) {
+    thread::spawn(move || {
+        let rt = Builder::new_current_thread().enable_all().build().unwrap();
+
+        let result = rt.block_on(async {
+            let pick_phone = match input {
+                LoginInput::Code(code) => {
+                    match flow.submit_code(code).await.map(|_| ()) {
+                        Err(plume_core::Error::Bad2faCode) => {
+                            let notice = "That code was incorrect, try again.".to_string();
+                            return Ok(LoginStep::NeedsCode(flow, Some(notice)));
+                        }
+                        result => result?,
+                    }
+                    false
+                }
+                // The code that was already sent still works, so don't give up on the login
+                LoginInput::Resend => {
+                    match flow.resend_code().await.map(|_| ()) {
+                        Err(e @ plume_core::Error::TwoFactorRateLimited(_)) => {
+                            return Ok(LoginStep::NeedsCode(flow, Some(format!("{}.", e))));
+                        }
+                        result => result?,
+                    }
+                    false
+                }
+                LoginInput::SmsPhone(phone_id) => {
+                    flow.request_sms_code(phone_id).await?;
+                    false
+                }
+                LoginInput::SwitchToSms => true,
+            };
+
+            advance_login_flow(flow, pick_phone).await
+        });
+
+        finish_login_step(&rt, &sender, result.map_err(crate::Error::from));
+    });
+}
+
+// Runs the steps that don't need the user, stopping when a code or phone number is needed
+async fn advance_login_flow(
+    mut flow: LoginFlow,
+    mut pick_phone: bool,
+) -> Result<LoginStep, plume_core::Error> {
+    loop {
+        if pick_phone || matches!(flow.state(), LoginState::NeedsSMS2FA) {
+            pick_phone = false;
+
+            let phones = flow.trusted_phone_numbers().await?;
+            if let [phone] = phones.as_slice() {
+                flow.request_sms_code(phone.id).await?;
+                continue;
+            }
+
+            return Ok(LoginStep::NeedsPhone(flow, phones));
+        }
+
+        match flow.state() {
+            LoginState::NeedsDevice2FA => {
+                flow.request_device_code().await?;
             }
-            match code_rx.recv() {
-                Ok(result) => result,
-                Err(_) => Err("2FA process cancelled or main thread error.".to_string()),
+            _ if flow.needs_code() => return Ok(LoginStep::NeedsCode(flow, None)),
+            _ => return Ok(LoginStep::Done(flow)),
+        }
+    }
+}
+
+fn finish_login_step(
+    rt: &Runtime,
+    sender: &mpsc::UnboundedSender<PlumeFrameMessage>,
+    result: Result<LoginStep, crate::Error>,
+) {
+    let account = match result {
+        Ok(LoginStep::NeedsCode(flow, notice)) => {
+            sender.send(PlumeFrameMessage::AwaitingTwoFactorCode(flow, notice)).ok();
+            return;
+        }
+        Ok(LoginStep::NeedsPhone(flow, phones)) => {
+            sender.send(PlumeFrameMessage::AwaitingTwoFactorPhone(flow, phones)).ok();
+            return;
+        }
+        Ok(LoginStep::Done(flow)) => {
+            let creds = AccountCredentials::new(flow.username());
+            flow.finish().map(|account| (creds, account)).map_err(crate::Error::from)
+        }
+        Err(e) => Err(e),
+    };
+
+    match account {
+        Ok((creds, account)) => {
+            if let Err(e) = rt.block_on(creds.save_session(&account)) {
+                sender.send(PlumeFrameMessage::Error(format!("Failed to save session: {}", e))).ok();
             }
-        },
-        anisette_config,
-    ));
 
-    account_result.map_err(|e| e.to_string())
+            sender.send(PlumeFrameMessage::AccountLogin(creds, account)).ok();
+        }
+        Err(e) => {
+            sender.send(PlumeFrameMessage::Error(format!("Login failed: {}", e))).ok();
+        }
+    }
 }
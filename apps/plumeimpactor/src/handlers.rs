@@ -4,13 +4,15 @@ use tokio::sync::{
     mpsc::error::TryRecvError
 };
 use std::sync::mpsc as std_mpsc;
-use plume_core::auth::Account;
+use plume_core::ServerHealth;
+use plume_core::auth::{Account, LoginFlow, LoginState, TrustedPhoneNumber};
 use plume_utils::{
     SignerOptions, 
     Package, 
     Device
 };
-use crate::frame::PlumeFrame;
+use crate::frame::{LoginInput, PlumeFrame, continue_login_flow, switch_account};
+use crate::pages::TwoFactorResponse;
 use crate::keychain::AccountCredentials;
 
 #[derive(Debug)]
@@ -19,10 +21,13 @@ pub enum PlumeFrameMessage {
     DeviceDisconnected(u32),
     PackageSelected(Package),
     PackageDeselected,
-    AccountLogin(Account),
+    AccountLogin(AccountCredentials, Account),
     AccountDeleted,
-    AwaitingTwoFactorCode(std_mpsc::Sender<Result<String, String>>),
+    AccountsChanged,
+    AwaitingTwoFactorCode(LoginFlow, Option<String>),
+    AwaitingTwoFactorPhone(LoginFlow, Vec<TrustedPhoneNumber>),
     RequestTeamSelection(Vec<String>, std_mpsc::Sender<Result<i32, String>>),
+    AnisetteServersChecked(Vec<ServerHealth>),
     WorkStarted,
     WorkUpdated(String),
     WorkEnded,
@@ -30,6 +35,7 @@ pub enum PlumeFrameMessage {
 }
 
 pub struct PlumeFrameMessageHandler {
+    pub sender: mpsc::UnboundedSender<PlumeFrameMessage>,
     pub receiver: mpsc::UnboundedReceiver<PlumeFrameMessage>,
     pub plume_frame: PlumeFrame,
     // --- device ---
@@ -39,23 +45,27 @@ pub struct PlumeFrameMessageHandler {
     pub package_selected: Option<Package>,
     // --- account ---
     pub account_credentials: Option<Account>,
+    pub active_account: Option<AccountCredentials>,
     // --- signer settings ---
     pub signer_settings: SignerOptions,
 }
 
 impl PlumeFrameMessageHandler {
     pub fn new(
+        sender: mpsc::UnboundedSender<PlumeFrameMessage>,
         receiver: mpsc::UnboundedReceiver<PlumeFrameMessage>,
         plume_frame: PlumeFrame,
     ) -> Self {
         let signer_settings = SignerOptions::default();
         Self {
+            sender,
             receiver,
             plume_frame,
             usbmuxd_device_list: Vec::new(),
             usbmuxd_selected_device_id: None,
             package_selected: None,
             account_credentials: None,
+            active_account: None,
             signer_settings,
         }
     }
@@ -148,8 +158,11 @@ impl PlumeFrameMessageHandler {
                 self.plume_frame.install_page.set_settings(&self.signer_settings, None);
                 self.plume_frame.add_ipa_button.enable(true);
             }
-            PlumeFrameMessage::AccountLogin(account) => {
-                let (first, last) = account.get_name();
+            PlumeFrameMessage::AccountLogin(creds, account) => {
+                // The name is only for display, don't turn away a working login over it
+                let (first, last) = account
+                    .get_name()
+                    .unwrap_or_else(|_| ("your".to_string(), "Apple ID".to_string()));
                 let dialog = MessageDialog::builder(
                     &self.plume_frame.frame, 
                     &format!("Logged in as {} {}", first, last), 
@@ -158,34 +171,90 @@ impl PlumeFrameMessageHandler {
                 .with_style(MessageDialogStyle::OK | MessageDialogStyle::IconInformation)
                 .build();
                 dialog.show_modal();
+
+                if let Err(e) = creds.set_active() {
+                    self.handle_message(PlumeFrameMessage::Error(format!("Failed to remember the active account: {}", e)));
+                }
+
                 self.account_credentials = Some(account);
+                self.active_account = Some(creds);
                 
                 self.plume_frame.login_dialog.clear_fields();
                 self.plume_frame.login_dialog.dialog.hide();
                 self.plume_frame.settings_dialog.set_account_name(Some((first, last)));
+                self.handle_message(PlumeFrameMessage::AccountsChanged);
             }
             PlumeFrameMessage::AccountDeleted => {
-                if self.account_credentials.is_none() {
+                let Some(creds) = self.active_account.take() else {
                     return;
-                }
+                };
                 
-                let creds = AccountCredentials;
-                if let Err(e) = creds.delete_password() {
+                if let Err(e) = creds.delete_session() {
+                    self.active_account = Some(creds);
                     self.handle_message(PlumeFrameMessage::Error(format!("Failed to delete account credentials: {}", e)));
                     return;
                 }
                 
                 self.account_credentials = None;
                 self.plume_frame.settings_dialog.set_account_name(None);
+                self.handle_message(PlumeFrameMessage::AccountsChanged);
+
+                // Fall back to another saved account, if there is one
+                if let Some(next) = AccountCredentials::list().into_iter().next() {
+                    switch_account(self.sender.clone(), next);
+                }
+            }
+            PlumeFrameMessage::AccountsChanged => {
+                self.plume_frame
+                    .settings_dialog
+                    .set_accounts(&AccountCredentials::list(), self.active_account.as_ref());
+            }
+            PlumeFrameMessage::AwaitingTwoFactorCode(flow, notice) => {
+                let (label, alternate_label) = match flow.state() {
+                    LoginState::NeedsSMS2FAVerification(_) => ("Enter the verification code sent to your phone:", "Use Another Number"),
+                    _ => ("Enter the verification code sent to your device:", "Text Me Instead"),
+                };
+
+                let label = match notice {
+                    Some(notice) => format!("{}\n\n{}", notice, label),
+                    None => label.to_string(),
+                };
+
+                let input = match self.plume_frame.create_two_factor_dialog(&label, alternate_label) {
+                    TwoFactorResponse::Code(code) => LoginInput::Code(code),
+                    TwoFactorResponse::Resend => LoginInput::Resend,
+                    TwoFactorResponse::Alternate => LoginInput::SwitchToSms,
+                    TwoFactorResponse::Cancelled => {
+                        self.handle_message(PlumeFrameMessage::Error("Login failed: 2FA cancelled".to_string()));
+                        return;
+                    }
+                };
+
+                continue_login_flow(self.sender.clone(), flow, input);
             }
-            PlumeFrameMessage::AwaitingTwoFactorCode(tx) => {
-                let result = self.plume_frame.create_single_field_dialog(
-                    "Two-Factor Authentication",
-                    "Enter the verification code sent to your device:",
+            PlumeFrameMessage::AwaitingTwoFactorPhone(flow, phones) => {
+                if phones.is_empty() {
+                    self.handle_message(PlumeFrameMessage::Error("Login failed: this Apple ID has no trusted phone numbers".to_string()));
+                    return;
+                }
+
+                let result = self.plume_frame.create_text_selection_dialog(
+                    "Choose a Phone Number",
+                    "Where should the verification code be sent?",
+                    phones.iter().map(|p| p.masked_number()).collect(),
                 );
 
-                if let Err(e) = tx.send(result) {
-                    self.handle_message(PlumeFrameMessage::Error(format!("Failed to send two-factor code response: {}", e)));
+                // A negative index means nothing was selected
+                let phone = result.and_then(|index| {
+                    usize::try_from(index)
+                        .ok()
+                        .and_then(|index| phones.get(index))
+                        .ok_or_else(|| "No phone number selected".to_string())
+                });
+
+                match phone {
+                    Ok(phone) => continue_login_flow(self.sender.clone(), flow, LoginInput::SmsPhone(phone.id)),
+                    Err(e) => self.handle_message(PlumeFrameMessage::Error(format!("Login failed: {}", e))),
                 }
             }
             PlumeFrameMessage::RequestTeamSelection(teams, tx) => {
@@ -199,6 +268,22 @@ impl PlumeFrameMessageHandler {
                     self.handle_message(PlumeFrameMessage::Error(format!("Failed to send team selection response: {}", e)));
                 }
             }
+            PlumeFrameMessage::AnisetteServersChecked(health) => {
+                let report = health
+                    .iter()
+                    .map(|server| match (&server.latency, &server.error) {
+                        (Some(latency), None) => format!("{}: {} ms", server.url, latency.as_millis()),
+                        (_, Some(error)) => format!("{}: unavailable ({})", server.url, error),
+                        (None, None) => format!("{}: unavailable", server.url),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                let dialog = MessageDialog::builder(&self.plume_frame.frame, &report, "Anisette Servers")
+                    .with_style(MessageDialogStyle::OK | MessageDialogStyle::IconInformation)
+                    .build();
+                dialog.show_modal();
+            }
             PlumeFrameMessage::WorkStarted => {
                 self.plume_frame.install_page.panel.hide();
                 self.plume_frame.work_page.enable_back_button(false);
@@ -206,6 +291,7 @@ impl PlumeFrameMessageHandler {
                 self.plume_frame.frame.layout();
             }
             PlumeFrameMessage::WorkUpdated(status_text) => {
+                tracing::info!(status = %status_text, "work updated");
                 self.plume_frame.work_page.set_status_text(&status_text);
             }
             PlumeFrameMessage::WorkEnded => {
@@ -213,6 +299,7 @@ impl PlumeFrameMessageHandler {
                 self.plume_frame.work_page.enable_back_button(true);
             }
             PlumeFrameMessage::Error(error_msg) => {
+                tracing::error!(error = %error_msg);
                 let dialog = MessageDialog::builder(&self.plume_frame.frame, &error_msg, "Error")
                     .with_style(MessageDialogStyle::OK | MessageDialogStyle::IconWarning)
                     .build();
@@ -1,37 +1,157 @@
-use keyring::{Entry, Error};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use keyring::Entry;
+use plume_core::{AnisetteProvider, auth::{Account, anisette_data::AnisetteData}};
+
+use crate::{Error, get_data_path, network};
 
 const KEYRING_SERVICE: &str = env!("CARGO_PKG_NAME");
-const KEYRING_EMAIL: &str = "Apple ID Email";
-const KEYRING_PASS: &str = "Apple ID Password";
+const KEYRING_SESSION_KEY: &str = "Apple ID Session Key";
+// Left behind by versions that saved the password instead of a session
+const KEYRING_LEGACY_EMAIL: &str = "Apple ID Email";
+const KEYRING_LEGACY_PASS: &str = "Apple ID Password";
 
-pub struct AccountCredentials;
+const ACCOUNTS_DIR: &str = "accounts";
+const ACTIVE_ACCOUNT_FILE: &str = "active_account";
+const SESSION_FILE: &str = "session.bin";
+
+/// The saved session of one Apple ID, each gets its own keyring entry and
+/// directory under `get_data_path()/accounts`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountCredentials {
+    apple_id: String,
+}
 
 impl AccountCredentials {
-    pub fn set_credentials(&self, email: String, password: String) -> Result<(), Error> {
-        let entry_email = Entry::new(KEYRING_SERVICE, KEYRING_EMAIL)?;
-        let entry_pass  = Entry::new(KEYRING_SERVICE, KEYRING_PASS)?;
-        entry_email.set_password(&email)?;
-        entry_pass.set_password(&password)?;
+    pub fn new(apple_id: &str) -> Self {
+        Self { apple_id: Account::normalize_apple_id(apple_id) }
+    }
+
+    pub fn apple_id(&self) -> &str {
+        &self.apple_id
+    }
+
+    /// Every Apple ID with a saved session, sorted.
+    pub fn list() -> Vec<Self> {
+        let Some(entries) = Self::accounts_path().ok().and_then(|path| fs::read_dir(path).ok()) else {
+            return Vec::new();
+        };
+
+        let mut accounts: Vec<Self> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().join(SESSION_FILE).exists())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .map(|apple_id| Self { apple_id })
+            .collect();
+
+        accounts.sort_by(|a, b| a.apple_id.cmp(&b.apple_id));
+        accounts
+    }
+
+    /// The account that was used last, if its session is still around.
+    pub fn active() -> Option<Self> {
+        let apple_id = fs::read_to_string(Self::accounts_path().ok()?.join(ACTIVE_ACCOUNT_FILE)).ok()?;
+        let creds = Self::new(&apple_id);
+
+        creds.session_path().ok()?.exists().then_some(creds)
+    }
+
+    pub fn set_active(&self) -> Result<(), Error> {
+        fs::write(Self::accounts_path()?.join(ACTIVE_ACCOUNT_FILE), &self.apple_id)?;
         Ok(())
     }
 
-    pub fn get_email(&self) -> Result<String, Error> {
-        let entry = Entry::new(KEYRING_SERVICE, KEYRING_EMAIL)?;
-        entry.get_password()
+    /// Saves the encrypted session to disk, the key to decrypt it lives in the keyring.
+    pub async fn save_session(&self, account: &Account) -> Result<(), Error> {
+        let key = Account::generate_session_key();
+        let blob = account.export_session(&key).await?;
+
+        self.key_entry()?.set_secret(&key)?;
+        fs::create_dir_all(self.data_path()?)?;
+        fs::write(self.session_path()?, blob)?;
+
+        Ok(())
     }
 
-    pub fn get_password(&self) -> Result<String, Error> {
-        let entry = Entry::new(KEYRING_SERVICE, KEYRING_PASS)?;
-        entry.get_password()
+    /// Returns `None` if there is no saved session. Expired sessions are
+    /// refreshed with their PET and saved again.
+    pub async fn restore_session(&self, provider: Arc<dyn AnisetteProvider>) -> Result<Option<Account>, Error> {
+        let path = self.session_path()?;
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let key = self.key_entry()?.get_secret()?;
+        let blob = fs::read(path)?;
+
+        let anisette = AnisetteData::with_provider(provider).await?;
+        let account = Account::with_network(anisette, &network::load_options())?;
+
+        let (account, refreshed) = Account::restore_or_refresh_session(&blob, &key, account).await?;
+
+        if refreshed {
+            self.save_session(&account).await?;
+        }
+
+        Ok(Some(account))
     }
 
-    pub fn delete_password(&self) -> Result<(), Error> {
-        let entry_email = Entry::new(KEYRING_SERVICE, KEYRING_EMAIL)?;
-        let entry_pass = Entry::new(KEYRING_SERVICE, KEYRING_PASS)?;
+    pub fn delete_session(&self) -> Result<(), Error> {
+        let path = self.session_path()?;
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        // Only goes away once empty, plumesign keeps its sessions in here too
+        fs::remove_dir(self.data_path()?).ok();
 
-        entry_email.delete_credential()?;
-        entry_pass.delete_credential()?;
+        match self.key_entry()?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        if Self::active().is_none() {
+            fs::remove_file(Self::accounts_path()?.join(ACTIVE_ACCOUNT_FILE)).ok();
+        }
 
         Ok(())
     }
+
+    /// The email and password saved by versions before sessions, removed
+    /// from the keyring as they're read. Signing in with them once leaves a
+    /// session in their place.
+    pub fn take_legacy_credentials() -> Option<(String, String)> {
+        let take = |user: &str| -> Option<String> {
+            let entry = Entry::new(KEYRING_SERVICE, user).ok()?;
+            let secret = entry.get_password().ok();
+            entry.delete_credential().ok();
+            secret
+        };
+
+        // Both are taken even if one is missing, so neither outlives this
+        let email = take(KEYRING_LEGACY_EMAIL);
+        let password = take(KEYRING_LEGACY_PASS);
+
+        Some((email?, password?))
+    }
+
+    fn key_entry(&self) -> Result<Entry, Error> {
+        Ok(Entry::new(KEYRING_SERVICE, &format!("{} ({})", KEYRING_SESSION_KEY, self.apple_id))?)
+    }
+
+    fn data_path(&self) -> Result<PathBuf, Error> {
+        Ok(Self::accounts_path()?.join(&self.apple_id))
+    }
+
+    fn session_path(&self) -> Result<PathBuf, Error> {
+        Ok(self.data_path()?.join(SESSION_FILE))
+    }
+
+    fn accounts_path() -> Result<PathBuf, Error> {
+        let path = get_data_path()?.join(ACCOUNTS_DIR);
+        fs::create_dir_all(&path).ok();
+        Ok(path)
+    }
 }
@@ -0,0 +1,47 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use tracing_subscriber::EnvFilter;
+
+use crate::{Error, get_data_path};
+
+const LOG_FILE: &str = "plumeimpactor.log";
+const OLD_LOG_FILE: &str = "plumeimpactor.old.log";
+// Kept across launches, rotated once it gets bigger than this
+const MAX_LOG_SIZE: u64 = 5 * 1024 * 1024;
+
+const DEFAULT_FILTER: &str = "plumeimpactor=debug,plume_core=debug,plume_utils=debug";
+
+/// Appends logs to `log_path()`, `RUST_LOG` overrides what gets logged.
+/// Logging is best effort, a log file that can't be opened is not an error.
+pub fn init() {
+    let Ok(path) = log_path() else {
+        return;
+    };
+
+    if fs::metadata(&path).is_ok_and(|m| m.len() > MAX_LOG_SIZE) {
+        fs::rename(&path, path.with_file_name(OLD_LOG_FILE)).ok();
+    }
+
+    let Ok(file) = OpenOptions::new().create(true).append(true).open(&path) else {
+        return;
+    };
+
+    let filter = env::var(EnvFilter::DEFAULT_ENV)
+        .map(EnvFilter::new)
+        .unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(false)
+        .with_writer(Mutex::new(file))
+        .init();
+
+    tracing::info!(version = env!("CARGO_PKG_VERSION"), "started");
+}
+
+pub fn log_path() -> Result<PathBuf, Error> {
+    Ok(get_data_path()?.join(LOG_FILE))
+}
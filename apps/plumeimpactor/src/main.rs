@@ -1,19 +1,19 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod anisette;
 mod frame;
 mod keychain;
+mod logging;
+mod network;
 mod pages;
 mod handlers;
 
-use std::{
-    env, 
-    fs, 
-    path::{Path, PathBuf}
-};
+pub use plume_utils::get_data_path;
 
 #[tokio::main]
 async fn main() {
     _ = rustls::crypto::ring::default_provider().install_default().unwrap();
+    logging::init();
 
     let _ = wxdragon::main(|_| {
         frame::PlumeFrame::new().show();
@@ -34,25 +34,13 @@ pub enum Error {
     Core(#[from] plume_core::Error),
     #[error("Utils error: {0}")]
     Utils(#[from] plume_utils::Error),
-}
-
-pub fn get_data_path() -> PathBuf {
-    let base = if cfg!(windows) {
-        env::var("APPDATA").unwrap()
-    } else {
-        env::var("HOME").unwrap() + "/.config"
-    };
-
-    let dir = Path::new(&base).join("PlumeImpactor");
-
-    fs::create_dir_all(&dir).ok();
-    
-    dir
+    #[error("Keyring error: {0}")]
+    Keyring(#[from] keyring::Error),
 }
 
 #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
 pub fn get_mac_udid() -> Option<String> {
-    let exe_dir = env::current_exe().ok()?.parent()?.to_path_buf();
+    let exe_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
     let udid_path = exe_dir.join("udid");
     
     if !udid_path.exists() {
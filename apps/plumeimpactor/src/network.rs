@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+
+use plume_core::auth::NetworkOptions;
+
+use crate::{Error, get_data_path};
+
+const NETWORK_FILE: &str = "network.plist";
+
+/// The proxy, root certificates and logging set in the settings dialog.
+pub fn load_options() -> NetworkOptions {
+    options_path()
+        .ok()
+        .and_then(|path| plist::from_file(path).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_options(options: &NetworkOptions) -> Result<(), Error> {
+    // Fail here rather than on the next login
+    options.client()?;
+
+    plist::to_file_xml(options_path()?, options)?;
+    Ok(())
+}
+
+fn options_path() -> Result<PathBuf, Error> {
+    Ok(get_data_path()?.join(NETWORK_FILE))
+}
@@ -4,6 +4,7 @@ pub use default::{DefaultPage, create_default_page};
 mod settings;
 pub use settings::{LoginDialog, create_login_dialog};
 pub use settings::{SettingsDialog, create_settings_dialog};
+pub use settings::TwoFactorResponse;
 
 mod install;
 pub use install::{InstallPage, create_install_page};
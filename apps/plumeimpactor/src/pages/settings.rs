@@ -1,6 +1,11 @@
+use std::{cell::RefCell, path::PathBuf, rc::Rc};
+
+use plume_core::auth::NetworkOptions;
 use wxdragon::prelude::*;
 
 use crate::frame::PlumeFrame;
+use crate::logging;
+use crate::keychain::AccountCredentials;
 use super::DIALOG_SIZE;
 
 #[derive(Clone)]
@@ -90,6 +95,15 @@ pub struct SettingsDialog {
     pub dialog: Dialog,
     pub logout_button: Button,
     pub account_label: StaticText,
+    pub account_picker: Choice,
+    pub add_account_button: Button,
+    accounts: Rc<RefCell<Vec<AccountCredentials>>>,
+    pub servers_field: TextCtrl,
+    pub check_servers_button: Button,
+    pub proxy_field: TextCtrl,
+    pub certificates_field: TextCtrl,
+    pub debug_logging_checkbox: CheckBox,
+    pub save_button: Button,
 }
 
 pub fn create_settings_dialog(parent: &Window) -> SettingsDialog {
@@ -108,15 +122,81 @@ pub fn create_settings_dialog(parent: &Window) -> SettingsDialog {
     account_row.add(&logout_button, 1, SizerFlag::Expand, 0);
 
     sizer.add_sizer(&account_row, 0, SizerFlag::Right | SizerFlag::Left, 13);
+    sizer.add_spacer(8);
+
+    let switch_row = BoxSizer::builder(Orientation::Horizontal).build();
+    let account_picker = Choice::builder(&dialog).build();
+    let add_account_button = Button::builder(&dialog).with_label("Add Account").build();
+    switch_row.add(&account_picker, 4, SizerFlag::Expand, 0);
+    switch_row.add_stretch_spacer(1);
+    switch_row.add(&add_account_button, 1, SizerFlag::Expand, 0);
+
+    sizer.add_sizer(&switch_row, 0, SizerFlag::Expand | SizerFlag::Right | SizerFlag::Left, 13);
 
     sizer.add(&StaticLine::builder(&dialog).build(), 0, SizerFlag::Expand | SizerFlag::All, 13);
 
+    let servers_label = StaticText::builder(&dialog)
+        .with_label("Anisette servers, one per line in order of preference:")
+        .build();
+    sizer.add(&servers_label, 0, SizerFlag::Right | SizerFlag::Left, 13);
+    sizer.add_spacer(4);
+
+    let servers_field = TextCtrl::builder(&dialog)
+        .with_style(TextCtrlStyle::MultiLine)
+        .build();
+    sizer.add(&servers_field, 1, SizerFlag::Expand | SizerFlag::Right | SizerFlag::Left, 13);
+
+    let check_servers_button = Button::builder(&dialog).with_label("Check Servers").build();
+    sizer.add(&check_servers_button, 0, SizerFlag::AlignRight | SizerFlag::All, 13);
+
+    sizer.add(&StaticLine::builder(&dialog).build(), 0, SizerFlag::Expand | SizerFlag::Right | SizerFlag::Left, 13);
+    sizer.add_spacer(13);
+
+    let proxy_row = BoxSizer::builder(Orientation::Horizontal).build();
+    let proxy_label = StaticText::builder(&dialog).with_label("Proxy:").build();
+    let proxy_field = TextCtrl::builder(&dialog).build();
+    proxy_field.set_tooltip("http://, https:// or socks5:// proxy used for all traffic to Apple, leave empty for none.");
+    proxy_row.add(&proxy_label, 0, SizerFlag::AlignCenterVertical | SizerFlag::Right, 8);
+    proxy_row.add(&proxy_field, 1, SizerFlag::Expand, 0);
+    sizer.add_sizer(&proxy_row, 0, SizerFlag::Expand | SizerFlag::Right | SizerFlag::Left, 13);
+    sizer.add_spacer(8);
+
+    let certificates_row = BoxSizer::builder(Orientation::Horizontal).build();
+    let certificates_label = StaticText::builder(&dialog).with_label("Root certificates:").build();
+    let certificates_field = TextCtrl::builder(&dialog).build();
+    certificates_field.set_tooltip("Paths to extra PEM or DER root certificates to trust, separated by ;. Needed when a proxy intercepts TLS.");
+    certificates_row.add(&certificates_label, 0, SizerFlag::AlignCenterVertical | SizerFlag::Right, 8);
+    certificates_row.add(&certificates_field, 1, SizerFlag::Expand, 0);
+    sizer.add_sizer(&certificates_row, 0, SizerFlag::Expand | SizerFlag::Right | SizerFlag::Left, 13);
+    sizer.add_spacer(8);
+
+    let debug_logging_checkbox = CheckBox::builder(&dialog)
+        .with_label("Log requests to Apple")
+        .build();
+    debug_logging_checkbox.set_tooltip(&match logging::log_path() {
+        Ok(path) => format!("Also logs the headers of every request, tokens are left out. Logs are kept in {}.", path.display()),
+        Err(e) => format!("Also logs the headers of every request, tokens are left out. Logs can't be kept: {}.", e),
+    });
+    sizer.add(&debug_logging_checkbox, 0, SizerFlag::Right | SizerFlag::Left, 13);
+
+    let save_button = Button::builder(&dialog).with_label("Save").build();
+    sizer.add(&save_button, 0, SizerFlag::AlignRight | SizerFlag::All, 13);
+
     dialog.set_sizer(sizer, true);
 
     SettingsDialog {
         dialog,
         logout_button,
         account_label,
+        account_picker,
+        add_account_button,
+        accounts: Rc::new(RefCell::new(Vec::new())),
+        servers_field,
+        check_servers_button,
+        proxy_field,
+        certificates_field,
+        debug_logging_checkbox,
+        save_button,
     }
 }
 
@@ -127,6 +207,91 @@ impl SettingsDialog {
         });
     }
 
+    pub fn set_add_account_handler(&self, on_add: impl Fn() + 'static) {
+        self.add_account_button.on_click(move |_| {
+            on_add();
+        });
+    }
+
+    pub fn set_switch_account_handler(&self, on_switch: impl Fn(AccountCredentials) + 'static) {
+        let picker = self.account_picker.clone();
+        let accounts = self.accounts.clone();
+        self.account_picker.on_selection_changed(move |_| {
+            let selected = picker
+                .get_selection()
+                .and_then(|i| accounts.borrow().get(i as usize).cloned());
+
+            if let Some(creds) = selected {
+                on_switch(creds);
+            }
+        });
+    }
+
+    /// Fills the picker with the saved accounts and selects the active one.
+    pub fn set_accounts(&self, accounts: &[AccountCredentials], active: Option<&AccountCredentials>) {
+        self.account_picker.clear();
+        for creds in accounts {
+            self.account_picker.append(creds.apple_id());
+        }
+
+        if let Some(index) = active.and_then(|active| accounts.iter().position(|c| c == active)) {
+            self.account_picker.set_selection(index as u32);
+        }
+
+        self.account_picker.enable(!accounts.is_empty());
+        *self.accounts.borrow_mut() = accounts.to_vec();
+    }
+
+    pub fn set_check_servers_handler(&self, on_check: impl Fn() + 'static) {
+        self.check_servers_button.on_click(move |_| {
+            on_check();
+        });
+    }
+
+    pub fn set_save_handler(&self, on_save: impl Fn() + 'static) {
+        self.save_button.on_click(move |_| {
+            on_save();
+        });
+    }
+
+    pub fn get_servers(&self) -> String {
+        self.servers_field.get_value().to_string()
+    }
+
+    pub fn set_servers(&self, servers: &[String]) {
+        self.servers_field.set_value(&servers.join("\n"));
+    }
+
+    pub fn get_network_options(&self) -> NetworkOptions {
+        let proxy = self.proxy_field.get_value().trim().to_string();
+
+        NetworkOptions {
+            proxy: (!proxy.is_empty()).then_some(proxy),
+            root_certificates: self
+                .certificates_field
+                .get_value()
+                .split(';')
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .collect(),
+            debug_logging: self.debug_logging_checkbox.get_value(),
+        }
+    }
+
+    pub fn set_network_options(&self, options: &NetworkOptions) {
+        self.proxy_field.set_value(options.proxy.as_deref().unwrap_or_default());
+        self.certificates_field.set_value(
+            &options
+                .root_certificates
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join("; "),
+        );
+        self.debug_logging_checkbox.set_value(options.debug_logging);
+    }
+
     pub fn set_account_name(&self, account_name: Option<(String, String)>) {
         match account_name {
             Some((first, last)) => {
@@ -141,10 +306,22 @@ impl SettingsDialog {
     }
 }
 
-// MARK: - Single Field Dialog
+// MARK: - Two Factor Dialog
+
+// Returned from `show_modal` for the buttons that aren't OK or Cancel
+const TWO_FACTOR_ALTERNATE: i32 = 1;
+const TWO_FACTOR_RESEND: i32 = 2;
+
+pub enum TwoFactorResponse {
+    Code(String),
+    Resend,
+    Alternate,
+    Cancelled,
+}
+
 impl PlumeFrame {
-    pub fn create_single_field_dialog(&self, title: &str, label: &str) -> Result<String, String> {
-        let dialog = Dialog::builder(&self.frame, title)
+    pub fn create_two_factor_dialog(&self, label: &str, alternate_label: &str) -> TwoFactorResponse {
+        let dialog = Dialog::builder(&self.frame, "Two-Factor Authentication")
             .with_style(DialogStyle::SystemMenu | DialogStyle::Caption)
             .with_size(DIALOG_SIZE.0, DIALOG_SIZE.1)
             .build();
@@ -163,9 +340,15 @@ impl PlumeFrame {
 
         let button_sizer = BoxSizer::builder(Orientation::Horizontal).build();
 
+        let alternate_button = Button::builder(&dialog).with_label(alternate_label).build();
+        let resend_button = Button::builder(&dialog).with_label("Resend Code").build();
         let cancel_button = Button::builder(&dialog).with_label("Cancel").build();
         let ok_button = Button::builder(&dialog).with_label("OK").build();
 
+        button_sizer.add(&alternate_button, 0, SizerFlag::All, 8);
+        button_sizer.add_spacer(8);
+        button_sizer.add(&resend_button, 0, SizerFlag::All, 8);
+        button_sizer.add_spacer(8);
         button_sizer.add(&cancel_button, 0, SizerFlag::All, 8);
         button_sizer.add_spacer(8);
         button_sizer.add(&ok_button, 0, SizerFlag::All, 8);
@@ -174,6 +357,14 @@ impl PlumeFrame {
 
         dialog.set_sizer(sizer, true);
 
+        alternate_button.on_click({
+            let dialog = dialog.clone();
+            move |_| dialog.end_modal(TWO_FACTOR_ALTERNATE)
+        });
+        resend_button.on_click({
+            let dialog = dialog.clone();
+            move |_| dialog.end_modal(TWO_FACTOR_RESEND)
+        });
         cancel_button.on_click({
             let dialog = dialog.clone();
             move |_| dialog.end_modal(ID_CANCEL as i32)
@@ -187,9 +378,13 @@ impl PlumeFrame {
 
         let rc = dialog.show_modal();
         let result = if rc == ID_OK as i32 {
-            Ok(text_field.get_value().to_string())
+            TwoFactorResponse::Code(text_field.get_value().to_string())
+        } else if rc == TWO_FACTOR_RESEND {
+            TwoFactorResponse::Resend
+        } else if rc == TWO_FACTOR_ALTERNATE {
+            TwoFactorResponse::Alternate
         } else {
-            Err("2FA cancelled".to_string())
+            TwoFactorResponse::Cancelled
         };
         dialog.destroy();
         result
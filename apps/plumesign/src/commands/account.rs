@@ -0,0 +1,544 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+
+use clap::{Args, Subcommand};
+use keyring::Entry;
+use serde_json::json;
+
+use plume_core::{
+    AnisetteConfiguration,
+    FailoverProvider,
+    MockProvider,
+    auth::{Account, LoginFlow, LoginState, NetworkOptions, anisette_data::AnisetteData},
+    developer::DeveloperSession,
+};
+
+use crate::{Error, get_data_path, output};
+
+const ACCOUNTS_DIR: &str = "accounts";
+// The GUI keeps its key in the keyring, so keep our files apart from its own
+const ACTIVE_ACCOUNT_FILE: &str = "cli_active_account";
+const SESSION_FILE: &str = "cli_session.bin";
+// Only used when logged in with `--key-file`, the keyring is used otherwise
+const SESSION_KEY_FILE: &str = "cli_session.key";
+
+const KEYRING_SERVICE: &str = env!("CARGO_PKG_NAME");
+const KEYRING_SESSION_KEY: &str = "Apple ID Session Key";
+
+// Set from `--account`, takes precedence over the active account
+static ACCOUNT_OVERRIDE: OnceLock<String> = OnceLock::new();
+// Set from `--record-traffic` or `--replay-traffic`
+static TRAFFIC: OnceLock<TrafficArg> = OnceLock::new();
+
+// Set from `--proxy`, `--ca-cert` and `--debug-http`
+static NETWORK: OnceLock<NetworkOptions> = OnceLock::new();
+// Set from `--anisette-server`, omnisette picks a provider when empty
+static ANISETTE_SERVERS: OnceLock<Vec<String>> = OnceLock::new();
+
+#[derive(Debug)]
+enum TrafficArg {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+#[derive(Debug, Args)]
+pub struct AccountArgs {
+    #[command(subcommand)]
+    pub command: AccountCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AccountCommands {
+    /// Sign in with your Apple ID and save the session for later commands
+    Login(LoginArgs),
+    /// Remove the saved session of the active account, or of the given one
+    Logout(LogoutArgs),
+    /// Show who is signed in and whether the saved session still works
+    Status,
+    /// List the Apple IDs with a saved session
+    List,
+    /// Make another saved Apple ID the one later commands use
+    Switch(SwitchArgs),
+    /// Log in again with the saved session's token instead of the password, without 2FA
+    Refresh,
+}
+
+#[derive(Debug, Args)]
+pub struct LoginArgs {
+    #[arg(long = "username", short = 'u', value_name = "EMAIL", help = "Apple ID email, prompted for if omitted")]
+    pub username: Option<String>,
+
+    #[arg(long = "password", short = 'p', value_name = "PASSWORD", env = "PLUME_PASSWORD", hide_env_values = true, help = "Apple ID password, prompted for if omitted")]
+    pub password: Option<String>,
+
+    #[arg(long = "key-file", env = "PLUME_KEY_FILE", help = "Keep the session key in a file next to the session instead of the system keyring, for hosts without one. Anyone who can read that directory can use the session")]
+    pub key_file: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct LogoutArgs {
+    #[arg(value_name = "EMAIL", help = "Apple ID to log out, defaults to the active one")]
+    pub apple_id: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct SwitchArgs {
+    #[arg(value_name = "EMAIL", help = "Apple ID to switch to")]
+    pub apple_id: String,
+}
+
+pub async fn execute(args: AccountArgs) -> Result<(), Error> {
+    match args.command {
+        AccountCommands::Login(args) => login(args).await,
+        AccountCommands::Logout(args) => logout(args),
+        AccountCommands::Status => status().await,
+        AccountCommands::List => list(),
+        AccountCommands::Switch(args) => switch(args),
+        AccountCommands::Refresh => refresh().await,
+    }
+}
+
+/// Records or replays the developer service traffic of `load_session`.
+pub fn set_traffic(record: Option<PathBuf>, replay: Option<PathBuf>) {
+    let traffic = match (record, replay) {
+        (_, Some(dir)) => TrafficArg::Replay(dir),
+        (Some(dir), None) => TrafficArg::Record(dir),
+        (None, None) => return,
+    };
+
+    TRAFFIC.set(traffic).ok();
+}
+
+pub fn set_network_options(options: NetworkOptions) {
+    NETWORK.set(options).ok();
+}
+
+pub fn set_anisette_servers(servers: Vec<String>) {
+    let servers = servers
+        .iter()
+        .map(|server| server.trim().trim_end_matches('/').to_string())
+        .filter(|server| !server.is_empty())
+        .collect();
+
+    ANISETTE_SERVERS.set(servers).ok();
+}
+
+/// Uses `apple_id` instead of the active account for this run.
+pub fn set_account_override(apple_id: Option<String>) {
+    if let Some(apple_id) = apple_id {
+        ACCOUNT_OVERRIDE.set(Account::normalize_apple_id(&apple_id)).ok();
+    }
+}
+
+async fn login(args: LoginArgs) -> Result<(), Error> {
+    let username = match args.username {
+        Some(username) => username,
+        None => prompt("Apple ID: ")?,
+    };
+
+    let password = match args.password {
+        Some(password) => password,
+        None => rpassword::prompt_password("Password: ")?,
+    };
+
+    let mut flow = LoginFlow::start_with_account(new_account().await?, &username, &password).await?;
+
+    loop {
+        match flow.state() {
+            LoginState::NeedsDevice2FA => {
+                flow.request_device_code().await?;
+            }
+            LoginState::NeedsSMS2FA => {
+                let phone_id = choose_phone(&flow).await?;
+                flow.request_sms_code(phone_id).await?;
+            }
+            LoginState::Needs2FAVerification => {
+                let code = prompt("Enter the verification code sent to your device (\"resend\" for a new one, \"sms\" to get a text instead): ")?;
+
+                if code.eq_ignore_ascii_case("sms") {
+                    let phone_id = choose_phone(&flow).await?;
+                    flow.request_sms_code(phone_id).await?;
+                } else {
+                    submit_code(&mut flow, code).await?;
+                }
+            }
+            LoginState::NeedsSMS2FAVerification(_) => {
+                let code = prompt("Enter the verification code sent to your phone (\"resend\" for a new one): ")?;
+                submit_code(&mut flow, code).await?;
+            }
+            _ => break,
+        }
+    }
+
+    let apple_id = Account::normalize_apple_id(flow.username());
+    let account = flow.finish()?;
+
+    save_session(&apple_id, &account, args.key_file).await?;
+    set_active_account(&apple_id)?;
+
+    let (first, last) = account.get_name()?;
+    output::emit(
+        &json!({ "logged_in": true, "apple_id": apple_id, "first_name": first, "last_name": last }),
+        || println!("Logged in as {} {} ({})", first, last, apple_id),
+    );
+
+    Ok(())
+}
+
+// Wrong codes are retried instead of failing the login, since retrying blindly
+// from scratch is what gets accounts locked
+async fn submit_code(flow: &mut LoginFlow, code: String) -> Result<(), Error> {
+    let result = if code.eq_ignore_ascii_case("resend") {
+        flow.resend_code().await.map(|_| ())
+    } else {
+        flow.submit_code(code).await.map(|_| ())
+    };
+
+    match result {
+        Err(plume_core::Error::Bad2faCode) => {
+            eprintln!("That code was incorrect, try again.");
+            Ok(())
+        }
+        Err(e @ plume_core::Error::TwoFactorRateLimited(_)) => {
+            eprintln!("{}", e);
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
+
+async fn choose_phone(flow: &LoginFlow) -> Result<u32, Error> {
+    let phones = flow.trusted_phone_numbers().await?;
+
+    match phones.as_slice() {
+        [] => Err(Error::Other("This Apple ID has no trusted phone numbers".to_string())),
+        [phone] => Ok(phone.id),
+        _ => {
+            for (i, phone) in phones.iter().enumerate() {
+                eprintln!("  {}) {}", i + 1, phone.masked_number());
+            }
+
+            let choice = prompt("Send the code to: ")?;
+            choice.parse::<usize>()
+                .ok()
+                .and_then(|i| phones.get(i.checked_sub(1)?))
+                .map(|p| p.id)
+                .ok_or_else(|| Error::Other(format!("Invalid choice {}", choice)))
+        }
+    }
+}
+
+fn logout(args: LogoutArgs) -> Result<(), Error> {
+    let apple_id = match args.apple_id {
+        Some(apple_id) => Account::normalize_apple_id(&apple_id),
+        None => match active_account() {
+            Some(apple_id) => apple_id,
+            None => {
+                output::emit(&json!({ "logged_in": false }), || println!("Not logged in"));
+                return Ok(());
+            }
+        },
+    };
+
+    let dir = account_path(&apple_id)?;
+    if !dir.join(SESSION_FILE).exists() {
+        return Err(Error::Other(format!("No saved session for {}", apple_id)));
+    }
+
+    fs::remove_file(dir.join(SESSION_FILE))?;
+    fs::remove_file(dir.join(SESSION_KEY_FILE)).ok();
+    match key_entry(&apple_id)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => {}
+        Err(e) => return Err(e.into()),
+    }
+    // Other files in there may belong to the GUI
+    fs::remove_dir(&dir).ok();
+
+    if active_account().is_none() {
+        fs::remove_file(accounts_path()?.join(ACTIVE_ACCOUNT_FILE)).ok();
+    }
+
+    output::emit(
+        &json!({ "logged_in": false, "apple_id": apple_id }),
+        || println!("Logged out of {}", apple_id),
+    );
+
+    Ok(())
+}
+
+fn list() -> Result<(), Error> {
+    let accounts = saved_accounts();
+    let active = active_account();
+
+    output::emit(
+        &json!({ "accounts": accounts, "active": active }),
+        || {
+            if accounts.is_empty() {
+                println!("No saved accounts, run `plumesign account login` to add one");
+            }
+            for apple_id in &accounts {
+                let marker = if Some(apple_id) == active.as_ref() { "*" } else { " " };
+                println!("{} {}", marker, apple_id);
+            }
+        },
+    );
+
+    Ok(())
+}
+
+fn switch(args: SwitchArgs) -> Result<(), Error> {
+    let apple_id = Account::normalize_apple_id(&args.apple_id);
+
+    if !account_path(&apple_id)?.join(SESSION_FILE).exists() {
+        return Err(Error::Other(format!("No saved session for {}, run `plumesign account login` first", apple_id)));
+    }
+
+    set_active_account(&apple_id)?;
+    output::emit(&json!({ "active": apple_id }), || println!("Switched to {}", apple_id));
+
+    Ok(())
+}
+
+// Meant for running unattended, e.g. from cron before re-signing apps
+async fn refresh() -> Result<(), Error> {
+    let (apple_id, mut account, refreshed) = restore_active_session().await?;
+
+    // Expired sessions were already refreshed while restoring
+    if !refreshed {
+        account.refresh_with_pet().await?;
+        save_session(&apple_id, &account, uses_key_file(&apple_id)).await?;
+    }
+
+    output::emit(
+        &json!({ "refreshed": true, "apple_id": apple_id }),
+        || println!("Refreshed the session of {}", apple_id),
+    );
+
+    Ok(())
+}
+
+async fn status() -> Result<(), Error> {
+    let Some(apple_id) = active_account().filter(|id| account_path(id).is_ok_and(|dir| dir.join(SESSION_FILE).exists())) else {
+        output::emit(&json!({ "logged_in": false }), || println!("Not logged in"));
+        return Ok(());
+    };
+
+    let session = match load_session().await {
+        Ok(session) => session,
+        Err(e) => {
+            output::emit(
+                &json!({ "logged_in": true, "apple_id": apple_id, "valid": false, "error": e.to_string() }),
+                || println!("Saved session is no longer valid, log in again: {}", e),
+            );
+            return Ok(());
+        }
+    };
+    let (first, last) = session.account.get_name()?;
+
+    match session.qh_list_teams().await {
+        Ok(response) => output::emit(
+            &json!({
+                "logged_in": true,
+                "apple_id": apple_id,
+                "valid": true,
+                "first_name": first,
+                "last_name": last,
+                "teams": response.teams,
+            }),
+            || {
+                println!("Logged in as {} {} ({})", first, last, apple_id);
+                for team in &response.teams {
+                    println!("  {} ({}, {})", team.name, team.team_id, team._type);
+                }
+            },
+        ),
+        Err(e) => output::emit(
+            &json!({
+                "logged_in": true,
+                "apple_id": apple_id,
+                "valid": false,
+                "first_name": first,
+                "last_name": last,
+                "error": e.to_string(),
+            }),
+            || {
+                println!("Logged in as {} {} ({})", first, last, apple_id);
+                println!("Saved session is no longer valid, log in again: {}", e);
+            },
+        ),
+    }
+
+    Ok(())
+}
+
+/// Restores the session of the active account, or the one passed with `--account`.
+pub async fn load_session() -> Result<DeveloperSession, Error> {
+    // Replays don't need a saved session, or to reach Apple at all
+    if let Some(TrafficArg::Replay(dir)) = TRAFFIC.get() {
+        let mut account = Account::with_provider(Arc::new(MockProvider::new("replay"))).await?;
+        account.replay_traffic(dir)?;
+        return Ok(DeveloperSession::with(account));
+    }
+
+    let (_, mut account, _) = restore_active_session().await?;
+
+    if let Some(TrafficArg::Record(dir)) = TRAFFIC.get() {
+        account.record_traffic(dir)?;
+    }
+
+    Ok(DeveloperSession::with(account))
+}
+
+// Sessions with expired tokens are refreshed with their PET and saved again
+async fn restore_active_session() -> Result<(String, Account, bool), Error> {
+    let Some(apple_id) = active_account() else {
+        return Err(Error::Other("Not logged in, run `plumesign account login` first".to_string()));
+    };
+
+    let dir = account_path(&apple_id)?;
+    let path = dir.join(SESSION_FILE);
+
+    if !path.exists() {
+        return Err(Error::Other(format!("No saved session for {}, run `plumesign account login` first", apple_id)));
+    }
+
+    let blob = fs::read(&path)?;
+    let key = if uses_key_file(&apple_id) {
+        fs::read(dir.join(SESSION_KEY_FILE))
+            .map_err(|e| Error::Other(format!("Failed to read session key: {}", e)))?
+    } else {
+        key_entry(&apple_id)?.get_secret().map_err(|e| match e {
+            keyring::Error::NoEntry => Error::Other(format!(
+                "The session key for {} is missing from the keyring, run `plumesign account login` again",
+                apple_id
+            )),
+            e => e.into(),
+        })?
+    };
+
+    let (account, refreshed) = Account::restore_or_refresh_session(&blob, &key, new_account().await?).await?;
+
+    if refreshed {
+        save_session(&apple_id, &account, uses_key_file(&apple_id)).await?;
+    }
+
+    Ok((apple_id, account, refreshed))
+}
+
+// The session is encrypted, the key to it lives in the keyring unless `key_file` is set
+async fn save_session(apple_id: &str, account: &Account, key_file: bool) -> Result<(), Error> {
+    let key = Account::generate_session_key();
+    let blob = account.export_session(&key).await?;
+
+    let dir = account_path(apple_id)?;
+    fs::create_dir_all(&dir)?;
+
+    if key_file {
+        // The key is as good as the password, keep it readable by us only
+        write_private(&dir.join(SESSION_KEY_FILE), &key)?;
+    } else {
+        key_entry(apple_id)?.set_secret(&key)?;
+        fs::remove_file(dir.join(SESSION_KEY_FILE)).ok();
+    }
+    write_private(&dir.join(SESSION_FILE), &blob)?;
+
+    Ok(())
+}
+
+// Set once at login, refreshes keep the key where it was
+fn uses_key_file(apple_id: &str) -> bool {
+    account_path(apple_id).is_ok_and(|dir| dir.join(SESSION_KEY_FILE).exists())
+}
+
+fn key_entry(apple_id: &str) -> Result<Entry, Error> {
+    Ok(Entry::new(KEYRING_SERVICE, &format!("{} ({})", KEYRING_SESSION_KEY, apple_id))?)
+}
+
+fn write_private(path: &PathBuf, contents: &[u8]) -> Result<(), Error> {
+    fs::write(path, contents)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+
+    Ok(())
+}
+
+fn saved_accounts() -> Vec<String> {
+    let Some(entries) = accounts_path().ok().and_then(|path| fs::read_dir(path).ok()) else {
+        return Vec::new();
+    };
+
+    let mut accounts: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join(SESSION_FILE).exists())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+
+    accounts.sort();
+    accounts
+}
+
+fn active_account() -> Option<String> {
+    if let Some(apple_id) = ACCOUNT_OVERRIDE.get() {
+        return Some(apple_id.clone());
+    }
+
+    let apple_id = fs::read_to_string(accounts_path().ok()?.join(ACTIVE_ACCOUNT_FILE)).ok()?;
+    let apple_id = Account::normalize_apple_id(&apple_id);
+
+    account_path(&apple_id).ok()?.join(SESSION_FILE).exists().then_some(apple_id)
+}
+
+fn set_active_account(apple_id: &str) -> Result<(), Error> {
+    fs::write(accounts_path()?.join(ACTIVE_ACCOUNT_FILE), apple_id)?;
+    Ok(())
+}
+
+fn account_path(apple_id: &str) -> Result<PathBuf, Error> {
+    Ok(accounts_path()?.join(apple_id))
+}
+
+fn accounts_path() -> Result<PathBuf, Error> {
+    let path = get_data_path()?.join(ACCOUNTS_DIR);
+    fs::create_dir_all(&path).ok();
+    Ok(path)
+}
+
+// Not logged in yet, set up with the network options from the command line
+async fn new_account() -> Result<Account, Error> {
+    let network = NETWORK.get().cloned().unwrap_or_default();
+
+    let anisette = match ANISETTE_SERVERS.get().filter(|servers| !servers.is_empty()) {
+        Some(servers) => {
+            let provider = FailoverProvider::new(anisette_config()?, servers.clone())
+                .with_client(network.client()?);
+            AnisetteData::with_provider(Arc::new(provider)).await?
+        }
+        None => AnisetteData::new(anisette_config()?).await?,
+    };
+
+    Ok(Account::with_network(anisette, &network)?)
+}
+
+fn anisette_config() -> Result<AnisetteConfiguration, Error> {
+    Ok(AnisetteConfiguration::default()
+        .set_configuration_path(get_data_path()?))
+}
+
+// Prompts go to stderr so they don't end up in `--json` output
+fn prompt(message: &str) -> io::Result<String> {
+    eprint!("{}", message);
+    io::stderr().flush()?;
+
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+
+    Ok(line.trim().to_string())
+}
@@ -0,0 +1,148 @@
+use clap::{Args, Subcommand};
+use serde_json::json;
+
+use plume_core::developer::DeveloperSession;
+
+use crate::{Error, commands::{account::load_session, resolve_team}, output, status};
+
+#[derive(Debug, Args)]
+pub struct AppIdsArgs {
+    #[command(subcommand)]
+    pub command: AppIdsCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AppIdsCommands {
+    /// List the app IDs registered on the team
+    List(ListArgs),
+    /// Delete app IDs by bundle identifier
+    Delete(DeleteArgs),
+    /// Show the details of a single app ID
+    Show(ShowArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    #[arg(long = "team", short = 't', value_name = "TEAM_ID", help = "Team to use, required if the account has more than one")]
+    pub team: Option<String>,
+
+    #[arg(long = "plume", help = "Only list app IDs created by sideloading, which have the team ID in their identifier")]
+    pub plume_only: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DeleteArgs {
+    #[arg(long = "team", short = 't', value_name = "TEAM_ID", help = "Team to use, required if the account has more than one")]
+    pub team: Option<String>,
+
+    #[arg(value_name = "IDENTIFIER", help = "Bundle identifiers of the app IDs to delete")]
+    pub identifiers: Vec<String>,
+
+    #[arg(long = "all-plume", help = "Delete every app ID created by sideloading")]
+    pub all_plume: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ShowArgs {
+    #[arg(long = "team", short = 't', value_name = "TEAM_ID", help = "Team to use, required if the account has more than one")]
+    pub team: Option<String>,
+
+    #[arg(value_name = "IDENTIFIER", help = "Bundle identifier of the app ID")]
+    pub identifier: String,
+}
+
+pub async fn execute(args: AppIdsArgs) -> Result<(), Error> {
+    let session = load_session().await?;
+
+    match args.command {
+        AppIdsCommands::List(args) => list(&session, args).await,
+        AppIdsCommands::Delete(args) => delete(&session, args).await,
+        AppIdsCommands::Show(args) => show(&session, args).await,
+    }
+}
+
+async fn list(session: &DeveloperSession, args: ListArgs) -> Result<(), Error> {
+    let team_id = resolve_team(session, args.team).await?;
+
+    let app_ids = session.qh_list_app_ids(&team_id).await?.app_ids
+        .into_iter()
+        .filter(|a| !args.plume_only || is_plume_identifier(&a.identifier, &team_id))
+        .collect::<Vec<_>>();
+
+    output::emit(&app_ids, || {
+        for app_id in &app_ids {
+            println!("{}\t{}\t{}", app_id.app_id_id, app_id.identifier, app_id.name);
+        }
+    });
+
+    Ok(())
+}
+
+async fn delete(session: &DeveloperSession, args: DeleteArgs) -> Result<(), Error> {
+    if args.identifiers.is_empty() && !args.all_plume {
+        return Err(Error::Other("Pass at least one identifier or --all-plume".into()));
+    }
+
+    let team_id = resolve_team(session, args.team).await?;
+
+    let app_ids = session.qh_list_app_ids(&team_id).await?.app_ids;
+
+    for identifier in &args.identifiers {
+        if !app_ids.iter().any(|a| &a.identifier == identifier) {
+            return Err(Error::Other(format!("No app ID with identifier {}", identifier)));
+        }
+    }
+
+    let mut deleted = Vec::new();
+    for app_id in app_ids {
+        let requested = args.identifiers.contains(&app_id.identifier);
+        let plume = args.all_plume && is_plume_identifier(&app_id.identifier, &team_id);
+
+        if !requested && !plume {
+            continue;
+        }
+
+        session.qh_delete_app_id(&team_id, &app_id.app_id_id).await?;
+        status!("Deleted {}", app_id.identifier);
+        deleted.push(app_id.identifier);
+    }
+
+    output::emit(&json!({ "deleted": deleted }), || {});
+
+    Ok(())
+}
+
+async fn show(session: &DeveloperSession, args: ShowArgs) -> Result<(), Error> {
+    let team_id = resolve_team(session, args.team).await?;
+
+    let app_id = session.qh_get_app_id(&team_id, &args.identifier).await?
+        .ok_or_else(|| Error::Other(format!("No app ID with identifier {}", args.identifier)))?;
+
+    output::emit(&app_id, || {
+        println!("Name: {}", app_id.name);
+        println!("Identifier: {}", app_id.identifier);
+        println!("App ID: {}", app_id.app_id_id);
+        println!("Prefix: {}", app_id.prefix);
+        println!("Platform: {}", app_id.app_id_platform);
+        println!("Wildcard: {}", app_id.is_wild_card);
+        println!("Created by Plume: {}", is_plume_identifier(&app_id.identifier, &team_id));
+
+        if let Some(count) = &app_id.associated_application_groups_count {
+            println!("App groups: {}", count);
+        }
+
+        if let Some(features) = app_id.enabled_features.as_ref().filter(|f| !f.is_empty()) {
+            println!("Enabled features: {}", features.join(", "));
+        }
+    });
+
+    Ok(())
+}
+
+// `Signer::modify_bundle` appends the team ID to the main bundle identifier,
+// and embedded bundles are renamed to match, so the team ID shows up either
+// at the end or in the middle of everything we registered.
+fn is_plume_identifier(identifier: &str, team_id: &str) -> bool {
+    let suffix = format!(".{team_id}");
+    identifier.ends_with(&suffix) || identifier.contains(&format!("{suffix}."))
+}
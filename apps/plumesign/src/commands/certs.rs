@@ -0,0 +1,219 @@
+use std::{fs, path::PathBuf};
+
+use clap::{Args, Subcommand};
+use serde_json::json;
+
+use plume_core::{CertificateIdentity, developer::{DeveloperSession, qh::certs::Cert}};
+
+use crate::{Error, commands::{account::load_session, resolve_team}, get_data_path, output, status};
+
+#[derive(Debug, Args)]
+pub struct CertsArgs {
+    #[command(subcommand)]
+    pub command: CertsCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CertsCommands {
+    /// List the development certificates on the team
+    List(ListArgs),
+    /// Revoke certificates by serial number or machine name
+    Revoke(RevokeArgs),
+    /// Request a new certificate without revoking any existing ones
+    Create(CreateArgs),
+    /// Export the certificate Plume uses when sideloading, along with its key
+    Export(ExportArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    #[arg(long = "team", short = 't', value_name = "TEAM_ID", help = "Team to use, required if the account has more than one")]
+    pub team: Option<String>,
+
+    #[arg(long = "machine-name", value_name = "NAME", help = "Only list certificates created with this machine name")]
+    pub machine_name: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct RevokeArgs {
+    #[arg(long = "team", short = 't', value_name = "TEAM_ID", help = "Team to use, required if the account has more than one")]
+    pub team: Option<String>,
+
+    #[arg(value_name = "SERIAL", help = "Serial numbers of the certificates to revoke")]
+    pub serial_numbers: Vec<String>,
+
+    #[arg(long = "machine-name", value_name = "NAME", help = "Revoke every certificate created with this machine name")]
+    pub machine_name: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CreateArgs {
+    #[arg(long = "team", short = 't', value_name = "TEAM_ID", help = "Team to use, required if the account has more than one")]
+    pub team: Option<String>,
+
+    #[arg(long = "machine-name", value_name = "NAME", default_value = "AltStore", help = "Machine name to register the certificate under")]
+    pub machine_name: String,
+
+    #[arg(long = "output", short = 'o', value_name = "DIR", help = "Directory to write cert.pem, key.pem and cert.p12 to")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[arg(long = "team", short = 't', value_name = "TEAM_ID", help = "Team to use, required if the account has more than one")]
+    pub team: Option<String>,
+
+    #[arg(long = "machine-name", value_name = "NAME", help = "Machine name the certificate was created with, defaults to AltStore")]
+    pub machine_name: Option<String>,
+
+    #[arg(long = "output", short = 'o', value_name = "DIR", help = "Directory to write cert.pem, key.pem and cert.p12 to")]
+    pub output: PathBuf,
+}
+
+pub async fn execute(args: CertsArgs) -> Result<(), Error> {
+    let session = load_session().await?;
+
+    match args.command {
+        CertsCommands::List(args) => list(&session, args).await,
+        CertsCommands::Revoke(args) => revoke(&session, args).await,
+        CertsCommands::Create(args) => create(&session, args).await,
+        CertsCommands::Export(args) => export(&session, args).await,
+    }
+}
+
+async fn list(session: &DeveloperSession, args: ListArgs) -> Result<(), Error> {
+    let team_id = resolve_team(session, args.team).await?;
+
+    let certs = session.qh_list_certs(&team_id).await?.certificates;
+
+    let certs = filter_by_machine_name(certs, args.machine_name.as_deref());
+
+    output::emit(&certs, || {
+        for cert in &certs {
+            println!(
+                "{}\t{}\t{}\t{}\t{}",
+                cert.serial_number,
+                cert.name,
+                cert.machine_name.as_deref().unwrap_or("-"),
+                cert.expiration_date.to_xml_format(),
+                cert.status,
+            );
+        }
+    });
+
+    Ok(())
+}
+
+async fn revoke(session: &DeveloperSession, args: RevokeArgs) -> Result<(), Error> {
+    if args.serial_numbers.is_empty() && args.machine_name.is_none() {
+        return Err(Error::Other("Pass at least one serial number or --machine-name".into()));
+    }
+
+    let team_id = resolve_team(session, args.team).await?;
+
+    let mut serial_numbers = args.serial_numbers;
+
+    if let Some(machine_name) = args.machine_name.as_deref() {
+        let certs = session.qh_list_certs(&team_id).await?.certificates;
+        let matching = filter_by_machine_name(certs, Some(machine_name));
+
+        if matching.is_empty() && serial_numbers.is_empty() {
+            output::emit(
+                &json!({ "revoked": serial_numbers }),
+                || println!("No certificates found for machine name {}", machine_name),
+            );
+            return Ok(());
+        }
+
+        serial_numbers.extend(matching.into_iter().map(|c| c.serial_number));
+    }
+
+    for serial_number in &serial_numbers {
+        session.qh_revoke_cert(&team_id, serial_number).await?;
+        status!("Revoked {}", serial_number);
+    }
+
+    output::emit(&json!({ "revoked": serial_numbers }), || {});
+
+    Ok(())
+}
+
+async fn create(session: &DeveloperSession, args: CreateArgs) -> Result<(), Error> {
+    let team_id = resolve_team(session, args.team).await?;
+
+    let (cert, key_pair) = match CertificateIdentity::request_certificate(session, &team_id, &args.machine_name).await {
+        Ok(result) => result,
+        // 7460 is for too many certificates
+        Err(plume_core::Error::DeveloperSession(code, message)) if code == 7460 => {
+            return Err(Error::Other(format!(
+                "{} (free accounts are limited in how many certificates they can have, use `plumesign certs list` and `plumesign certs revoke` to make room)",
+                message
+            )));
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    status!("Created certificate {}", cert.serial_number);
+
+    write_certificate(&cert, &key_pair, &args.output)
+}
+
+async fn export(session: &DeveloperSession, args: ExportArgs) -> Result<(), Error> {
+    let team_id = resolve_team(session, args.team).await?;
+
+    let Some((cert, key_pair)) = CertificateIdentity::find_stored_certificate(
+        session,
+        get_data_path()?,
+        args.machine_name,
+        &team_id,
+    ).await? else {
+        return Err(Error::Other("No certificate on this team matches the stored key, sideload something first or use `plumesign certs create`".into()));
+    };
+
+    write_certificate(&cert, &key_pair, &args.output)
+}
+
+fn filter_by_machine_name(certs: Vec<Cert>, machine_name: Option<&str>) -> Vec<Cert> {
+    match machine_name {
+        Some(name) => certs.into_iter().filter(|c| c.machine_name.as_deref() == Some(name)).collect(),
+        None => certs,
+    }
+}
+
+// `create_pkcs12` uses the machine ID as the P12 password
+fn write_certificate(cert: &Cert, key_pair: &[Vec<u8>; 2], output_dir: &PathBuf) -> Result<(), Error> {
+    fs::create_dir_all(output_dir)?;
+
+    fs::write(output_dir.join("cert.pem"), &key_pair[0])?;
+    fs::write(output_dir.join("key.pem"), &key_pair[1])?;
+
+    let identity = CertificateIdentity {
+        cert: None,
+        key: None,
+        machine_id: cert.machine_id.clone(),
+        serial_number: Some(cert.serial_number.clone()),
+        p12_data: None,
+    };
+
+    let p12_data = identity
+        .create_pkcs12(key_pair)
+        .ok_or_else(|| Error::Other("Failed to create P12".into()))?;
+
+    fs::write(output_dir.join("cert.p12"), p12_data)?;
+
+    output::emit(
+        &json!({
+            "certificate": cert,
+            "output": output_dir,
+            "p12_password": cert.machine_id,
+        }),
+        || {
+            println!("Wrote certificate to {}", output_dir.display());
+            if let Some(machine_id) = &cert.machine_id {
+                println!("P12 password: {}", machine_id);
+            }
+        },
+    );
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use clap::{Args, Subcommand};
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::json;
+
+use idevice::usbmuxd::{UsbmuxdConnection, UsbmuxdListenEvent};
+use plume_utils::Device;
+
+use crate::{Error, output};
+
+#[derive(Debug, Args)]
+pub struct DevicesArgs {
+    #[command(subcommand)]
+    pub command: DevicesCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DevicesCommands {
+    /// List connected devices
+    List,
+    /// Print devices as they are attached and detached until interrupted
+    Watch,
+}
+
+pub async fn execute(args: DevicesArgs) -> Result<(), Error> {
+    match args.command {
+        DevicesCommands::List => list().await,
+        DevicesCommands::Watch => watch().await,
+    }
+}
+
+async fn list() -> Result<(), Error> {
+    let mut muxer = UsbmuxdConnection::default().await
+        .map_err(|e| Error::Other(format!("Failed to connect to usbmuxd: {}", e)))?;
+
+    let mut devices = Vec::new();
+    for dev in muxer.get_devices().await? {
+        let device = Device::new(dev).await;
+        devices.push(DeviceInfo::new(&device).await);
+    }
+
+    output::emit(&devices, || {
+        for device in &devices {
+            println!("{}", device);
+        }
+    });
+
+    Ok(())
+}
+
+// Mirrors `PlumeFrame::spawn_usbmuxd_listener`, but prints events instead of
+// forwarding them to the UI.
+async fn watch() -> Result<(), Error> {
+    let mut muxer = UsbmuxdConnection::default().await
+        .map_err(|e| Error::Other(format!("Failed to connect to usbmuxd: {}", e)))?;
+
+    // Disconnect events only carry the device ID, so remember who's who
+    let mut devices: HashMap<u32, Device> = HashMap::new();
+
+    for dev in muxer.get_devices().await? {
+        let device = Device::new(dev).await;
+        emit_attached(&device).await;
+        devices.insert(device.device_id, device);
+    }
+
+    let mut stream = muxer.listen().await
+        .map_err(|e| Error::Other(format!("Failed to listen for events: {}", e)))?;
+
+    while let Some(event) = stream.next().await {
+        match event? {
+            UsbmuxdListenEvent::Connected(dev) => {
+                let device = Device::new(dev).await;
+                emit_attached(&device).await;
+                devices.insert(device.device_id, device);
+            }
+            UsbmuxdListenEvent::Disconnected(device_id) => {
+                match devices.remove(&device_id) {
+                    Some(device) => output::emit(
+                        &json!({ "event": "detached", "device_id": device_id, "udid": device.udid, "name": device.name }),
+                        || println!("detached\t{}\t{}", device.udid, device.name),
+                    ),
+                    None => output::emit(
+                        &json!({ "event": "detached", "device_id": device_id }),
+                        || println!("detached\t{}", device_id),
+                    ),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn emit_attached(device: &Device) {
+    let info = DeviceInfo::new(device).await;
+
+    output::emit(
+        &json!({ "event": "attached", "device": info }),
+        || println!("attached\t{}", info),
+    );
+}
+
+#[derive(Serialize)]
+struct DeviceInfo {
+    udid: String,
+    name: String,
+    device_id: u32,
+    connection_type: &'static str,
+    product_version: String,
+}
+
+impl DeviceInfo {
+    async fn new(device: &Device) -> Self {
+        Self {
+            udid: device.udid.clone(),
+            name: device.name.clone(),
+            device_id: device.device_id,
+            connection_type: device.connection_type(),
+            product_version: device.get_product_version().await.unwrap_or_default(),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            self.udid,
+            self.name,
+            self.connection_type,
+            self.product_version,
+        )
+    }
+}
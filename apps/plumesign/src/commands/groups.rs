@@ -0,0 +1,115 @@
+use clap::{Args, Subcommand};
+use serde_json::json;
+
+use plume_core::developer::DeveloperSession;
+
+use crate::{Error, commands::{account::load_session, resolve_team}, output};
+
+#[derive(Debug, Args)]
+pub struct GroupsArgs {
+    #[command(subcommand)]
+    pub command: GroupsCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum GroupsCommands {
+    /// List the app groups registered on the team
+    List(ListArgs),
+    /// Register a new app group
+    Create(CreateArgs),
+    /// Assign app groups to an app ID
+    Assign(AssignArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    #[arg(long = "team", short = 't', value_name = "TEAM_ID", help = "Team to use, required if the account has more than one")]
+    pub team: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CreateArgs {
+    #[arg(long = "team", short = 't', value_name = "TEAM_ID", help = "Team to use, required if the account has more than one")]
+    pub team: Option<String>,
+
+    #[arg(value_name = "IDENTIFIER", help = "Group identifier, e.g. group.com.example.app")]
+    pub identifier: String,
+
+    #[arg(long = "name", value_name = "NAME", help = "Display name, defaults to the identifier")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct AssignArgs {
+    #[arg(long = "team", short = 't', value_name = "TEAM_ID", help = "Team to use, required if the account has more than one")]
+    pub team: Option<String>,
+
+    #[arg(value_name = "APP_IDENTIFIER", help = "Bundle identifier of the app ID")]
+    pub app_identifier: String,
+
+    #[arg(value_name = "GROUP_IDENTIFIER", required = true, help = "Identifiers of the app groups to assign")]
+    pub group_identifiers: Vec<String>,
+}
+
+pub async fn execute(args: GroupsArgs) -> Result<(), Error> {
+    let session = load_session().await?;
+
+    match args.command {
+        GroupsCommands::List(args) => list(&session, args).await,
+        GroupsCommands::Create(args) => create(&session, args).await,
+        GroupsCommands::Assign(args) => assign(&session, args).await,
+    }
+}
+
+async fn list(session: &DeveloperSession, args: ListArgs) -> Result<(), Error> {
+    let team_id = resolve_team(session, args.team).await?;
+
+    let groups = session.qh_list_app_groups(&team_id).await?.application_group_list;
+
+    output::emit(&groups, || {
+        for group in &groups {
+            println!("{}\t{}\t{}\t{}", group.application_group, group.identifier, group.name, group.status);
+        }
+    });
+
+    Ok(())
+}
+
+async fn create(session: &DeveloperSession, args: CreateArgs) -> Result<(), Error> {
+    let team_id = resolve_team(session, args.team).await?;
+
+    let name = args.name.unwrap_or_else(|| args.identifier.clone());
+
+    let group = session.qh_add_app_group(&team_id, &name, &args.identifier).await?.application_group;
+
+    output::emit(&group, || println!("Created {} ({})", group.identifier, group.application_group));
+
+    Ok(())
+}
+
+async fn assign(session: &DeveloperSession, args: AssignArgs) -> Result<(), Error> {
+    let team_id = resolve_team(session, args.team).await?;
+
+    let app_id = session.qh_get_app_id(&team_id, &args.app_identifier).await?
+        .ok_or_else(|| Error::Other(format!("No app ID with identifier {}", args.app_identifier)))?;
+
+    let groups = session.qh_list_app_groups(&team_id).await?.application_group_list;
+
+    let mut app_group_ids: Vec<String> = Vec::new();
+    for identifier in &args.group_identifiers {
+        let group = groups.iter()
+            .find(|g| &g.identifier == identifier)
+            .ok_or_else(|| Error::Other(format!("No app group with identifier {}", identifier)))?;
+
+        app_group_ids.push(group.application_group.clone());
+    }
+
+    session.qh_assign_app_group(&team_id, &app_id.app_id_id, &app_group_ids).await?;
+
+    output::emit(
+        &json!({ "app_id": app_id.identifier, "app_groups": args.group_identifiers }),
+        || println!("Assigned {} to {}", args.group_identifiers.join(", "), app_id.identifier),
+    );
+
+    Ok(())
+}
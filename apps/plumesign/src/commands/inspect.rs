@@ -0,0 +1,236 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use plist::{Dictionary, Value};
+use serde::Serialize;
+
+use plume_core::{MachO, MobileProvision};
+use plume_utils::{Bundle, PlistInfoTrait};
+
+use crate::{Error, commands::BundleInput, output};
+
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    #[arg(value_name = "PATH", help = "Path to an IPA, app bundle, Mach-O binary or mobileprovision")]
+    pub path: PathBuf,
+}
+
+pub async fn execute(args: InspectArgs) -> Result<(), Error> {
+    let path = args.path;
+
+    if !path.exists() {
+        return Err(Error::Other(format!("{} does not exist", path.display())));
+    }
+
+    if path.extension().and_then(|e| e.to_str()) == Some("mobileprovision") {
+        let report = ProfileReport::new(&MobileProvision::load_with_path(&path)?);
+        output::emit(&report, || report.print(""));
+        return Ok(());
+    }
+
+    if path.is_file() && !super::is_package_path(&path) {
+        let report = EntitlementsReport::new(&path);
+        output::emit(&report, || report.print(""));
+        return Ok(());
+    }
+
+    let input = BundleInput::open(&path, false)?;
+    let reports = inspect_bundle(&input.bundle)?;
+    output::emit(&reports, || {
+        for report in &reports {
+            report.print();
+            println!();
+        }
+    });
+
+    Ok(())
+}
+
+fn inspect_bundle(main_bundle: &Bundle) -> Result<Vec<BundleReport>, Error> {
+    // Sorted deepest first for signing, flip it so the main app comes first
+    let bundles = main_bundle.collect_bundles_sorted()?;
+
+    Ok(bundles.iter().rev().map(|b| BundleReport::new(main_bundle, b)).collect())
+}
+
+#[derive(Serialize)]
+struct BundleReport {
+    path: String,
+    bundle_type: String,
+    name: Option<String>,
+    identifier: Option<String>,
+    version: Option<String>,
+    build_version: Option<String>,
+    executable: Option<String>,
+    entitlements: Option<EntitlementsReport>,
+    profile: Option<ProfileReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile_error: Option<String>,
+}
+
+impl BundleReport {
+    fn new(main_bundle: &Bundle, bundle: &Bundle) -> Self {
+        let path = bundle.bundle_dir()
+            .strip_prefix(main_bundle.bundle_dir())
+            .ok()
+            .filter(|p| !p.as_os_str().is_empty())
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| ".".to_string());
+
+        let executable = bundle.get_executable();
+        let entitlements = executable
+            .as_ref()
+            .map(|e| EntitlementsReport::new(&bundle.bundle_dir().join(e)));
+
+        let provision_path = bundle.bundle_dir().join("embedded.mobileprovision");
+        let (profile, profile_error) = if provision_path.exists() {
+            match MobileProvision::load_with_path(&provision_path) {
+                Ok(provision) => (Some(ProfileReport::new(&provision)), None),
+                Err(e) => (None, Some(e.to_string())),
+            }
+        } else {
+            (None, None)
+        };
+
+        Self {
+            path,
+            bundle_type: format!("{:?}", bundle.bundle_type()),
+            name: bundle.get_name(),
+            identifier: bundle.get_bundle_identifier(),
+            version: bundle.get_version(),
+            build_version: bundle.get_build_version(),
+            executable,
+            entitlements,
+            profile,
+            profile_error,
+        }
+    }
+
+    fn print(&self) {
+        println!("{} ({})", self.path, self.bundle_type);
+        println!("  Name: {}", self.name.as_deref().unwrap_or_default());
+        println!("  Identifier: {}", self.identifier.as_deref().unwrap_or_default());
+        println!(
+            "  Version: {} ({})",
+            self.version.as_deref().unwrap_or_default(),
+            self.build_version.as_deref().unwrap_or_default()
+        );
+
+        if let Some(executable) = &self.executable {
+            println!("  Executable: {}", executable);
+        }
+
+        if let Some(entitlements) = &self.entitlements {
+            entitlements.print("  ");
+        }
+
+        if let Some(profile) = &self.profile {
+            println!("  Embedded profile:");
+            profile.print("    ");
+        } else if let Some(error) = &self.profile_error {
+            println!("  Embedded profile: unreadable ({})", error);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EntitlementsReport {
+    entitlements: Option<Dictionary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl EntitlementsReport {
+    fn new(binary_path: &Path) -> Self {
+        match MachO::new(binary_path) {
+            Ok(macho) => Self { entitlements: macho.entitlements, error: None },
+            Err(e) => Self { entitlements: None, error: Some(e.to_string()) },
+        }
+    }
+
+    fn print(&self, indent: &str) {
+        match (&self.entitlements, &self.error) {
+            (Some(entitlements), _) => {
+                println!("{indent}Entitlements:");
+                print_dictionary(entitlements, &format!("{indent}  "));
+            }
+            (None, Some(error)) => println!("{indent}Entitlements: unreadable ({})", error),
+            (None, None) => println!("{indent}Entitlements: none"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ProfileReport {
+    name: Option<String>,
+    uuid: Option<String>,
+    team_name: Option<String>,
+    team_identifier: Option<String>,
+    bundle_id: Option<String>,
+    expiration_date: Option<String>,
+    devices: Vec<String>,
+    entitlements: Dictionary,
+}
+
+impl ProfileReport {
+    fn new(provision: &MobileProvision) -> Self {
+        Self {
+            name: provision.name(),
+            uuid: provision.uuid(),
+            team_name: provision.team_name(),
+            team_identifier: provision.team_identifier(),
+            bundle_id: provision.bundle_id(),
+            expiration_date: provision.expiration_date().map(|d| d.to_xml_format()),
+            devices: provision.provisioned_devices(),
+            entitlements: provision.entitlements().clone(),
+        }
+    }
+
+    fn print(&self, indent: &str) {
+        println!("{indent}Name: {}", self.name.as_deref().unwrap_or_default());
+        println!("{indent}UUID: {}", self.uuid.as_deref().unwrap_or_default());
+        println!(
+            "{indent}Team: {} ({})",
+            self.team_name.as_deref().unwrap_or_default(),
+            self.team_identifier.as_deref().unwrap_or_default()
+        );
+        println!("{indent}Bundle ID: {}", self.bundle_id.as_deref().unwrap_or_default());
+
+        if let Some(date) = &self.expiration_date {
+            println!("{indent}Expires: {}", date);
+        }
+
+        if !self.devices.is_empty() {
+            println!("{indent}Devices: {}", self.devices.join(", "));
+        }
+
+        println!("{indent}Entitlements:");
+        print_dictionary(&self.entitlements, &format!("{indent}  "));
+    }
+}
+
+fn print_dictionary(dict: &Dictionary, indent: &str) {
+    for (key, value) in dict {
+        println!("{indent}{}: {}", key, format_value(value));
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Array(arr) => format!(
+            "[{}]",
+            arr.iter().map(format_value).collect::<Vec<_>>().join(", ")
+        ),
+        Value::Dictionary(dict) => format!(
+            "{{{}}}",
+            dict.iter()
+                .map(|(k, v)| format!("{}: {}", k, format_value(v)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        other => format!("{:?}", other),
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use clap::Args;
+use serde_json::json;
+
+use idevice::usbmuxd::Connection;
+use plume_utils::{Bundle, Device, PlistInfoTrait, Signer, SignerApp, SignerMode};
+
+use crate::{Error, output, status};
+use super::{BundleInput, resolve_device};
+
+#[derive(Debug, Args)]
+pub struct InstallArgs {
+    #[arg(value_name = "BUNDLE", long = "bundle", required = true, help = "Path to the IPA or signed app bundle to install")]
+    pub bundle: PathBuf,
+
+    #[arg(long = "device", short = 'd', value_name = "DEVICE", help = "UDID or usbmuxd device ID to install to, defaults to the only connected device")]
+    pub device: Option<String>,
+
+    #[arg(long = "skip-pairing-file", help = "Don't install a pairing file for apps that support one")]
+    pub skip_pairing_file: bool,
+
+    #[arg(long = "adhoc", help = "Sign ad-hoc before installing, for devices with AppSync")]
+    pub adhoc: bool,
+}
+
+pub async fn execute(args: InstallArgs) -> Result<(), Error> {
+    let device = resolve_device(args.device.as_deref()).await?;
+    let input = BundleInput::open(&args.bundle, args.adhoc)?;
+
+    let mut app = input.signer_options().app;
+    if args.skip_pairing_file {
+        app = SignerApp::Default;
+    }
+
+    let result = if args.adhoc {
+        adhoc_install(&device, &input, app).await
+    } else {
+        install_bundle(&device, &input.bundle, app).await
+    };
+
+    if result.is_ok() {
+        emit_installed(&device, &input.bundle);
+    }
+
+    result
+}
+
+async fn adhoc_install(device: &Device, input: &BundleInput, app: SignerApp) -> Result<(), Error> {
+    let mut options = input.signer_options();
+    options.mode = SignerMode::AdhocSignAndInstall;
+
+    let mut signer = Signer::adhoc(options);
+    let bundle_name = input.bundle.get_name().unwrap_or_default();
+
+    status!("Modifying {}...", bundle_name);
+    signer.modify_bundle(&input.bundle, &None).await?;
+
+    status!("Signing {} ad-hoc...", bundle_name);
+    signer.sign_bundle(&input.bundle).await?;
+
+    install_bundle(device, &input.bundle, app).await
+}
+
+/// Installs `bundle` onto `device`, along with a pairing file if `app` supports one.
+pub async fn install_bundle(device: &Device, bundle: &Bundle, app: SignerApp) -> Result<(), Error> {
+    let bundle_name = bundle.get_name().unwrap_or_default();
+
+    status!("Installing {} to {}...", bundle_name, device);
+
+    let progress_callback = |progress: i32| async move {
+        if output::is_json() {
+            return;
+        }
+
+        print!("\rInstalling... {}%", progress);
+        io::stdout().flush().ok();
+    };
+
+    device.install_app(bundle.bundle_dir(), progress_callback).await?;
+
+    if !output::is_json() {
+        println!();
+    }
+
+    if app.supports_pairing_file() {
+        if let (Some(identifier), Some(pairing_file_path)) = (bundle.get_bundle_identifier(), app.pairing_file_path()) {
+            let is_usb = device.usbmuxd_device
+                .as_ref()
+                .is_some_and(|d| d.connection_type == Connection::Usb);
+
+            if is_usb {
+                status!("Installing pairing file...");
+                device.install_pairing_record(&identifier, pairing_file_path).await?;
+            } else {
+                eprintln!("Skipping pairing file, {} is not connected over USB", device);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reports a finished install, `install_bundle` only prints progress.
+pub fn emit_installed(device: &Device, bundle: &Bundle) {
+    let bundle_name = bundle.get_name().unwrap_or_default();
+
+    output::emit(
+        &json!({
+            "name": bundle_name,
+            "bundle_identifier": bundle.get_bundle_identifier(),
+            "device": device.udid,
+        }),
+        || println!("Installed {}", bundle_name),
+    );
+}
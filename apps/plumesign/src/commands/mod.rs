@@ -0,0 +1,245 @@
+pub mod account;
+pub mod appids;
+pub mod certs;
+pub mod devices;
+pub mod groups;
+pub mod inspect;
+pub mod install;
+pub mod sideload;
+pub mod sign;
+pub mod unsign;
+
+use std::{
+    env, 
+    fs, 
+    path::{Path, PathBuf}
+};
+
+use clap::Args;
+use uuid::Uuid;
+use plume_core::developer::DeveloperSession;
+use plume_utils::{Bundle, Device, Package, PlistInfoTrait, SignerApp, SignerOptions, get_device_for_id, get_devices};
+
+use crate::Error;
+
+/// Bundle modifications shared by the commands that sign.
+#[derive(Debug, Args)]
+pub struct ModifyArgs {
+    #[arg(long = "custom-identifier", value_name = "BUNDLE_ID", help = "Custom bundle identifier to set")]
+    pub bundle_identifier: Option<String>,
+
+    #[arg(long = "custom-name", value_name = "NAME", help = "Custom bundle name to set")]
+    pub name: Option<String>,
+
+    #[arg(long = "custom-version", value_name = "VERSION", help = "Custom bundle version to set")]
+    pub version: Option<String>,
+
+    #[arg(long = "support-older-versions", help = "Try to support older versions by setting MinimumOSVersion to 7.0")]
+    pub support_minimum_os_version: bool,
+
+    #[arg(long = "file-sharing", help = "Force file sharing by setting UIFileSharingEnabled and UISupportsDocumentBrowser")]
+    pub support_file_sharing: bool,
+
+    #[arg(long = "ipad-fullscreen", help = "Force fullscreen on iPad by setting UIRequiresFullScreen")]
+    pub support_ipad_fullscreen: bool,
+
+    #[arg(long = "game-mode", help = "Force Game Mode by setting GCSupportsGameMode")]
+    pub support_game_mode: bool,
+
+    #[arg(long = "pro-motion", help = "Force ProMotion by setting CADisableMinimumFrameDurationOnPhone")]
+    pub support_pro_motion: bool,
+
+    #[arg(long = "remove-url-schemes", help = "Remove the URL schemes the app registers")]
+    pub remove_url_schemes: bool,
+}
+
+impl ModifyArgs {
+    pub fn load_into_signer_options(self, options: &mut SignerOptions) {
+        options.custom_identifier = self.bundle_identifier;
+        options.custom_name = self.name;
+        options.custom_version = self.version;
+
+        options.features.support_minimum_os_version = self.support_minimum_os_version;
+        options.features.support_file_sharing = self.support_file_sharing;
+        options.features.support_ipad_fullscreen = self.support_ipad_fullscreen;
+        options.features.support_game_mode = self.support_game_mode;
+        options.features.support_pro_motion = self.support_pro_motion;
+        options.features.remove_url_schemes = self.remove_url_schemes;
+    }
+}
+
+/// An app bundle opened from either a `.app` directory or an `.ipa`.
+///
+/// Whatever was staged for it is removed when it is dropped, so a command
+/// bailing out with `?` doesn't leave copies behind in the temp directory.
+pub struct BundleInput {
+    pub bundle: Bundle,
+    package: Option<Package>,
+    stage_dir: Option<PathBuf>,
+}
+
+impl BundleInput {
+    /// Opens `path`, `.app` directories are copied to a temporary
+    /// stage first when `stage` is set so the original is left untouched.
+    pub fn open(path: &PathBuf, stage: bool) -> Result<Self, Error> {
+        if path.is_dir() {
+            if !stage {
+                return Ok(Self {
+                    bundle: Bundle::new(path)?,
+                    package: None,
+                    stage_dir: None,
+                });
+            }
+
+            let bundle_name = path.file_name()
+                .ok_or_else(|| Error::Other(format!("Invalid bundle path: {}", path.display())))?;
+            let stage_dir = env::temp_dir().join(format!("plume_stage_{}", Uuid::new_v4().to_string().to_uppercase()));
+            let staged_bundle = stage_dir.join(bundle_name);
+
+            copy_dir_recursively(path, &staged_bundle)?;
+
+            return Ok(Self {
+                bundle: Bundle::new(staged_bundle)?,
+                package: None,
+                stage_dir: Some(stage_dir),
+            });
+        }
+
+        let package = Package::new(path.clone())?;
+        let bundle = package.get_package_bundle()?;
+
+        Ok(Self {
+            bundle,
+            package: Some(package),
+            stage_dir: None,
+        })
+    }
+
+    pub fn signer_options(&self) -> SignerOptions {
+        let mut options = SignerOptions::default();
+
+        if let Some(package) = &self.package {
+            package.load_into_signer_options(&mut options);
+        } else {
+            options = SignerOptions::new_for_app(SignerApp::from_bundle_identifier(self.bundle.get_bundle_identifier()));
+        }
+
+        options
+    }
+
+    /// Writes the bundle to `output`, archiving it when `output` is an `.ipa`.
+    pub fn write_to(&self, output: &PathBuf) -> Result<(), Error> {
+        if is_package_path(output) {
+            match &self.package {
+                Some(package) => package.repackage_with_bundle(&self.bundle, output)?,
+                None => Package::create_package_from_bundle(&self.bundle, output)?,
+            }
+        } else if output != self.bundle.bundle_dir() {
+            if output.exists() {
+                fs::remove_dir_all(output)?;
+            }
+            copy_dir_recursively(self.bundle.bundle_dir(), output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for BundleInput {
+    fn drop(&mut self) {
+        if let Some(package) = self.package.take() {
+            package.remove_package_stage();
+        }
+
+        if let Some(stage_dir) = self.stage_dir.take() {
+            fs::remove_dir_all(stage_dir).ok();
+        }
+    }
+}
+
+/// Picks `device` by UDID or usbmuxd device ID, or the only connected device if not given.
+pub async fn resolve_device(device: Option<&str>) -> Result<Device, Error> {
+    if let Some(device) = device {
+        return Ok(get_device_for_id(device).await?);
+    }
+
+    let mut devices = get_devices().await?;
+
+    match devices.len() {
+        0 => Err(Error::Other("No devices connected".to_string())),
+        1 => Ok(devices.remove(0)),
+        _ => {
+            let udids = devices.iter().map(|d| d.udid.clone()).collect::<Vec<_>>().join(", ");
+            Err(Error::Other(format!("Multiple devices connected, choose one with --device: {}", udids)))
+        }
+    }
+}
+
+/// Picks `team_id`, or the only team on the account if not given.
+pub async fn resolve_team(session: &DeveloperSession, team_id: Option<String>) -> Result<String, Error> {
+    if let Some(team_id) = team_id {
+        return Ok(team_id);
+    }
+
+    let teams = session.qh_list_teams().await?.teams;
+
+    match teams.len() {
+        0 => Err(Error::Other("No teams available for the Apple ID account".to_string())),
+        1 => Ok(teams[0].team_id.clone()),
+        _ => {
+            let team_names = teams.iter()
+                .map(|t| format!("{} ({})", t.team_id, t.name))
+                .collect::<Vec<_>>()
+                .join(", ");
+            Err(Error::Other(format!("Multiple teams available, choose one with --team: {}", team_names)))
+        }
+    }
+}
+
+pub fn is_package_path(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("ipa") | Some("tipa"))
+}
+
+fn copy_dir_recursively(src: &Path, dst: &Path) -> Result<(), Error> {
+    fs::create_dir_all(dst)?;
+
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let src_path = entry.path();
+        let dst_path = dst.join(entry.file_name());
+
+        // Doesn't follow links, so a symlink shows up as one rather than as what it points to
+        let file_type = entry.file_type()?;
+
+        if file_type.is_symlink() {
+            copy_symlink(&src_path, &dst_path)?;
+        } else if file_type.is_dir() {
+            copy_dir_recursively(&src_path, &dst_path)?;
+        } else {
+            fs::copy(&src_path, &dst_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Frameworks link `Versions/Current` and friends, following those would
+// duplicate the framework or pull in files from outside the bundle
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dst: &Path) -> Result<(), Error> {
+    std::os::unix::fs::symlink(fs::read_link(src)?, dst)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn copy_symlink(src: &Path, dst: &Path) -> Result<(), Error> {
+    let target = fs::read_link(src)?;
+
+    if src.is_dir() {
+        std::os::windows::fs::symlink_dir(target, dst)?;
+    } else {
+        std::os::windows::fs::symlink_file(target, dst)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+use plume_core::{CertificateIdentity, developer::DeveloperSession};
+use plume_utils::{Device, PlistInfoTrait, Signer, SignerApp, SignerMode};
+
+use crate::{Error, get_data_path, status};
+use super::{
+    BundleInput, 
+    ModifyArgs, 
+    account::load_session, 
+    install::{emit_installed, install_bundle}, 
+    resolve_device, 
+    resolve_team
+};
+
+#[derive(Debug, Args)]
+pub struct SideloadArgs {
+    #[arg(value_name = "BUNDLE", long = "bundle", required = true, help = "Path to the IPA or app bundle to sideload")]
+    pub bundle: PathBuf,
+
+    #[arg(long = "device", short = 'd', value_name = "DEVICE", help = "UDID or usbmuxd device ID to install to, defaults to the only connected device")]
+    pub device: Option<String>,
+
+    #[arg(long = "team", short = 't', value_name = "TEAM_ID", help = "Team to sign with, defaults to the only team on the account")]
+    pub team_id: Option<String>,
+
+    #[arg(long = "machine-name", value_name = "NAME", help = "Machine name of the signing certificate, defaults to AltStore")]
+    pub machine_name: Option<String>,
+
+    #[arg(long = "single-profile", help = "Only register the main bundle, skipping any extensions")]
+    pub single_profile: bool,
+
+    #[arg(long = "skip-pairing-file", help = "Don't install a pairing file for apps that support one")]
+    pub skip_pairing_file: bool,
+
+    #[command(flatten)]
+    pub modify: ModifyArgs,
+}
+
+pub async fn execute(args: SideloadArgs) -> Result<(), Error> {
+    let session = load_session().await?;
+    let device = resolve_device(args.device.as_deref()).await?;
+    let team_id = resolve_team(&session, args.team_id.clone()).await?;
+
+    let input = BundleInput::open(&args.bundle, true)?;
+    sideload(args, &input, &session, &device, &team_id).await
+}
+
+async fn sideload(
+    args: SideloadArgs,
+    input: &BundleInput,
+    session: &DeveloperSession,
+    device: &Device,
+    team_id: &String,
+) -> Result<(), Error> {
+    let bundle = &input.bundle;
+
+    let mut options = input.signer_options();
+    options.mode = SignerMode::SignAndInstall;
+    options.embedding.single_profile |= args.single_profile;
+    args.modify.load_into_signer_options(&mut options);
+
+    status!("Ensuring {} is registered...", device.name);
+    session.qh_ensure_device(team_id, &device.name, &device.udid).await?;
+
+    let cert_identity = CertificateIdentity::new_with_session(
+        session,
+        get_data_path()?,
+        args.machine_name,
+        team_id,
+    ).await?;
+
+    let mut signer = Signer::new(Some(cert_identity), options);
+
+    let bundle_name = bundle.get_name().unwrap_or_default();
+
+    status!("Modifying {}...", bundle_name);
+    signer.modify_bundle(bundle, &Some(team_id.clone())).await?;
+
+    status!("Registering {}...", bundle_name);
+    signer.register_bundle(bundle, session, team_id).await?;
+
+    status!("Signing {}...", bundle_name);
+    signer.sign_bundle(bundle).await?;
+
+    let app = if args.skip_pairing_file {
+        SignerApp::Default
+    } else {
+        signer.options.app
+    };
+
+    install_bundle(device, bundle, app).await?;
+    emit_installed(device, bundle);
+
+    Ok(())
+}
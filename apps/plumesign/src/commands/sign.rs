@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use serde_json::json;
+
+use plume_core::{CertificateIdentity, MobileProvision};
+use plume_utils::{PlistInfoTrait, Signer, SignerMode};
+
+use crate::{Error, output, status};
+use super::{BundleInput, ModifyArgs};
+
+#[derive(Debug, Args)]
+pub struct SignArgs {
+    #[arg(long = "pem", value_name = "PEM", num_args = 1.., required_unless_present = "adhoc", help = "PEM files for certificate and private key")]
+    pub pem_files: Vec<PathBuf>,
+
+    #[arg(long = "provision", value_name = "PROVISION", num_args = 1.., required_unless_present = "adhoc", conflicts_with = "adhoc", help = "Provisioning profile files to embed")]
+    pub provisioning_files: Vec<PathBuf>,
+
+    #[arg(long = "adhoc", conflicts_with = "pem_files", help = "Sign ad-hoc for AppSync or jailbroken devices, keeping the existing entitlements")]
+    pub adhoc: bool,
+
+    #[arg(value_name = "BUNDLE", long = "bundle", required = true, help = "Path to the app bundle or IPA to sign")]
+    pub bundle: PathBuf,
+
+    #[arg(long = "output", short = 'o', value_name = "OUTPUT", help = "Where to write the signed .app or .ipa, signs in place if omitted")]
+    pub output: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub modify: ModifyArgs,
+}
+
+pub async fn execute(args: SignArgs) -> Result<(), Error> {
+    let provisioning_files = args.provisioning_files
+        .iter()
+        .map(MobileProvision::load_with_path)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let input = BundleInput::open(&args.bundle, args.output.is_some())?;
+
+    let mut options = input.signer_options();
+    options.mode = SignerMode::Export;
+    args.modify.load_into_signer_options(&mut options);
+
+    let mut signer = if args.adhoc {
+        Signer::adhoc(options)
+    } else {
+        let certificate = CertificateIdentity::new_with_paths(Some(args.pem_files)).await?;
+        Signer::new(Some(certificate), options)
+    };
+    signer.provisioning_files = provisioning_files;
+
+    let bundle_name = input.bundle.get_name().unwrap_or_default();
+
+    status!("Modifying {}...", bundle_name);
+    signer.modify_bundle(&input.bundle, &None).await?;
+
+    status!("Signing {}...", bundle_name);
+    signer.sign_bundle(&input.bundle).await?;
+
+    let output_path = args.output.unwrap_or(args.bundle);
+    input.write_to(&output_path)?;
+
+    let bundle_identifier = input.bundle.get_bundle_identifier();
+
+    output::emit(
+        &json!({
+            "name": bundle_name,
+            "bundle_identifier": bundle_identifier,
+            "output": output_path,
+        }),
+        || println!("Signed {} to {}", bundle_name, output_path.display()),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use serde_json::json;
+
+use plume_utils::{PlistInfoTrait, Signer};
+
+use crate::{Error, output, status};
+use super::BundleInput;
+
+#[derive(Debug, Args)]
+pub struct UnsignArgs {
+    #[arg(value_name = "BUNDLE", long = "bundle", required = true, help = "Path to the app bundle or IPA to unsign")]
+    pub bundle: PathBuf,
+
+    #[arg(long = "output", short = 'o', value_name = "OUTPUT", help = "Where to write the unsigned .app or .ipa, unsigns in place if omitted")]
+    pub output: Option<PathBuf>,
+}
+
+pub async fn execute(args: UnsignArgs) -> Result<(), Error> {
+    let input = BundleInput::open(&args.bundle, args.output.is_some())?;
+    let bundle_name = input.bundle.get_name().unwrap_or_default();
+
+    status!("Removing signatures from {}...", bundle_name);
+    Signer::unsign_bundle(&input.bundle).await?;
+
+    let output_path = args.output.unwrap_or(args.bundle);
+    input.write_to(&output_path)?;
+
+    output::emit(
+        &json!({ "name": bundle_name, "output": output_path }),
+        || println!("Unsigned {} to {}", bundle_name, output_path.display()),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::sync::Mutex;
+
+use tracing_subscriber::EnvFilter;
+
+use crate::Error;
+
+// Everything else (reqwest, idevice, ...) stays quiet unless asked for through `RUST_LOG`
+const LOG_TARGETS: [&str; 3] = ["plumesign", "plume_core", "plume_utils"];
+const HTTP_TARGET: &str = "plume_core::auth::network";
+
+/// Sends logs to stderr, or to `log_file` if given. Nothing is logged by
+/// default, `-v` raises the level and `RUST_LOG` overrides it entirely.
+pub fn init(verbose: u8, debug_http: bool, log_file: Option<&Path>) -> Result<(), Error> {
+    let level = match verbose {
+        // A log file is for bug reports, so it gets the detail without asking
+        0 if log_file.is_some() => Some("debug"),
+        0 => None,
+        1 => Some("info"),
+        2 => Some("debug"),
+        _ => Some("trace"),
+    };
+
+    let mut directives: Vec<String> = level
+        .map(|level| LOG_TARGETS.iter().map(|target| format!("{}={}", target, level)).collect())
+        .unwrap_or_default();
+
+    if debug_http {
+        directives.push(format!("{}=debug", HTTP_TARGET));
+    }
+
+    let filter = match env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(filter) => EnvFilter::new(filter),
+        Err(_) if directives.is_empty() => return Ok(()),
+        Err(_) => EnvFilter::new(directives.join(",")),
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match log_file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            builder.with_ansi(false).with_writer(Mutex::new(file)).init();
+        }
+        None => builder.with_writer(std::io::stderr).init(),
+    }
+
+    Ok(())
+}
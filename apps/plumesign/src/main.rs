@@ -1,43 +1,143 @@
+mod commands;
+mod logging;
+mod output;
+
 use std::path::PathBuf;
 
-use clap::Parser;
+pub use plume_utils::get_data_path;
+
+use clap::{Parser, Subcommand};
+use plume_core::auth::NetworkOptions;
 
-use clap::{Args, Subcommand};
+use commands::{
+    account::AccountArgs, 
+    appids::AppIdsArgs, 
+    certs::CertsArgs, 
+    devices::DevicesArgs, 
+    groups::GroupsArgs, 
+    inspect::InspectArgs, 
+    install::InstallArgs, 
+    sideload::SideloadArgs, 
+    sign::SignArgs,
+    unsign::UnsignArgs
+};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, disable_help_subcommand = true)]
 pub struct Cli {
+    #[arg(long = "json", global = true, help = "Print results and errors as JSON")]
+    pub json: bool,
+
+    #[arg(long = "account", global = true, value_name = "EMAIL", env = "PLUME_ACCOUNT", help = "Apple ID to use instead of the active one")]
+    pub account: Option<String>,
+
+    #[arg(long = "record-traffic", global = true, value_name = "DIR", help = "Save developer service requests and responses, with secrets redacted, to DIR")]
+    pub record_traffic: Option<PathBuf>,
+
+    #[arg(long = "replay-traffic", global = true, value_name = "DIR", conflicts_with = "record_traffic", help = "Answer developer service requests from DIR instead of Apple, no login needed")]
+    pub replay_traffic: Option<PathBuf>,
+
+    #[arg(long = "proxy", global = true, value_name = "URL", env = "PLUME_PROXY", help = "HTTP(S) or SOCKS5 proxy for all traffic to Apple")]
+    pub proxy: Option<String>,
+
+    #[arg(long = "ca-cert", global = true, value_name = "PATH", help = "Extra PEM or DER root certificate to trust, can be repeated")]
+    pub ca_certs: Vec<PathBuf>,
+
+    #[arg(long = "anisette-server", global = true, value_name = "URL", env = "PLUME_ANISETTE_SERVERS", value_delimiter = ',', help = "Anisette v3 server to sign in with, can be repeated to fail over in order")]
+    pub anisette_servers: Vec<String>,
+
+    #[arg(long = "debug-http", global = true, help = "Log every request to Apple with its headers and response status, tokens are left out")]
+    pub debug_http: bool,
+
+    #[arg(short = 'v', long = "verbose", global = true, action = clap::ArgAction::Count, help = "Log what's happening to stderr, repeat for more detail")]
+    pub verbose: u8,
+
+    #[arg(long = "log-file", global = true, value_name = "PATH", env = "PLUME_LOG_FILE", help = "Append logs to PATH instead of stderr, at debug level unless -v says otherwise")]
+    pub log_file: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
 #[derive(Debug, Subcommand)]
 pub enum Commands {
+    /// Sign an app bundle or IPA with your own certificate and provisioning profiles
     Sign(SignArgs),
+    /// Manage and switch between the Apple ID sessions used by commands that talk to Apple
+    Account(AccountArgs),
+    /// Install an IPA or signed app bundle onto a connected device
+    Install(InstallArgs),
+    /// List connected devices or watch for devices being attached and detached
+    Devices(DevicesArgs),
+    /// Sign an app with your Apple ID and install it, like the Impactor GUI does
+    Sideload(SideloadArgs),
+    /// List, revoke, create and export development certificates
+    Certs(CertsArgs),
+    /// List, delete and inspect app IDs
+    #[command(name = "appids")]
+    AppIds(AppIdsArgs),
+    /// List, create and assign app groups
+    Groups(GroupsArgs),
+    /// Print the bundles, entitlements and provisioning profiles inside an app
+    Inspect(InspectArgs),
+    /// Strip code signatures and provisioning profiles from an app bundle or IPA
+    Unsign(UnsignArgs),
 }
 
-#[derive(Debug, Args)]
-pub struct SignArgs {
-    #[arg(long = "pem", value_name = "PEM", num_args = 1.., required = true, help = "PEM files for certificate and private key")]
-    pub pem_files: Vec<PathBuf>,
+#[tokio::main]
+async fn main() {
+    _ = rustls::crypto::ring::default_provider().install_default().unwrap();
 
-    #[arg(long = "provision", value_name = "PROVISION", num_args = 1.., required = true, help = "Provisioning profile files to embed")]
-    pub provisioning_files: Vec<PathBuf>,
+    let cli = Cli::parse();
+    output::set_json(cli.json);
 
-    #[arg(value_name = "BUNDLE", long = "bundle", required = true, help = "Path to the app bundle to sign")]
-    pub bundle: PathBuf,
+    if let Err(e) = logging::init(cli.verbose, cli.debug_http, cli.log_file.as_deref()) {
+        output::emit_error(&e);
+        std::process::exit(1);
+    }
 
-    #[arg(long = "custom-identifier", value_name = "BUNDLE_ID", help = "Custom bundle identifier to set")]
-    pub bundle_identifier: Option<String>,
+    commands::account::set_account_override(cli.account);
+    commands::account::set_traffic(cli.record_traffic, cli.replay_traffic);
+    commands::account::set_network_options(NetworkOptions {
+        proxy: cli.proxy,
+        root_certificates: cli.ca_certs,
+        debug_logging: cli.debug_http,
+    });
+    commands::account::set_anisette_servers(cli.anisette_servers);
 
-    #[arg(long = "custom-name", value_name = "NAME", help = "Custom bundle name to set")]
-    pub name: Option<String>,
+    let result = match cli.command {
+        Commands::Sign(args) => commands::sign::execute(args).await,
+        Commands::Account(args) => commands::account::execute(args).await,
+        Commands::Install(args) => commands::install::execute(args).await,
+        Commands::Devices(args) => commands::devices::execute(args).await,
+        Commands::Sideload(args) => commands::sideload::execute(args).await,
+        Commands::Certs(args) => commands::certs::execute(args).await,
+        Commands::AppIds(args) => commands::appids::execute(args).await,
+        Commands::Groups(args) => commands::groups::execute(args).await,
+        Commands::Inspect(args) => commands::inspect::execute(args).await,
+        Commands::Unsign(args) => commands::unsign::execute(args).await,
+    };
 
-    #[arg(long = "custom-version", value_name = "VERSION", help = "Custom bundle version to set")]
-    pub version: Option<String>,
+    if let Err(e) = result {
+        output::emit_error(&e);
+        std::process::exit(1);
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    todo!();
+use thiserror::Error as ThisError;
+
+#[derive(Debug, ThisError)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Core error: {0}")]
+    Core(#[from] plume_core::Error),
+    #[error("Utils error: {0}")]
+    Utils(#[from] plume_utils::Error),
+    #[error("Idevice error: {0}")]
+    Idevice(#[from] idevice::IdeviceError),
+    #[error("Keyring error: {0}")]
+    Keyring(#[from] keyring::Error),
+    #[error("{0}")]
+    Other(String),
 }
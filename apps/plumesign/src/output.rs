@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::Error;
+
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_json(enabled: bool) {
+    JSON_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Prints `value` as a single line of JSON under `--json`, otherwise runs `human`.
+pub fn emit<T: Serialize + ?Sized>(value: &T, human: impl FnOnce()) {
+    if !is_json() {
+        human();
+        return;
+    }
+
+    match serde_json::to_string(value) {
+        Ok(line) => println!("{}", line),
+        Err(e) => emit_error(&Error::Other(format!("Failed to serialize output: {}", e))),
+    }
+}
+
+pub fn emit_error(error: &Error) {
+    if !is_json() {
+        eprintln!("Error: {}", error);
+        return;
+    }
+
+    let code = match error {
+        Error::Core(plume_core::Error::DeveloperSession(code, _)) => Some(*code),
+        _ => None,
+    };
+
+    println!("{}", json!({ "error": error.to_string(), "code": code }));
+}
+
+/// Progress messages, sent to stderr under `--json` so stdout stays parseable.
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::output::is_json() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
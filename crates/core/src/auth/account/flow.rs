@@ -0,0 +1,183 @@
+use std::fmt;
+
+use omnisette::AnisetteConfiguration;
+
+use crate::Error;
+
+use crate::auth::anisette_data::AnisetteData;
+use crate::auth::{Account, LoginState, TrustedPhoneNumber};
+
+/// Drives a login one step at a time, for callers that can't block on closures.
+///
+/// Every step returns the state the login ended up in, callers act on it with
+/// the matching method until the state is `LoggedIn` and then call `finish`.
+pub struct LoginFlow {
+    account: Account,
+    username: String,
+    password: String,
+    state: LoginState,
+}
+
+impl LoginFlow {
+    /// Sends the credentials and returns the flow in whatever state GSA asked for.
+    pub async fn start(
+        username: &str,
+        password: &str,
+        config: AnisetteConfiguration,
+    ) -> Result<Self, Error> {
+        let anisette = AnisetteData::new(config).await?;
+        Self::start_with_anisette(username, password, anisette).await
+    }
+
+    pub async fn start_with_anisette(
+        username: &str,
+        password: &str,
+        anisette: AnisetteData,
+    ) -> Result<Self, Error> {
+        Self::start_with_account(Account::new_with_anisette(anisette)?, username, password).await
+    }
+
+    /// Logs in with an account that isn't logged in yet, e.g. one made with `Account::with_transport`.
+    pub async fn start_with_account(
+        mut account: Account,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, Error> {
+        let state = account.login_email_pass(username, password).await?;
+
+        let mut flow = Self {
+            account,
+            username: username.to_string(),
+            password: password.to_string(),
+            state: LoginState::NeedsLogin,
+        };
+        flow.set_state(state).await?;
+
+        Ok(flow)
+    }
+
+    pub fn state(&self) -> &LoginState {
+        &self.state
+    }
+
+    /// The Apple ID the flow was started with.
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Whether the next step is `submit_code`.
+    pub fn needs_code(&self) -> bool {
+        matches!(
+            self.state,
+            LoginState::Needs2FAVerification | LoginState::NeedsSMS2FAVerification(_)
+        )
+    }
+
+    /// Pushes a code to the trusted devices, valid in `NeedsDevice2FA`.
+    pub async fn request_device_code(&mut self) -> Result<&LoginState, Error> {
+        self.expect_state(matches!(self.state, LoginState::NeedsDevice2FA), "request_device_code")?;
+
+        let state = self.account.send_2fa_to_devices().await?;
+        self.set_state(state).await
+    }
+
+    /// Lists the phone numbers `request_sms_code` can send to.
+    pub async fn trusted_phone_numbers(&self) -> Result<Vec<TrustedPhoneNumber>, Error> {
+        self.expect_state(self.in_two_factor(), "trusted_phone_numbers")?;
+
+        Ok(self.account.get_auth_extras().await?.trusted_phone_numbers)
+    }
+
+    /// Texts a code to the trusted phone number with `phone_id`.
+    ///
+    /// Valid in any two factor state, so a user without their Apple devices
+    /// nearby can switch to SMS after a code was pushed to them.
+    pub async fn request_sms_code(&mut self, phone_id: u32) -> Result<&LoginState, Error> {
+        self.expect_state(self.in_two_factor(), "request_sms_code")?;
+
+        let state = self.account.send_sms_2fa_to_devices(phone_id).await?;
+        self.set_state(state).await
+    }
+
+    /// Sends the last code again the same way it was delivered, valid whenever `needs_code` is true.
+    pub async fn resend_code(&mut self) -> Result<&LoginState, Error> {
+        let state = match &self.state {
+            LoginState::Needs2FAVerification => self.account.send_2fa_to_devices().await?,
+            LoginState::NeedsSMS2FAVerification(body) => {
+                self.account.send_sms_2fa_to_devices(body.phone_number.id).await?
+            }
+            _ => return Err(self.invalid_step("resend_code")),
+        };
+
+        self.set_state(state).await
+    }
+
+    /// Verifies the code the user entered, valid whenever `needs_code` is true.
+    ///
+    /// An incorrect code leaves the flow where it was so the user can try again.
+    pub async fn submit_code(&mut self, code: String) -> Result<&LoginState, Error> {
+        let state = match &self.state {
+            LoginState::Needs2FAVerification => self.account.verify_2fa(code).await?,
+            LoginState::NeedsSMS2FAVerification(body) => {
+                self.account.verify_sms_2fa(code, body.clone()).await?
+            }
+            _ => return Err(self.invalid_step("submit_code")),
+        };
+
+        self.set_state(state).await
+    }
+
+    /// Returns the logged in account once the flow is done.
+    pub fn finish(self) -> Result<Account, Error> {
+        match self.state {
+            LoginState::LoggedIn => Ok(self.account),
+            LoginState::NeedsExtraStep(step) => {
+                if self.account.get_pet().is_some() {
+                    Ok(self.account)
+                } else {
+                    Err(Error::ExtraStep(step))
+                }
+            }
+            state => Err(Error::InvalidLoginStep("finish".to_string(), format!("{:?}", state))),
+        }
+    }
+
+    fn in_two_factor(&self) -> bool {
+        matches!(
+            self.state,
+            LoginState::NeedsDevice2FA
+                | LoginState::Needs2FAVerification
+                | LoginState::NeedsSMS2FA
+                | LoginState::NeedsSMS2FAVerification(_)
+        )
+    }
+
+    async fn set_state(&mut self, state: LoginState) -> Result<&LoginState, Error> {
+        // After 2FA GSA wants the password again, which we already have
+        self.state = match state {
+            LoginState::NeedsLogin => {
+                self.account.login_email_pass(&self.username, &self.password).await?
+            }
+            state => state,
+        };
+
+        Ok(&self.state)
+    }
+
+    fn expect_state(&self, valid: bool, step: &str) -> Result<(), Error> {
+        if valid { Ok(()) } else { Err(self.invalid_step(step)) }
+    }
+
+    fn invalid_step(&self, step: &str) -> Error {
+        Error::InvalidLoginStep(step.to_string(), format!("{:?}", self.state))
+    }
+}
+
+impl fmt::Debug for LoginFlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoginFlow")
+            .field("username", &self.username)
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
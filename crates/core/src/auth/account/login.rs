@@ -9,33 +9,19 @@ use crate::Error;
 
 use crate::auth::account::{check_error, parse_response};
 use crate::auth::anisette_data::AnisetteData;
-use crate::auth::{Account, ChallengeRequest, ChallengeRequestBody, GSA_ENDPOINT, InitRequest, InitRequestBody,LoginState, RequestHeader};
+use crate::auth::{Account, ChallengeRequest, LoginFlow, ChallengeRequestBody, InitRequest, InitRequestBody,LoginState, RequestHeader, SendLogged};
 
 macro_rules! plist_get_string {
     ($base:expr, $( $path:literal )+, $final_key:literal) => {{
-        let mut current_val = $base;
+        let current_val: &plist::Dictionary = $base;
         $(
-            current_val = current_val
-                .get($path)
-                .expect(concat!("Missing dictionary key: ", $path))
-                .as_dictionary()
-                .expect(concat!("Key value is not a dictionary: ", $path));
+            let current_val = super::dict_dictionary(current_val, $path, None)?;
         )+
-        current_val
-            .get($final_key)
-            .expect(concat!("Missing string key: ", $final_key))
-            .as_string()
-            .expect(concat!("Value is not a string: ", $final_key))
-            .to_string()
+        super::dict_string(current_val, $final_key, None)?.to_string()
     }};
 
     ($base:expr, $key:literal) => {{
-        $base
-            .get($key)
-            .expect(concat!("Missing key: ", $key))
-            .as_string()
-            .expect(concat!("Value is not a string: ", $key))
-            .to_string()
+        super::dict_string($base, $key, None)?.to_string()
     }};
 }
 
@@ -57,52 +43,45 @@ impl Account {
         tfa_closure: G,
         anisette: AnisetteData,
     ) -> Result<Account, Error> {
-        let mut _self = Account::new_with_anisette(anisette)?;
         let (username, password) = appleid_closure().map_err(|e| {
             Error::AuthSrpWithMessage(0, format!("Failed to get Apple ID credentials: {}", e))
         })?;
-        
-        let mut response = _self.login_email_pass(&username, &password).await?;
-        
+
+        let mut flow = LoginFlow::start_with_anisette(&username, &password, anisette).await?;
+
         loop {
-            match response {
-                LoginState::NeedsDevice2FA => response = _self.send_2fa_to_devices().await?,
-                LoginState::Needs2FAVerification => {
-                    response = _self
-                        .verify_2fa(tfa_closure().map_err(|e| {
-                            Error::AuthSrpWithMessage(0, format!("Failed to get 2FA code: {}", e))
-                        })?)
-                        .await?
+            match flow.state() {
+                LoginState::NeedsDevice2FA => {
+                    flow.request_device_code().await?;
                 }
-                LoginState::NeedsSMS2FA => response = _self.send_sms_2fa_to_devices(1).await?,
-                LoginState::NeedsSMS2FAVerification(body) => {
-                    response = _self
-                        .verify_sms_2fa(
-                            tfa_closure().map_err(|e| {
-                                Error::AuthSrpWithMessage(
-                                    0,
-                                    format!("Failed to get SMS 2FA code: {}", e),
-                                )
-                            })?,
-                            body,
-                        )
-                        .await?
+                LoginState::NeedsSMS2FA => {
+                    let phone_id = flow.trusted_phone_numbers().await?
+                        .first()
+                        .map(|p| p.id)
+                        .ok_or_else(|| {
+                            Error::AuthSrpWithMessage(0, "This Apple ID has no trusted phone numbers".to_string())
+                        })?;
+                    flow.request_sms_code(phone_id).await?;
                 }
-                LoginState::NeedsLogin => {
-                    response = _self.login_email_pass(&username, &password).await?
+                LoginState::Needs2FAVerification => {
+                    let code = tfa_closure().map_err(|e| {
+                        Error::AuthSrpWithMessage(0, format!("Failed to get 2FA code: {}", e))
+                    })?;
+                    flow.submit_code(code).await?;
                 }
-                LoginState::LoggedIn => return Ok(_self),
-                LoginState::NeedsExtraStep(step) => {
-                    if _self.get_pet().is_some() {
-                        return Ok(_self);
-                    } else {
-                        return Err(Error::ExtraStep(step));
-                    }
+                LoginState::NeedsSMS2FAVerification(_) => {
+                    let code = tfa_closure().map_err(|e| {
+                        Error::AuthSrpWithMessage(0, format!("Failed to get SMS 2FA code: {}", e))
+                    })?;
+                    flow.submit_code(code).await?;
                 }
+                _ => return flow.finish(),
             }
         }
     }
 
+    // The Apple ID stays out of the span, logs get attached to bug reports
+    #[tracing::instrument(name = "login", skip_all)]
     pub async fn login_email_pass(
         &mut self,
         username: &str,
@@ -112,7 +91,7 @@ impl Account {
         let a: Vec<u8> = (0..32).map(|_| rand::random::<u8>()).collect();
         let a_pub = srp_client.compute_public_ephemeral(&a);
 
-        let valid_anisette = self.get_anisette().await;
+        let valid_anisette = self.get_anisette().await?;
 
         let mut gsa_headers = HeaderMap::new();
         gsa_headers.insert(
@@ -126,7 +105,7 @@ impl Account {
         );
         gsa_headers.insert(
             "X-MMe-Client-Info",
-            HeaderValue::from_str(&valid_anisette.get_header("x-mme-client-info")?).unwrap(),
+            HeaderValue::from_str(&valid_anisette.get_header("x-mme-client-info")?)?,
         );
 
         let header = RequestHeader {
@@ -134,7 +113,7 @@ impl Account {
         };
         let body = InitRequestBody {
             a_pub: plist::Value::Data(a_pub),
-            cpd: valid_anisette.to_plist(true, false, false),
+            cpd: valid_anisette.to_plist(true, false, false)?,
             operation: "init".to_string(),
             ps: vec!["s2k".to_string(), "s2k_fo".to_string()],
             username: username.to_string(),
@@ -149,27 +128,22 @@ impl Account {
         plist::to_writer_xml(&mut buffer, &packet)?;
         let buffer = String::from_utf8(buffer).unwrap();
 
-        // println!("{:?}", gsa_headers.clone());
-        // println!("{:?}", buffer);
-
         let res = self
             .client
-            .post(GSA_ENDPOINT)
+            .post(self.endpoints.gsa_service())
             .headers(gsa_headers.clone())
             .body(buffer)
-            .send()
+            .send_logged(self.debug_logging)
             .await;
 
         let res = parse_response(res).await?;
-        let err_check = check_error(&res);
-        if err_check.is_err() {
-            return Err(err_check.err().unwrap());
-        }
-        // println!("{:?}", res);
-        let salt = res.get("s").unwrap().as_data().unwrap();
-        let b_pub = res.get("B").unwrap().as_data().unwrap();
-        let iters = res.get("i").unwrap().as_signed_integer().unwrap();
-        let c = res.get("c").unwrap().as_string().unwrap();
+        check_error(&res)?;
+        tracing::debug!("srp init accepted");
+        let salt = res.get_data("s")?;
+        let b_pub = res.get_data("B")?;
+        let iters = u32::try_from(res.get_integer("i")?)
+            .map_err(|_| res.unexpected("iteration count is out of range"))?;
+        let c = res.get_string("c")?;
 
         let hashed_password = Sha256::digest(password.as_bytes());
 
@@ -177,20 +151,20 @@ impl Account {
         pbkdf2::pbkdf2::<hmac::Hmac<Sha256>>(
             &hashed_password,
             salt,
-            iters as u32,
+            iters,
             &mut password_buf,
         );
 
         let verifier: SrpClientVerifier<Sha256> = srp_client
             .process_reply(&a, &username.as_bytes(), &password_buf, salt, b_pub)
-            .unwrap();
+            .map_err(|_| res.unexpected("server sent an invalid SRP public key"))?;
 
         let m = verifier.proof();
 
         let body = ChallengeRequestBody {
             m: plist::Value::Data(m.to_vec()),
             c: c.to_string(),
-            cpd: valid_anisette.to_plist(true, false, false),
+            cpd: valid_anisette.to_plist(true, false, false)?,
             operation: "complete".to_string(),
             username: username.to_string(),
         };
@@ -206,30 +180,31 @@ impl Account {
 
         let res = self
             .client
-            .post(GSA_ENDPOINT)
+            .post(self.endpoints.gsa_service())
             .headers(gsa_headers.clone())
             .body(buffer)
-            .send()
+            .send_logged(self.debug_logging)
             .await;
 
         let res = parse_response(res).await?;
-        let err_check = check_error(&res);
-        if err_check.is_err() {
-            return Err(err_check.err().unwrap());
-        }
-        // println!("{:?}", res);
-        let m2 = res.get("M2").unwrap().as_data().unwrap();
-        verifier.verify_server(&m2).unwrap();
+        check_error(&res)?;
+        let m2 = res.get_data("M2")?;
+        verifier
+            .verify_server(m2)
+            .map_err(|_| res.unexpected("server proof did not verify"))?;
 
-        let spd = res.get("spd").unwrap().as_data().unwrap();
-        let decrypted_spd = super::decrypt_cbc(&verifier, spd);
-        let decoded_spd: Dictionary = plist::from_bytes(&decrypted_spd).unwrap();
+        let spd = res.get_data("spd")?;
+        let decrypted_spd = super::decrypt_cbc(&verifier, spd)?;
+        let decoded_spd: Dictionary = plist::from_bytes(&decrypted_spd)
+            .map_err(|_| res.unexpected("spd is not a plist"))?;
 
-        let status = res.get("Status").unwrap().as_dictionary().unwrap();
+        let status = res.get_dictionary("Status")?;
 
         self.spd = Some(decoded_spd);
+        self.username = Some(username.to_string());
 
         if let Some(Value::String(s)) = status.get("au") {
+            tracing::info!(step = %s, "login needs another step");
             return match s.as_str() {
                 "trustedDeviceSecondaryAuth" => Ok(LoginState::NeedsDevice2FA),
                 "secondaryAuth" => Ok(LoginState::NeedsSMS2FA),
@@ -237,30 +212,85 @@ impl Account {
             };
         }
 
+        // Shared with clones of the account, so only dropped once the new session is in
+        self.clear_app_tokens().await;
+
+        tracing::info!("logged in");
         Ok(LoginState::LoggedIn)
     }
 
+    /// Logs in with a PET from an earlier login in place of the password.
+    ///
+    /// GSA skips 2FA for a PET it issued, so this never asks for a code. If it
+    /// wants one anyway the PET is no longer good and the login fails.
+    #[tracing::instrument(name = "login_pet", skip_all)]
+    pub async fn login_email_pet(&mut self, username: &str, pet: &str) -> Result<LoginState, Error> {
+        let previous_spd = self.spd.clone();
+        let previous_username = self.username.clone();
+
+        let state = match self.login_email_pass(username, pet).await? {
+            LoginState::LoggedIn => LoginState::LoggedIn,
+            LoginState::NeedsExtraStep(_) if self.get_pet().is_some() => {
+                self.clear_app_tokens().await;
+                LoginState::LoggedIn
+            }
+            state => {
+                // Leave the account as it was rather than half logged in
+                self.spd = previous_spd;
+                self.username = previous_username;
+                return Err(Error::PetLogin(format!("GSA asked for {:?}", state)));
+            }
+        };
+
+        Ok(state)
+    }
+
+    /// Logs in again with the Apple ID and PET of the current session, for
+    /// refreshing a session whose tokens expired without asking the user anything.
+    pub async fn refresh_with_pet(&mut self) -> Result<(), Error> {
+        let username = self.username.clone()
+            .ok_or_else(|| Error::PetLogin("session doesn't say which Apple ID it belongs to".to_string()))?;
+        let pet = self.get_pet()
+            .ok_or_else(|| Error::PetLogin("session has no PET".to_string()))?;
+
+        self.login_email_pet(&username, &pet).await?;
+        tracing::info!("session refreshed with PET");
+
+        Ok(())
+    }
+
+    /// The Apple ID this account logged in with.
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
     pub fn get_pet(&self) -> Option<String> {
-        let base = self.spd.as_ref().unwrap();
-        let token = base.get("t")?.as_dictionary()?;
+        self.try_get_pet().ok()
+    }
+
+    fn try_get_pet(&self) -> Result<String, Error> {
+        let base = self.logged_in_spd()?;
 
-        Some(plist_get_string!(
-            token,
+        Ok(plist_get_string!(
+            base,
+            "t"
             "com.apple.gs.idms.pet",
             "token"
         ))
     }
 
-    pub fn get_name(&self) -> (String, String) {
-        let base = self.spd.as_ref().unwrap();
-        (plist_get_string!(base, "fn"), plist_get_string!(base, "ln"))
+    pub fn get_name(&self) -> Result<(String, String), Error> {
+        let base = self.logged_in_spd()?;
+        Ok((plist_get_string!(base, "fn"), plist_get_string!(base, "ln")))
     }
 
-    pub async fn get_anisette(&self) -> AnisetteData {
-        let mut locked = self.anisette.lock().await;
-        if locked.needs_refresh() {
-            *locked = locked.refresh().await.unwrap();
-        }
-        locked.clone()
+    pub(crate) fn logged_in_spd(&self) -> Result<&Dictionary, Error> {
+        self.spd.as_ref().ok_or(Error::NotLoggedIn)
+    }
+
+    /// Returns the current anisette data, refreshed in the background once it
+    /// gets old. Only waits if it has gone stale, e.g. after sitting idle.
+    pub async fn get_anisette(&self) -> Result<AnisetteData, Error> {
+        self.anisette.current().await
     }
 }
@@ -1,54 +1,145 @@
+mod flow;
 mod login;
 pub mod request;
+mod refresher;
+mod session;
 mod token;
+mod traffic;
 mod two_factor_auth;
 
+pub use flow::LoginFlow;
+pub(crate) use refresher::AnisetteRefresher;
+pub use traffic::{Exchange, Traffic, TrafficRecorder, TrafficReplayer};
+
 use cbc::cipher::{BlockDecryptMut, KeyIvInit, block_padding::Pkcs7};
 use hmac::{Hmac, Mac};
+use plist::{Dictionary, Value};
 use reqwest::Response;
 use sha2::Sha256;
 use srp::client::SrpClientVerifier;
 
 use crate::Error;
 
+// GSA `ec` values that get their own error
+const INCORRECT_VERIFICATION_CODE: i64 = -21669;
+const ACCOUNT_LOCKED: i64 = -20209;
+
+/// A GSA plist response, along with the HTTP status it came back with.
+pub struct GsaResponse {
+    pub status: u16,
+    pub body: Dictionary,
+}
+
+impl GsaResponse {
+    pub fn get_data(&self, key: &str) -> Result<&[u8], Error> {
+        dict_data(&self.body, key, Some(self.status))
+    }
+
+    pub fn get_string(&self, key: &str) -> Result<&str, Error> {
+        dict_string(&self.body, key, Some(self.status))
+    }
+
+    pub fn get_integer(&self, key: &str) -> Result<i64, Error> {
+        dict_integer(&self.body, key, Some(self.status))
+    }
+
+    pub fn get_dictionary(&self, key: &str) -> Result<&Dictionary, Error> {
+        dict_dictionary(&self.body, key, Some(self.status))
+    }
+
+    pub fn unexpected(&self, reason: &str) -> Error {
+        Error::UnexpectedResponse { reason: reason.to_string(), status: Some(self.status) }
+    }
+}
+
 pub async fn parse_response(
     res: Result<Response, reqwest::Error>,
-) -> Result<plist::Dictionary, Error> {
-    let res = res?.text().await?;
-    let res: plist::Dictionary = plist::from_bytes(res.as_bytes())?;
-    let res: plist::Value = res.get("Response").unwrap().to_owned();
-    match res {
-        plist::Value::Dictionary(dict) => Ok(dict),
-        _ => Err(crate::Error::Parse),
-    }
+) -> Result<GsaResponse, Error> {
+    let res = res?;
+    let status = res.status().as_u16();
+    let text = res.text().await?;
+
+    parse_response_body(status, &text)
+}
+
+/// Parses the body of a GSA response that came back with `status`.
+pub fn parse_response_body(status: u16, text: &str) -> Result<GsaResponse, Error> {
+    let res: Dictionary = plist::from_bytes(text.as_bytes()).map_err(|e| {
+        Error::UnexpectedResponse { reason: format!("body is not a plist: {}", e), status: Some(status) }
+    })?;
+
+    let body = dict_dictionary(&res, "Response", Some(status))?.clone();
+
+    Ok(GsaResponse { status, body })
 }
 
-pub fn check_error(res: &plist::Dictionary) -> Result<(), Error> {
-    let res = match res.get("Status") {
-        Some(plist::Value::Dictionary(d)) => d,
-        _ => &res,
+pub fn check_error(res: &GsaResponse) -> Result<(), Error> {
+    let status = match res.body.get("Status") {
+        Some(Value::Dictionary(d)) => d,
+        _ => &res.body,
     };
 
-    if res.get("ec").unwrap().as_signed_integer().unwrap() != 0 {
-        return Err(Error::AuthSrpWithMessage(
-            res.get("ec").unwrap().as_signed_integer().unwrap().into(),
-            res.get("em").unwrap().as_string().unwrap().to_owned(),
-        ));
+    let ec = dict_integer(status, "ec", Some(res.status))?;
+    if ec != 0 {
+        let em = status
+            .get("em")
+            .and_then(|v| v.as_string())
+            .unwrap_or("Unknown error")
+            .to_owned();
+
+        return Err(match ec {
+            INCORRECT_VERIFICATION_CODE => Error::Bad2faCode,
+            ACCOUNT_LOCKED => Error::AccountLocked(em),
+            _ => Error::AuthSrpWithMessage(ec, em),
+        });
     }
 
     Ok(())
 }
 
+// MARK: - Dictionary lookups
+
+// `status` is the HTTP status of the response the dictionary came from, if any
+
+pub fn dict_value<'a>(dict: &'a Dictionary, key: &str, status: Option<u16>) -> Result<&'a Value, Error> {
+    dict.get(key).ok_or_else(|| Error::ResponseKey { key: key.to_string(), status })
+}
+
+pub fn dict_data<'a>(dict: &'a Dictionary, key: &str, status: Option<u16>) -> Result<&'a [u8], Error> {
+    dict_value(dict, key, status)?
+        .as_data()
+        .ok_or_else(|| Error::ResponseKey { key: key.to_string(), status })
+}
+
+pub fn dict_string<'a>(dict: &'a Dictionary, key: &str, status: Option<u16>) -> Result<&'a str, Error> {
+    dict_value(dict, key, status)?
+        .as_string()
+        .ok_or_else(|| Error::ResponseKey { key: key.to_string(), status })
+}
+
+pub fn dict_integer(dict: &Dictionary, key: &str, status: Option<u16>) -> Result<i64, Error> {
+    dict_value(dict, key, status)?
+        .as_signed_integer()
+        .ok_or_else(|| Error::ResponseKey { key: key.to_string(), status })
+}
 
-pub fn decrypt_cbc(usr: &SrpClientVerifier<Sha256>, data: &[u8]) -> Vec<u8> {
+pub fn dict_dictionary<'a>(dict: &'a Dictionary, key: &str, status: Option<u16>) -> Result<&'a Dictionary, Error> {
+    dict_value(dict, key, status)?
+        .as_dictionary()
+        .ok_or_else(|| Error::ResponseKey { key: key.to_string(), status })
+}
+
+// MARK: - SRP
+
+pub fn decrypt_cbc(usr: &SrpClientVerifier<Sha256>, data: &[u8]) -> Result<Vec<u8>, Error> {
     let extra_data_key = create_session_key(usr, "extra data key:");
     let extra_data_iv = create_session_key(usr, "extra data iv:");
     let extra_data_iv = &extra_data_iv[..16];
 
     cbc::Decryptor::<aes::Aes256>::new_from_slices(&extra_data_key, extra_data_iv)
-        .unwrap()
-        .decrypt_padded_vec_mut::<Pkcs7>(&data)
-        .unwrap()
+        .map_err(|_| Error::UnexpectedResponse { reason: "invalid spd key".to_string(), status: None })?
+        .decrypt_padded_vec_mut::<Pkcs7>(data)
+        .map_err(|_| Error::UnexpectedResponse { reason: "failed to decrypt spd".to_string(), status: None })
 }
 
 pub fn create_session_key(usr: &SrpClientVerifier<Sha256>, name: &str) -> Vec<u8> {
@@ -59,3 +150,211 @@ pub fn create_session_key(usr: &SrpClientVerifier<Sha256>, name: &str) -> Vec<u8
         .into_bytes()
         .to_vec()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plist_xml(response: Value) -> String {
+        let mut root = Dictionary::new();
+        root.insert("Response".to_string(), response);
+
+        let mut buf = Vec::new();
+        plist::to_writer_xml(&mut buf, &Value::Dictionary(root)).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn response(entries: &[(&str, Value)]) -> Dictionary {
+        entries.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+    }
+
+    fn init_response() -> Dictionary {
+        let mut status = Dictionary::new();
+        status.insert("ec".to_string(), Value::Integer(0.into()));
+
+        response(&[
+            ("Status", Value::Dictionary(status)),
+            ("s", Value::Data(vec![1; 16])),
+            ("B", Value::Data(vec![2; 256])),
+            ("i", Value::Integer(20000.into())),
+            ("c", Value::String("challenge".to_string())),
+        ])
+    }
+
+    fn assert_key_error(result: Result<impl std::fmt::Debug, Error>, expected_key: &str, expected_status: Option<u16>) {
+        match result {
+            Err(Error::ResponseKey { key, status }) => {
+                assert_eq!(key, expected_key);
+                assert_eq!(status, expected_status);
+            }
+            other => panic!("expected ResponseKey({}), got {:?}", expected_key, other),
+        }
+    }
+
+    // Deterministic so a failure can be reproduced
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn parses_a_well_formed_response() {
+        let res = parse_response_body(200, &plist_xml(Value::Dictionary(init_response()))).unwrap();
+
+        assert_eq!(res.status, 200);
+        check_error(&res).unwrap();
+        assert_eq!(res.get_string("c").unwrap(), "challenge");
+        assert_eq!(res.get_integer("i").unwrap(), 20000);
+    }
+
+    #[test]
+    fn body_that_is_not_a_plist_is_unexpected() {
+        for body in ["", "not a plist", "<?xml version=\"1.0\"?><plist><dict><key>Response</key>", "\u{0}\u{1}\u{2}"] {
+            match parse_response_body(503, body) {
+                Err(Error::UnexpectedResponse { status, .. }) => assert_eq!(status, Some(503)),
+                other => panic!("expected UnexpectedResponse for {:?}, got {:?}", body, other.map(|r| r.body)),
+            }
+        }
+    }
+
+    #[test]
+    fn missing_or_mistyped_response_dictionary() {
+        let mut root = Dictionary::new();
+        root.insert("Other".to_string(), Value::Boolean(true));
+        let mut buf = Vec::new();
+        plist::to_writer_xml(&mut buf, &Value::Dictionary(root)).unwrap();
+
+        assert_key_error(parse_response_body(200, &String::from_utf8(buf).unwrap()).map(|r| r.body), "Response", Some(200));
+        assert_key_error(
+            parse_response_body(401, &plist_xml(Value::String("nope".to_string()))).map(|r| r.body),
+            "Response",
+            Some(401),
+        );
+    }
+
+    #[test]
+    fn getters_report_missing_and_mistyped_keys() {
+        let res = GsaResponse { status: 409, body: init_response() };
+
+        assert_key_error(res.get_data("missing"), "missing", Some(409));
+        assert_key_error(res.get_data("c"), "c", Some(409));
+        assert_key_error(res.get_string("i"), "i", Some(409));
+        assert_key_error(res.get_integer("s"), "s", Some(409));
+        assert_key_error(res.get_dictionary("B"), "B", Some(409));
+    }
+
+    #[test]
+    fn check_error_without_usable_ec() {
+        let res = GsaResponse { status: 200, body: response(&[("s", Value::Data(vec![1]))]) };
+        assert_key_error(check_error(&res), "ec", Some(200));
+
+        let mut status = Dictionary::new();
+        status.insert("ec".to_string(), Value::String("0".to_string()));
+        let res = GsaResponse { status: 500, body: response(&[("Status", Value::Dictionary(status))]) };
+        assert_key_error(check_error(&res), "ec", Some(500));
+
+        // A `Status` that isn't a dictionary falls back to the body, which has no `ec`
+        let res = GsaResponse { status: 200, body: response(&[("Status", Value::Boolean(false))]) };
+        assert_key_error(check_error(&res), "ec", Some(200));
+    }
+
+    #[test]
+    fn check_error_maps_known_codes() {
+        let with_ec = |ec: i64, em: Option<&str>| {
+            let mut status = Dictionary::new();
+            status.insert("ec".to_string(), Value::Integer(ec.into()));
+            if let Some(em) = em {
+                status.insert("em".to_string(), Value::String(em.to_string()));
+            }
+            GsaResponse { status: 200, body: response(&[("Status", Value::Dictionary(status))]) }
+        };
+
+        assert!(matches!(check_error(&with_ec(INCORRECT_VERIFICATION_CODE, None)), Err(Error::Bad2faCode)));
+        assert!(matches!(
+            check_error(&with_ec(ACCOUNT_LOCKED, Some("locked"))),
+            Err(Error::AccountLocked(em)) if em == "locked"
+        ));
+        assert!(matches!(
+            check_error(&with_ec(-22406, None)),
+            Err(Error::AuthSrpWithMessage(-22406, em)) if em == "Unknown error"
+        ));
+    }
+
+    #[test]
+    fn truncated_responses_never_panic() {
+        let xml = plist_xml(Value::Dictionary(init_response()));
+
+        for len in 0..xml.len() {
+            let Some(truncated) = xml.get(..len) else { continue };
+
+            match parse_response_body(200, truncated) {
+                Ok(res) => {
+                    let _ = check_error(&res);
+                }
+                Err(Error::UnexpectedResponse { status, .. }) | Err(Error::ResponseKey { status, .. }) => {
+                    assert_eq!(status, Some(200));
+                }
+                Err(e) => panic!("unexpected error for {} bytes: {:?}", len, e),
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_responses_never_panic() {
+        let xml = plist_xml(Value::Dictionary(init_response())).into_bytes();
+        let mut state = 0x2545_f491_4f6c_dd1d;
+
+        for _ in 0..2000 {
+            let mut corrupted = xml.clone();
+            for _ in 0..(xorshift(&mut state) % 8 + 1) {
+                let index = (xorshift(&mut state) as usize) % corrupted.len();
+                corrupted[index] = xorshift(&mut state) as u8;
+            }
+            let text = String::from_utf8_lossy(&corrupted);
+
+            match parse_response_body(200, &text) {
+                Ok(res) => {
+                    let _ = check_error(&res);
+                    let _ = res.get_data("s");
+                    let _ = res.get_data("B");
+                    let _ = res.get_integer("i");
+                    let _ = res.get_string("c");
+                }
+                Err(Error::UnexpectedResponse { status, .. }) | Err(Error::ResponseKey { status, .. }) => {
+                    assert_eq!(status, Some(200));
+                }
+                Err(e) => panic!("unexpected error: {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn wrongly_typed_values_never_panic() {
+        let values = [
+            Value::Boolean(true),
+            Value::Integer((-1).into()),
+            Value::Real(1.5),
+            Value::String(String::new()),
+            Value::Data(Vec::new()),
+            Value::Array(Vec::new()),
+            Value::Dictionary(Dictionary::new()),
+        ];
+
+        for name in ["Status", "s", "B", "i", "c"] {
+            for value in &values {
+                let mut body = init_response();
+                body.insert(name.to_string(), value.clone());
+                let res = parse_response_body(200, &plist_xml(Value::Dictionary(body))).unwrap();
+
+                let _ = check_error(&res);
+                for result in [res.get_data(name).map(|_| ()), res.get_string(name).map(|_| ()), res.get_integer(name).map(|_| ())] {
+                    if let Err(e) = result {
+                        assert!(matches!(e, Error::ResponseKey { ref key, status: Some(200) } if key == name), "{:?}", e);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use tokio::sync::{Mutex, watch};
+
+use crate::Error;
+
+use crate::auth::anisette_data::AnisetteData;
+
+/// Keeps an account's anisette data fresh, refreshing only when a request
+/// asks for headers that are getting old.
+///
+/// Requests read the last good snapshot and never wait on the provider while
+/// it is still valid, an idle account doesn't talk to the provider at all.
+/// Clones of an account share one refresher.
+#[derive(Debug)]
+pub(crate) struct AnisetteRefresher {
+    current: watch::Sender<AnisetteData>,
+    // Held by whoever is fetching, so a burst of requests only fetches once
+    refreshing: Arc<Mutex<()>>,
+}
+
+impl AnisetteRefresher {
+    pub(crate) fn new(anisette: AnisetteData) -> Arc<Self> {
+        let (current, _) = watch::channel(anisette);
+
+        Arc::new(Self {
+            current,
+            refreshing: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// The latest anisette data. Only waits if it has gone stale, e.g. after
+    /// the account sat idle, and then on a single fetch shared by every request.
+    pub(crate) async fn current(self: &Arc<Self>) -> Result<AnisetteData, Error> {
+        let anisette = self.snapshot();
+
+        if !anisette.needs_refresh() {
+            return Ok(anisette);
+        }
+        if anisette.is_valid() {
+            self.refresh_in_background();
+            return Ok(anisette);
+        }
+
+        let _refreshing = self.refreshing.lock().await;
+        self.refresh().await
+    }
+
+    fn snapshot(&self) -> AnisetteData {
+        self.current.borrow().clone()
+    }
+
+    // Still usable, so the request goes ahead with the old headers while the
+    // new ones are fetched on its runtime
+    fn refresh_in_background(self: &Arc<Self>) {
+        let Ok(refreshing) = self.refreshing.clone().try_lock_owned() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+
+        let refresher = self.clone();
+        runtime.spawn(async move {
+            let _refreshing = refreshing;
+            if let Err(e) = refresher.refresh().await {
+                tracing::warn!(error = %e, "anisette refresh failed");
+            }
+        });
+    }
+
+    // Callers hold `refreshing`, whoever had it before may have refreshed already
+    async fn refresh(&self) -> Result<AnisetteData, Error> {
+        let anisette = self.snapshot();
+        if !anisette.needs_refresh() {
+            return Ok(anisette);
+        }
+
+        self.offer(anisette.refresh().await?);
+        Ok(self.snapshot())
+    }
+
+    // Keeps whichever of the two is newer
+    fn offer(&self, anisette: AnisetteData) {
+        self.current.send_if_modified(|current| {
+            if anisette.generated_at > current.generated_at {
+                *current = anisette;
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, SystemTime};
+
+    use futures::future::BoxFuture;
+
+    use super::*;
+    use crate::auth::anisette_provider::{AnisetteProvider, MockProvider};
+
+    // Counts how often headers were fetched
+    #[derive(Debug)]
+    struct CountingProvider {
+        mock: MockProvider,
+        fetches: AtomicUsize,
+    }
+
+    impl AnisetteProvider for CountingProvider {
+        fn get_headers(&self) -> BoxFuture<'_, Result<HashMap<String, String>, Error>> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            self.mock.get_headers()
+        }
+    }
+
+    fn aged(provider: &Arc<CountingProvider>, age: Duration) -> AnisetteData {
+        AnisetteData {
+            base_headers: HashMap::new(),
+            generated_at: SystemTime::now() - age,
+            provider: provider.clone(),
+        }
+    }
+
+    fn counting() -> Arc<CountingProvider> {
+        Arc::new(CountingProvider {
+            mock: MockProvider::new("counting"),
+            fetches: AtomicUsize::new(0),
+        })
+    }
+
+    #[tokio::test]
+    async fn fresh_headers_are_read_without_a_refresh() {
+        let provider = counting();
+        let anisette = aged(&provider, Duration::ZERO);
+        let generated_at = anisette.generated_at;
+
+        let current = AnisetteRefresher::new(anisette).current().await.unwrap();
+
+        assert_eq!(current.generated_at, generated_at);
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn ageing_headers_are_served_while_they_refresh() {
+        let provider = counting();
+        let anisette = aged(&provider, Duration::from_secs(75));
+        let generated_at = anisette.generated_at;
+        let refresher = AnisetteRefresher::new(anisette);
+        let mut updates = refresher.current.subscribe();
+
+        assert_eq!(refresher.current().await.unwrap().generated_at, generated_at);
+
+        tokio::time::timeout(Duration::from_secs(5), updates.changed()).await.unwrap().unwrap();
+        assert!(!refresher.snapshot().needs_refresh());
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn stale_headers_are_refreshed_once() {
+        let provider = counting();
+        let refresher = AnisetteRefresher::new(aged(&provider, Duration::from_secs(120)));
+
+        let (first, second) = tokio::join!(refresher.current(), refresher.current());
+
+        assert!(first.unwrap().is_valid());
+        assert!(second.unwrap().is_valid());
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn the_newer_snapshot_wins() {
+        let provider = counting();
+        let old = aged(&provider, Duration::from_secs(30));
+        let new = aged(&provider, Duration::ZERO);
+        let new_generated_at = new.generated_at;
+
+        let refresher = AnisetteRefresher::new(new);
+        refresher.offer(old);
+
+        assert_eq!(refresher.snapshot().generated_at, new_generated_at);
+    }
+}
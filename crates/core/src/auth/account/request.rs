@@ -1,10 +1,17 @@
 use plist::Dictionary;
+use reqwest::StatusCode;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde_json::Value;
 
 use crate::Error;
 
 use crate::{SessionRequestTrait, auth::Account};
+use crate::auth::SendLogged;
+use crate::auth::account::dict_string;
+
+const XCODE_AUTH_APP: &str = "com.apple.gs.xcode.auth";
+// What the portal answers a stale token with, inside an otherwise successful response
+const SESSION_EXPIRED: i64 = 1100;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RequestType {
@@ -13,15 +20,89 @@ pub enum RequestType {
     Patch,
 }
 
+impl RequestType {
+    // How a request shows up in traffic fixtures, GETs with a body are sent as
+    // a POST overridden to GET
+    fn traffic_method(request_type: Option<RequestType>, has_body: bool) -> &'static str {
+        match (request_type, has_body) {
+            (Some(RequestType::Patch), true) => "PATCH",
+            (Some(RequestType::Get), _) | (_, false) => "GET",
+            _ => "POST",
+        }
+    }
+}
+
 impl SessionRequestTrait for Account {
+    #[tracing::instrument(name = "qh_request", skip_all, fields(url = %url, method = tracing::field::Empty))]
     async fn qh_send_request(
         &self,
         url: &str,
         body: Option<Dictionary>,
     ) -> Result<Dictionary, Error> {
-        let spd = self.spd.as_ref().unwrap();
-        let app_token = self.get_app_token("com.apple.gs.xcode.auth").await?;
-        let valid_anisette = self.get_anisette().await;
+        let method = if body.is_some() { "POST" } else { "GET" };
+        tracing::Span::current().record("method", method);
+
+        if let Some(response) = self.replay_qh(method, url, body.as_ref()) {
+            tracing::debug!("replayed");
+            return response;
+        }
+
+        let request = body.clone();
+        let (status, text) = self.qh_send_live(url, body).await?;
+        let response = plist::from_bytes::<Dictionary>(text.as_bytes()).map_err(Error::from);
+
+        let expired = response
+            .as_ref()
+            .is_ok_and(|r| r.get("resultCode").and_then(plist::Value::as_signed_integer) == Some(SESSION_EXPIRED));
+        if expired || status == StatusCode::UNAUTHORIZED.as_u16() {
+            self.evict_app_token(XCODE_AUTH_APP).await;
+        }
+
+        self.record_qh(method, url, request.as_ref(), status, &text, response.as_ref());
+
+        response
+    }
+
+    #[tracing::instrument(name = "v1_request", skip_all, fields(url = %url, method = tracing::field::Empty))]
+    async fn v1_send_request(
+        &self,
+        url: &str,
+        body: Option<Value>,
+        request_type: Option<RequestType>,
+    ) -> Result<Value, Error> {
+        let method = RequestType::traffic_method(request_type, body.is_some());
+        tracing::Span::current().record("method", method);
+
+        if let Some(response) = self.replay_v1(method, url, body.as_ref()) {
+            tracing::debug!("replayed");
+            return response;
+        }
+
+        let request = body.clone();
+        let (status, text) = self.v1_send_live(url, body, request_type).await?;
+        let response = serde_json::from_str::<Value>(&text).map_err(Error::from);
+
+        if status == StatusCode::UNAUTHORIZED.as_u16() {
+            self.evict_app_token(XCODE_AUTH_APP).await;
+        }
+
+        self.record_v1(method, url, request.as_ref(), status, &text, response.as_ref());
+
+        response
+    }
+}
+
+// The live requests hand back the status and raw body, so a response that
+// doesn't parse can still be recorded
+impl Account {
+    async fn qh_send_live(
+        &self,
+        url: &str,
+        body: Option<Dictionary>,
+    ) -> Result<(u16, String), Error> {
+        let spd = self.logged_in_spd()?;
+        let app_token = self.get_app_token(XCODE_AUTH_APP).await?;
+        let valid_anisette = self.get_anisette().await?;
 
         let mut headers = HeaderMap::new();
         headers.insert("Content-Type", HeaderValue::from_static("text/x-xml-plist"));
@@ -30,22 +111,22 @@ impl SessionRequestTrait for Account {
         headers.insert("User-Agent", HeaderValue::from_static("Xcode"));
         headers.insert(
             "X-Apple-I-Identity-Id",
-            HeaderValue::from_str(spd.get("adsid").unwrap().as_string().unwrap()).unwrap(),
+            HeaderValue::from_str(dict_string(spd, "adsid", None)?)?,
         );
         headers.insert(
             "X-Apple-GS-Token",
-            HeaderValue::from_str(&app_token.auth_token).unwrap(),
+            HeaderValue::from_str(&app_token.auth_token)?,
         );
 
-        for (k, v) in valid_anisette.generate_headers(false, true, true) {
+        for (k, v) in valid_anisette.generate_headers(false, true, true)? {
             headers.insert(
-                HeaderName::from_bytes(k.as_bytes()).unwrap(),
-                HeaderValue::from_str(&v).unwrap(),
+                HeaderName::from_bytes(k.as_bytes())?,
+                HeaderValue::from_str(&v)?,
             );
         }
 
         if let Ok(locale) = valid_anisette.get_header("x-apple-locale") {
-            headers.insert("X-Apple-Locale", HeaderValue::from_str(&locale).unwrap());
+            headers.insert("X-Apple-Locale", HeaderValue::from_str(&locale)?);
         }
 
         let response = if let Some(body) = body {
@@ -55,27 +136,25 @@ impl SessionRequestTrait for Account {
                 .post(url)
                 .headers(headers)
                 .body(buf)
-                .send()
+                .send_logged(self.debug_logging)
                 .await?
         } else {
-            self.client.get(url).headers(headers).send().await?
+            self.client.get(url).headers(headers).send_logged(self.debug_logging).await?
         };
 
-        let response = response.text().await?;
-        let response_data: Dictionary = plist::from_bytes(response.as_bytes())?;
-        
-        Ok(response_data)
+        let status = response.status().as_u16();
+        Ok((status, response.text().await?))
     }
     
-    async fn v1_send_request(
+    async fn v1_send_live(
         &self,
         url: &str,
         body: Option<Value>,
         request_type: Option<RequestType>,
-    ) -> Result<Value, Error> {
-        let spd = self.spd.as_ref().unwrap();
-        let app_token = self.get_app_token("com.apple.gs.xcode.auth").await?;
-        let valid_anisette = self.get_anisette().await;
+    ) -> Result<(u16, String), Error> {
+        let spd = self.logged_in_spd()?;
+        let app_token = self.get_app_token(XCODE_AUTH_APP).await?;
+        let valid_anisette = self.get_anisette().await?;
 
         let mut headers = HeaderMap::new();
         headers.insert("Content-Type", HeaderValue::from_static("application/vnd.api+json"));
@@ -86,22 +165,22 @@ impl SessionRequestTrait for Account {
         
         headers.insert(
             "X-Apple-I-Identity-Id",
-            HeaderValue::from_str(spd.get("adsid").unwrap().as_string().unwrap()).unwrap(),
+            HeaderValue::from_str(dict_string(spd, "adsid", None)?)?,
         );
         headers.insert(
             "X-Apple-GS-Token",
-            HeaderValue::from_str(&app_token.auth_token).unwrap()
+            HeaderValue::from_str(&app_token.auth_token)?
         );
 
-        for (k, v) in valid_anisette.generate_headers(false, true, true) {
+        for (k, v) in valid_anisette.generate_headers(false, true, true)? {
             headers.insert(
-                HeaderName::from_bytes(k.as_bytes()).unwrap(),
-                HeaderValue::from_str(&v).unwrap()
+                HeaderName::from_bytes(k.as_bytes())?,
+                HeaderValue::from_str(&v)?
             );
         }
 
         if let Ok(locale) = valid_anisette.get_header("x-apple-locale") {
-            headers.insert("X-Apple-Locale", HeaderValue::from_str(&locale).unwrap());
+            headers.insert("X-Apple-Locale", HeaderValue::from_str(&locale)?);
         }
         
         if let Some(RequestType::Get) = request_type {
@@ -117,7 +196,7 @@ impl SessionRequestTrait for Account {
                     .post(url)
                     .headers(headers)
                     .json(&body)
-                    .send()
+                    .send_logged(self.debug_logging)
                     .await?
             }
             (Some(RequestType::Patch), Some(body)) => {
@@ -125,7 +204,7 @@ impl SessionRequestTrait for Account {
                     .patch(url)
                     .headers(headers)
                     .json(&body)
-                    .send()
+                    .send_logged(self.debug_logging)
                     .await?
             }
             (_, Some(body)) => {
@@ -133,17 +212,15 @@ impl SessionRequestTrait for Account {
                     .post(url)
                     .headers(headers)
                     .json(&body)
-                    .send()
+                    .send_logged(self.debug_logging)
                     .await?
             }
             _ => {
-                self.client.get(url).headers(headers).send().await?
+                self.client.get(url).headers(headers).send_logged(self.debug_logging).await?
             }
         };
 
-        let response = response.text().await?;
-        let response_data: Value = serde_json::from_str(&response)?;
-
-        Ok(response_data)
+        let status = response.status().as_u16();
+        Ok((status, response.text().await?))
     }
 }
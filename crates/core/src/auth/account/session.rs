@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+
+use botan::{Cipher, CipherDirection};
+use omnisette::AnisetteConfiguration;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+use crate::auth::Account;
+use crate::auth::anisette_data::AnisetteData;
+
+const SESSION_MAGIC: &[u8; 4] = b"PLSN";
+const SESSION_VERSION: u8 = 1;
+const SESSION_NONCE_LEN: usize = 12;
+const SESSION_KEY_LEN: usize = 32;
+
+// Keys `get_app_token` needs out of the spd, anything less can't be used
+const REQUIRED_SPD_KEYS: [&str; 4] = ["adsid", "GsIdmsToken", "sk", "c"];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionBlob {
+    spd: plist::Dictionary,
+    // Missing from sessions saved before PET refresh, those can't be refreshed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    // GSA tokens are bound to the anisette device they were issued to
+    device: BTreeMap<String, String>,
+}
+
+impl Account {
+    /// Generates a random key suitable for `export_session` and `restore_session`.
+    pub fn generate_session_key() -> [u8; SESSION_KEY_LEN] {
+        rand::random()
+    }
+
+    /// The form of an Apple ID sessions are saved under, lowercase and with
+    /// anything an email address wouldn't have replaced so it is safe in a path.
+    pub fn normalize_apple_id(apple_id: &str) -> String {
+        apple_id
+            .trim()
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || "@.-_+".contains(c) { c } else { '_' })
+            .collect()
+    }
+
+    /// Encrypts the authenticated session so it can be restored later without the password.
+    pub async fn export_session(&self, key: &[u8]) -> Result<Vec<u8>, Error> {
+        let spd = self.spd.clone()
+            .ok_or_else(|| Error::Session("account is not logged in".to_string()))?;
+        Self::validate_spd(&spd)?;
+
+        let blob = SessionBlob {
+            spd,
+            username: self.username.clone(),
+            device: self.get_anisette().await?.device_identity(),
+        };
+
+        let mut plaintext = Vec::new();
+        plist::to_writer_binary(&mut plaintext, &blob)?;
+
+        let nonce: [u8; SESSION_NONCE_LEN] = rand::random();
+        let header = Self::session_header();
+
+        let mut cipher = Self::session_cipher(key, CipherDirection::Encrypt, &header, &nonce)?;
+        let ciphertext = cipher
+            .finish(&mut plaintext)
+            .map_err(|_| Error::Session("failed to encrypt session".to_string()))?;
+
+        let mut out = Vec::with_capacity(header.len() + nonce.len() + ciphertext.len());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ciphertext);
+
+        Ok(out)
+    }
+
+    /// Restores a session written by `export_session`, checking with GSA that it is still valid.
+    pub async fn restore_session(
+        blob: &[u8],
+        key: &[u8],
+        config: AnisetteConfiguration,
+    ) -> Result<Self, Error> {
+        let anisette = AnisetteData::new(config).await?;
+        Self::restore_session_with_anisette(blob, key, anisette).await
+    }
+
+    pub async fn restore_session_with_anisette(
+        blob: &[u8],
+        key: &[u8],
+        anisette: AnisetteData,
+    ) -> Result<Self, Error> {
+        Self::restore_session_with_account(blob, key, Self::new_with_anisette(anisette)?).await
+    }
+
+    /// Restores the session into `account`, keeping its endpoints and client.
+    pub async fn restore_session_with_account(
+        blob: &[u8],
+        key: &[u8],
+        account: Account,
+    ) -> Result<Self, Error> {
+        Ok(Self::restore_or_refresh_session(blob, key, account).await?.0)
+    }
+
+    /// Like `restore_session_with_account`, but logs in again with the saved
+    /// PET if the session's tokens have expired. Returns whether it did, the
+    /// session should then be exported again since the old one won't work anymore.
+    ///
+    /// Only `Error::Session` means the saved session is no good, anything else
+    /// (network, anisette, ...) may well work on the next try.
+    #[tracing::instrument(name = "restore_session", skip_all)]
+    pub async fn restore_or_refresh_session(
+        blob: &[u8],
+        key: &[u8],
+        mut account: Account,
+    ) -> Result<(Self, bool), Error> {
+        let header = Self::session_header();
+
+        if blob.len() < header.len() + SESSION_NONCE_LEN || &blob[..SESSION_MAGIC.len()] != SESSION_MAGIC {
+            return Err(Error::Session("not a saved session".to_string()));
+        }
+        if blob[SESSION_MAGIC.len()] != SESSION_VERSION {
+            return Err(Error::Session(format!("unsupported session version {}", blob[SESSION_MAGIC.len()])));
+        }
+
+        let (nonce, ciphertext) = blob[header.len()..].split_at(SESSION_NONCE_LEN);
+
+        let mut cipher = Self::session_cipher(key, CipherDirection::Decrypt, &header, nonce)?;
+        let mut buf = ciphertext.to_vec();
+        buf = cipher
+            .finish(&mut buf)
+            .map_err(|_| Error::Session("failed to decrypt session, wrong key or corrupted data".to_string()))?;
+
+        let blob: SessionBlob = plist::from_bytes(&buf)
+            .map_err(|e| Error::Session(format!("saved session is corrupted: {}", e)))?;
+        Self::validate_spd(&blob.spd)?;
+
+        if account.get_anisette().await?.device_identity() != blob.device {
+            return Err(Error::Session("anisette device has changed since the session was saved".to_string()));
+        }
+
+        account.spd = Some(blob.spd);
+        account.username = blob.username;
+        account.clear_app_tokens().await;
+
+        let error = match account.get_app_token("com.apple.gs.xcode.auth").await {
+            Ok(_) => return Ok((account, false)),
+            Err(e) if Self::is_rejection(&e) => e,
+            Err(e) => return Err(e),
+        };
+
+        tracing::info!(error = %error, "session tokens expired, trying the PET");
+
+        account.refresh_with_pet().await.map_err(|e| match e {
+            e if Self::is_rejection(&e) => Error::Session(format!("session is no longer valid: {}, {}", error, e)),
+            e => e,
+        })?;
+
+        account.get_app_token("com.apple.gs.xcode.auth").await.map_err(|e| match e {
+            e if Self::is_rejection(&e) => Error::Session(format!("session is no longer valid: {}", e)),
+            e => e,
+        })?;
+
+        Ok((account, true))
+    }
+
+    // GSA answered and said no, as opposed to not being reachable at all
+    fn is_rejection(error: &Error) -> bool {
+        matches!(
+            error,
+            Error::AuthSrpWithMessage(..) | Error::AccountLocked(_) | Error::PetLogin(_)
+        )
+    }
+
+    fn validate_spd(spd: &plist::Dictionary) -> Result<(), Error> {
+        for key in REQUIRED_SPD_KEYS {
+            if !spd.contains_key(key) {
+                return Err(Error::Session(format!("session is missing {}", key)));
+            }
+        }
+
+        match spd.get("sk").and_then(|v| v.as_data()) {
+            Some(sk) if sk.len() == 32 => Ok(()),
+            _ => Err(Error::Session("session key is malformed".to_string())),
+        }
+    }
+
+    fn session_header() -> [u8; 5] {
+        let mut header = [0u8; 5];
+        header[..SESSION_MAGIC.len()].copy_from_slice(SESSION_MAGIC);
+        header[SESSION_MAGIC.len()] = SESSION_VERSION;
+        header
+    }
+
+    fn session_cipher(
+        key: &[u8],
+        direction: CipherDirection,
+        header: &[u8],
+        nonce: &[u8],
+    ) -> Result<Cipher, Error> {
+        if key.len() != SESSION_KEY_LEN {
+            return Err(Error::Session(format!("session key must be {} bytes", SESSION_KEY_LEN)));
+        }
+
+        let mut cipher = Cipher::new("AES-256/GCM", direction)
+            .map_err(|_| Error::Session("AES-256/GCM is unavailable".to_string()))?;
+        cipher.set_key(key).map_err(|_| Error::Session("invalid session key".to_string()))?;
+        cipher.set_associated_data(header).map_err(|_| Error::Parse)?;
+        cipher.start(nonce).map_err(|_| Error::Parse)?;
+
+        Ok(cipher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apple_ids_are_normalized_for_paths() {
+        assert_eq!(Account::normalize_apple_id("  Someone+Dev@Example.com\n"), "someone+dev@example.com");
+        assert_eq!(Account::normalize_apple_id("../../evil@example.com"), ".._.._evil@example.com");
+        assert_eq!(Account::normalize_apple_id("a b\\c@d"), "a_b_c@d");
+    }
+}
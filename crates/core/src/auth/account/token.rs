@@ -1,3 +1,5 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use botan::Cipher;
 use hmac::{Hmac, Mac};
 use reqwest::header::{HeaderMap, HeaderValue};
@@ -5,20 +7,44 @@ use reqwest::header::{HeaderMap, HeaderValue};
 use crate::Error;
 use sha2::Sha256;
 
-use crate::auth::{Account, AppToken, AuthTokenRequest, AuthTokenRequestBody, GSA_ENDPOINT, RequestHeader};
-use crate::auth::account::{check_error, parse_response};
+use crate::auth::{Account, AppToken, AuthTokenRequest, AuthTokenRequestBody, RequestHeader, SendLogged};
+use crate::auth::account::{check_error, dict_data, dict_string, parse_response};
 
 
 impl Account {
+    /// Returns a cached token for `app_name`, fetching a new one if it is missing or about to expire.
     pub async fn get_app_token(&self, app_name: &str) -> Result<AppToken, Error> {
-        let spd = self.spd.as_ref().unwrap();
-        let dsid = spd.get("adsid").unwrap().as_string().unwrap();
-        let auth_token = spd.get("GsIdmsToken").unwrap().as_string().unwrap();
+        if let Some(token) = self.app_tokens.lock().await.get(app_name).filter(|t| !t.is_expired()) {
+            return Ok(token.clone());
+        }
+
+        // Not locked while fetching, so requests for other apps don't queue up behind it
+        let token = self.fetch_app_token(app_name).await?;
+        self.app_tokens.lock().await.insert(app_name.to_string(), token.clone());
+
+        Ok(token)
+    }
+
+    pub(crate) async fn clear_app_tokens(&self) {
+        self.app_tokens.lock().await.clear();
+    }
 
-        let valid_anisette = self.get_anisette().await;
+    /// Forgets the token for `app_name` once Apple turns it down, so the next
+    /// request fetches a new one instead of failing until it would expire.
+    pub(crate) async fn evict_app_token(&self, app_name: &str) {
+        self.app_tokens.lock().await.remove(app_name);
+    }
 
-        let sk = spd.get("sk").unwrap().as_data().unwrap();
-        let c = spd.get("c").unwrap().as_data().unwrap();
+    #[tracing::instrument(skip(self))]
+    async fn fetch_app_token(&self, app_name: &str) -> Result<AppToken, Error> {
+        let spd = self.logged_in_spd()?;
+        let dsid = dict_string(spd, "adsid", None)?;
+        let auth_token = dict_string(spd, "GsIdmsToken", None)?;
+
+        let valid_anisette = self.get_anisette().await?;
+
+        let sk = dict_data(spd, "sk", None)?;
+        let c = dict_data(spd, "c", None)?;
 
         let checksum = Self::create_checksum(&sk.to_vec(), dsid, app_name);
 
@@ -34,14 +60,14 @@ impl Account {
         );
         gsa_headers.insert(
             "X-MMe-Client-Info",
-            HeaderValue::from_str(&valid_anisette.get_header("x-mme-client-info")?).unwrap(),
+            HeaderValue::from_str(&valid_anisette.get_header("x-mme-client-info")?)?,
         );
 
         let header = RequestHeader {
             version: "1.0.1".to_string(),
         };
         let body = AuthTokenRequestBody {
-            cpd: valid_anisette.to_plist(true, false, false),
+            cpd: valid_anisette.to_plist(true, false, false)?,
             app: vec![app_name.to_string()],
             c: plist::Value::Data(c.to_vec()),
             operation: "apptokens".to_owned(),
@@ -61,22 +87,15 @@ impl Account {
 
         let res = self
             .client
-            .post(GSA_ENDPOINT)
+            .post(self.endpoints.gsa_service())
             .headers(gsa_headers.clone())
             .body(buffer)
-            .send()
+            .send_logged(self.debug_logging)
             .await;
         let res = parse_response(res).await?;
-        let err_check = check_error(&res);
-        if err_check.is_err() {
-            return Err(err_check.err().unwrap());
-        }
+        check_error(&res)?;
 
-        let encrypted_token = res
-            .get("et")
-            .ok_or(Error::Parse)?
-            .as_data()
-            .ok_or(Error::Parse)?;
+        let encrypted_token = res.get_data("et")?;
 
         if encrypted_token.len() < 3 + 16 + 16 {
             return Err(Error::Parse);
@@ -130,8 +149,30 @@ impl Account {
             app_tokens: app_tokens.clone(),
             auth_token: token.to_string(),
             app: app_name.to_string(),
+            expires_at: Some(Self::token_expiry(app_token, SystemTime::now())),
         })
     }
+
+    // `expiry` and `cts` are milliseconds since the epoch, `duration` is in seconds.
+    // Tokens that don't say are kept for a while rather than fetched every request.
+    fn token_expiry(app_token: &plist::Dictionary, now: SystemTime) -> SystemTime {
+        const UNKNOWN_EXPIRY_TTL: Duration = Duration::from_secs(10 * 60);
+
+        let millis = |key: &str| app_token.get(key).and_then(|v| v.as_unsigned_integer());
+
+        if let Some(expiry) = millis("expiry") {
+            return UNIX_EPOCH + Duration::from_millis(expiry);
+        }
+
+        let Some(duration) = millis("duration") else {
+            return now + UNKNOWN_EXPIRY_TTL;
+        };
+        let created = millis("cts")
+            .map(|cts| UNIX_EPOCH + Duration::from_millis(cts))
+            .unwrap_or(now);
+
+        created + Duration::from_secs(duration)
+    }
     
     fn create_checksum(session_key: &Vec<u8>, dsid: &str, app_name: &str) -> Vec<u8> {
         Hmac::<Sha256>::new_from_slice(&session_key)
@@ -144,3 +185,33 @@ impl Account {
             .to_vec()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(entries: &[(&str, u64)]) -> plist::Dictionary {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), plist::Value::Integer((*v).into())))
+            .collect()
+    }
+
+    #[test]
+    fn expiry_comes_from_the_token_or_a_bounded_default() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            Account::token_expiry(&token(&[("expiry", 1_700_000_500_000)]), now),
+            UNIX_EPOCH + Duration::from_secs(1_700_000_500),
+        );
+        assert_eq!(
+            Account::token_expiry(&token(&[("cts", 1_699_999_000_000), ("duration", 3600)]), now),
+            UNIX_EPOCH + Duration::from_secs(1_700_002_600),
+        );
+        assert_eq!(Account::token_expiry(&token(&[("duration", 3600)]), now), now + Duration::from_secs(3600));
+
+        let unknown = Account::token_expiry(&token(&[]), now);
+        assert!(unknown > now && unknown <= now + Duration::from_secs(60 * 60));
+    }
+}
@@ -0,0 +1,523 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use plist::Dictionary;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::Error;
+
+use crate::auth::Account;
+
+const REDACTED: &str = "<redacted>";
+
+// Any key containing one of these is replaced before it is written to disk
+const SECRET_KEY_PARTS: [&str; 13] = [
+    "token",
+    "password",
+    "secret",
+    "privatekey",
+    "dsid",
+    "personid",
+    "email",
+    "phone",
+    "udid",
+    "devicenumber",
+    "serialnumber",
+    "encodedprofile",
+    "certcontent",
+];
+
+// People's and devices' names, matched whole since teams and app IDs have names too
+const SECRET_NAME_KEYS: [&str; 4] = ["firstname", "lastname", "fullname", "devicename"];
+
+// A device calls its own name just "name", next to one of these
+const DEVICE_KEYS: [&str; 2] = ["devicenumber", "udid"];
+
+// Different on every request, so left out of the request digest
+const VOLATILE_KEYS: [&str; 1] = ["requestId"];
+
+/// What happens to developer-service requests made through an `Account`.
+#[derive(Debug, Clone, Default)]
+pub enum Traffic {
+    /// Requests go to Apple as usual.
+    #[default]
+    Live,
+    /// Requests go to Apple, every exchange is also saved as a fixture.
+    Record(Arc<TrafficRecorder>),
+    /// Requests never leave the machine, responses come from saved fixtures.
+    Replay(Arc<TrafficReplayer>),
+}
+
+/// One saved request and response, with secrets redacted.
+///
+/// QH bodies are kept as XML plists so the fixture stays readable. A response
+/// that didn't parse is kept as the raw body, with `error` saying why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub request: Option<Value>,
+    /// Digest of the redacted request body, replays only match a request with
+    /// the same one. Fixtures without it match any body.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_digest: Option<String>,
+    pub response: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct TrafficRecorder {
+    dir: PathBuf,
+    next: AtomicUsize,
+}
+
+impl TrafficRecorder {
+    fn new(dir: PathBuf) -> Result<Self, Error> {
+        fs::create_dir_all(&dir)?;
+
+        // Appends to what's there so a trace can span several runs
+        let next = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .count();
+
+        Ok(Self { dir, next: AtomicUsize::new(next) })
+    }
+
+    fn record(&self, mut exchange: Exchange) -> Result<(), Error> {
+        if let Some(request) = exchange.request.as_mut() {
+            redact_json(request);
+        }
+        redact_json(&mut exchange.response);
+
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        let name = exchange.path.rsplit('/').next().unwrap_or_default();
+        let file_name = format!("{:04}-{}-{}.json", index, exchange.method, sanitize_file_name(name));
+
+        fs::write(self.dir.join(file_name), serde_json::to_vec_pretty(&exchange)?)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct TrafficReplayer {
+    exchanges: Mutex<Vec<Exchange>>,
+}
+
+impl TrafficReplayer {
+    fn load(dir: &Path) -> Result<Self, Error> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+
+        let exchanges = paths
+            .iter()
+            .map(|path| {
+                serde_json::from_slice(&fs::read(path)?)
+                    .map_err(|e| Error::Traffic(format!("{}: {}", path.display(), e)))
+            })
+            .collect::<Result<Vec<Exchange>, Error>>()?;
+
+        Ok(Self { exchanges: Mutex::new(exchanges) })
+    }
+
+    // The oldest unused exchange for the same request, so repeated calls play back in order
+    fn take(&self, method: &str, path: &str, digest: Option<&str>) -> Result<Value, Error> {
+        let mut exchanges = self.exchanges.lock().unwrap();
+
+        let index = exchanges
+            .iter()
+            .position(|e| {
+                e.method == method
+                    && e.path == path
+                    && e.request_digest.as_deref().is_none_or(|recorded| Some(recorded) == digest)
+            })
+            .ok_or_else(|| Error::Traffic(format!("no recorded response left for {} {}", method, path)))?;
+
+        let exchange = exchanges.remove(index);
+        match exchange.error {
+            Some(error) => Err(Error::Traffic(format!(
+                "recorded {} {} failed (HTTP {}): {}",
+                method,
+                path,
+                exchange.status.map(|s| s.to_string()).unwrap_or_else(|| "?".to_string()),
+                error
+            ))),
+            None => Ok(exchange.response),
+        }
+    }
+}
+
+impl Account {
+    /// Saves every developer-service request and response to `dir` from now on.
+    pub fn record_traffic(&mut self, dir: impl Into<PathBuf>) -> Result<(), Error> {
+        self.traffic = Traffic::Record(Arc::new(TrafficRecorder::new(dir.into())?));
+        Ok(())
+    }
+
+    /// Answers developer-service requests from the fixtures in `dir` instead of Apple.
+    ///
+    /// No login is needed, an account made with a `MockProvider` will do.
+    pub fn replay_traffic(&mut self, dir: impl AsRef<Path>) -> Result<(), Error> {
+        self.traffic = Traffic::Replay(Arc::new(TrafficReplayer::load(dir.as_ref())?));
+        Ok(())
+    }
+
+    pub fn traffic(&self) -> &Traffic {
+        &self.traffic
+    }
+
+    // Fixtures shouldn't care which host they were recorded against
+    fn traffic_path(&self, url: &str) -> String {
+        url.strip_prefix(&self.endpoints().developer_services)
+            .unwrap_or(url)
+            .to_string()
+    }
+
+    pub(crate) fn replay_qh(
+        &self,
+        method: &str,
+        url: &str,
+        request: Option<&Dictionary>,
+    ) -> Option<Result<Dictionary, Error>> {
+        let Traffic::Replay(replayer) = &self.traffic else {
+            return None;
+        };
+
+        let digest = request.map(qh_digest).transpose();
+        Some(digest.and_then(|digest| replayer.take(method, &self.traffic_path(url), digest.as_deref())).and_then(|response| {
+            let xml = response
+                .as_str()
+                .ok_or_else(|| Error::Traffic("QH response is not a plist".to_string()))?;
+            Ok(plist::from_bytes(xml.as_bytes())?)
+        }))
+    }
+
+    pub(crate) fn replay_v1(&self, method: &str, url: &str, request: Option<&Value>) -> Option<Result<Value, Error>> {
+        let Traffic::Replay(replayer) = &self.traffic else {
+            return None;
+        };
+
+        let digest = request.map(v1_digest).transpose();
+        Some(digest.and_then(|digest| replayer.take(method, &self.traffic_path(url), digest.as_deref())))
+    }
+
+    // Recording is best effort, a fixture that can't be written never fails the request
+
+    pub(crate) fn record_qh(
+        &self,
+        method: &str,
+        url: &str,
+        request: Option<&Dictionary>,
+        status: u16,
+        body: &str,
+        response: Result<&Dictionary, &Error>,
+    ) {
+        let Traffic::Record(recorder) = &self.traffic else {
+            return;
+        };
+
+        let to_xml = |dict: &Dictionary| -> Result<Value, Error> {
+            let mut dict = dict.clone();
+            redact_plist(&mut dict);
+
+            let mut buf = Vec::new();
+            plist::to_writer_xml(&mut buf, &dict)?;
+            Ok(Value::String(String::from_utf8_lossy(&buf).into_owned()))
+        };
+
+        let result = request.map(to_xml).transpose().and_then(|recorded| {
+            let request_digest = request.map(qh_digest).transpose()?;
+            let (response, error) = match response {
+                Ok(dict) => (to_xml(dict)?, None),
+                Err(e) => (Value::String(body.to_string()), Some(e.to_string())),
+            };
+
+            recorder.record(Exchange {
+                method: method.to_string(),
+                path: self.traffic_path(url),
+                status: Some(status),
+                request: recorded,
+                request_digest,
+                response,
+                error,
+            })
+        });
+
+        warn_if_unrecorded(result, method, url);
+    }
+
+    pub(crate) fn record_v1(
+        &self,
+        method: &str,
+        url: &str,
+        request: Option<&Value>,
+        status: u16,
+        body: &str,
+        response: Result<&Value, &Error>,
+    ) {
+        let Traffic::Record(recorder) = &self.traffic else {
+            return;
+        };
+
+        let (response, error) = match response {
+            Ok(value) => (value.clone(), None),
+            Err(e) => (Value::String(body.to_string()), Some(e.to_string())),
+        };
+
+        let result = request.map(v1_digest).transpose().and_then(|request_digest| {
+            recorder.record(Exchange {
+                method: method.to_string(),
+                path: self.traffic_path(url),
+                status: Some(status),
+                request: request.cloned(),
+                request_digest,
+                response,
+                error,
+            })
+        });
+
+        warn_if_unrecorded(result, method, url);
+    }
+}
+
+fn warn_if_unrecorded(result: Result<(), Error>, method: &str, url: &str) {
+    if let Err(e) = result {
+        tracing::warn!(method, url, error = %e, "failed to record exchange");
+    }
+}
+
+fn is_secret_key(key: &str, in_device: bool) -> bool {
+    let key = key.to_lowercase();
+    SECRET_KEY_PARTS.iter().any(|part| key.contains(part))
+        || SECRET_NAME_KEYS.contains(&key.as_str())
+        || (in_device && key == "name")
+}
+
+fn is_device<'a>(mut keys: impl Iterator<Item = &'a String>) -> bool {
+    keys.any(|key| DEVICE_KEYS.contains(&key.to_lowercase().as_str()))
+}
+
+// Hashed after redacting, so the digest holds nothing the fixture doesn't and
+// a replay under another account still matches
+
+fn qh_digest(request: &Dictionary) -> Result<String, Error> {
+    let mut request = request.clone();
+    for key in VOLATILE_KEYS {
+        request.remove(key);
+    }
+    redact_plist(&mut request);
+
+    let mut buf = Vec::new();
+    plist::to_writer_xml(&mut buf, &request)?;
+    Ok(hex::encode(Sha256::digest(&buf)))
+}
+
+fn v1_digest(request: &Value) -> Result<String, Error> {
+    let mut request = request.clone();
+    if let Value::Object(map) = &mut request {
+        for key in VOLATILE_KEYS {
+            map.remove(key);
+        }
+    }
+    redact_json(&mut request);
+
+    Ok(hex::encode(Sha256::digest(serde_json::to_vec(&request)?)))
+}
+
+// Secrets are replaced with a value of the same type, so a fixture still
+// deserializes into the same structs when it is replayed
+
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            let in_device = is_device(map.keys());
+            for (key, value) in map.iter_mut() {
+                if is_secret_key(key, in_device) {
+                    redact_json_secret(value);
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+fn redact_json_secret(value: &mut Value) {
+    match value {
+        Value::String(_) => *value = Value::String(REDACTED.to_string()),
+        Value::Number(_) => *value = Value::from(0),
+        Value::Object(map) => map.values_mut().for_each(redact_json_secret),
+        Value::Array(values) => values.iter_mut().for_each(redact_json_secret),
+        Value::Bool(_) | Value::Null => {}
+    }
+}
+
+fn redact_plist(dict: &mut Dictionary) {
+    let in_device = is_device(dict.keys());
+    for (key, value) in dict.iter_mut() {
+        if is_secret_key(key, in_device) {
+            redact_plist_secret(value);
+        } else {
+            redact_plist_value(value);
+        }
+    }
+}
+
+fn redact_plist_value(value: &mut plist::Value) {
+    match value {
+        plist::Value::Dictionary(dict) => redact_plist(dict),
+        plist::Value::Array(values) => values.iter_mut().for_each(redact_plist_value),
+        _ => {}
+    }
+}
+
+fn redact_plist_secret(value: &mut plist::Value) {
+    match value {
+        plist::Value::Dictionary(dict) => {
+            for (_, value) in dict.iter_mut() {
+                redact_plist_secret(value);
+            }
+        }
+        plist::Value::Array(values) => values.iter_mut().for_each(redact_plist_secret),
+        plist::Value::Integer(_) => *value = plist::Value::Integer(0.into()),
+        plist::Value::Real(_) => *value = plist::Value::Real(0.0),
+        plist::Value::Data(_) => *value = plist::Value::Data(REDACTED.as_bytes().to_vec()),
+        plist::Value::String(_) => *value = plist::Value::String(REDACTED.to_string()),
+        _ => {}
+    }
+}
+
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_and_person_details_are_redacted() {
+        let mut dict = Dictionary::new();
+        dict.insert("deviceNumber".to_string(), plist::Value::String("00008030-001A2B3C4D5E6F70".to_string()));
+        dict.insert("serialNumber".to_string(), plist::Value::String("F2LXK0ABCDEF".to_string()));
+        dict.insert("name".to_string(), plist::Value::String("Someone's iPhone".to_string()));
+        dict.insert("personId".to_string(), plist::Value::Integer(1234567890.into()));
+        dict.insert("encodedProfile".to_string(), plist::Value::Data(b"signed profile".to_vec()));
+        dict.insert("certContent".to_string(), plist::Value::Data(b"certificate".to_vec()));
+        dict.insert("teamId".to_string(), plist::Value::String("TEAM123456".to_string()));
+
+        redact_plist(&mut dict);
+
+        let get = |key: &str| dict.get(key).unwrap().clone();
+        assert_eq!(get("deviceNumber"), plist::Value::String(REDACTED.to_string()));
+        assert_eq!(get("serialNumber"), plist::Value::String(REDACTED.to_string()));
+        assert_eq!(get("name"), plist::Value::String(REDACTED.to_string()));
+        assert_eq!(get("teamId"), plist::Value::String("TEAM123456".to_string()));
+        // Same types as before so replays still deserialize
+        assert_eq!(get("personId"), plist::Value::Integer(0.into()));
+        assert_eq!(get("encodedProfile"), plist::Value::Data(REDACTED.as_bytes().to_vec()));
+        assert_eq!(get("certContent"), plist::Value::Data(REDACTED.as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn only_people_and_devices_lose_their_names() {
+        let mut value = serde_json::json!({
+            "team": { "name": "Tester Team", "teamId": "TEAM123456" },
+            "appId": { "name": "Example App", "identifier": "com.example.app" },
+            "member": { "firstName": "Test", "lastName": "User", "deviceName": "Someone's Mac" },
+        });
+
+        redact_json(&mut value);
+
+        assert_eq!(value["team"]["name"], "Tester Team");
+        assert_eq!(value["appId"]["name"], "Example App");
+        assert_eq!(value["member"]["firstName"], REDACTED);
+        assert_eq!(value["member"]["lastName"], REDACTED);
+        assert_eq!(value["member"]["deviceName"], REDACTED);
+    }
+
+    #[test]
+    fn digests_follow_the_body_but_not_the_request_id() {
+        let request = |request_id: &str, device: &str| {
+            let mut dict = Dictionary::new();
+            dict.insert("requestId".to_string(), plist::Value::String(request_id.to_string()));
+            dict.insert("teamId".to_string(), plist::Value::String("TEAM123456".to_string()));
+            dict.insert("deviceId".to_string(), plist::Value::String(device.to_string()));
+            dict
+        };
+
+        let digest = qh_digest(&request("A", "DEVICE1")).unwrap();
+        assert_eq!(digest, qh_digest(&request("B", "DEVICE1")).unwrap());
+        assert_ne!(digest, qh_digest(&request("A", "DEVICE2")).unwrap());
+
+        let json = serde_json::json!({ "data": { "type": "devices", "attributes": { "udid": "1234" } } });
+        let other = serde_json::json!({ "data": { "type": "bundleIds" } });
+        assert_ne!(v1_digest(&json).unwrap(), v1_digest(&other).unwrap());
+    }
+
+    #[test]
+    fn replays_match_on_the_request_body() {
+        let exchange = |digest: Option<&str>, response: &str| Exchange {
+            method: "POST".to_string(),
+            path: "/deleteDevice.action".to_string(),
+            status: Some(200),
+            request: None,
+            request_digest: digest.map(str::to_string),
+            response: Value::String(response.to_string()),
+            error: None,
+        };
+        let replayer = TrafficReplayer {
+            exchanges: Mutex::new(vec![exchange(Some("first"), "1"), exchange(Some("second"), "2"), exchange(None, "any")]),
+        };
+
+        assert_eq!(replayer.take("POST", "/deleteDevice.action", Some("second")).unwrap(), "2");
+        assert_eq!(replayer.take("POST", "/deleteDevice.action", Some("third")).unwrap(), "any");
+        assert!(matches!(replayer.take("POST", "/deleteDevice.action", Some("second")), Err(Error::Traffic(_))));
+        assert_eq!(replayer.take("POST", "/deleteDevice.action", Some("first")).unwrap(), "1");
+    }
+
+    #[test]
+    fn nested_json_secrets_keep_their_types() {
+        let mut value = serde_json::json!({
+            "data": [{
+                "id": "DEVICE1",
+                "attributes": {
+                    "udid": "00008030-001A2B3C4D5E6F70",
+                    "name": "Someone's iPhone",
+                    "personId": 1234567890,
+                    "enabled": true,
+                },
+            }],
+        });
+
+        redact_json(&mut value);
+
+        assert_eq!(value, serde_json::json!({
+            "data": [{
+                "id": "DEVICE1",
+                "attributes": {
+                    "udid": REDACTED,
+                    "name": REDACTED,
+                    "personId": 0,
+                    "enabled": true,
+                },
+            }],
+        }));
+    }
+}
@@ -1,22 +1,27 @@
-use std::str::FromStr;
+use std::time::SystemTime;
 
 use base64::{Engine, engine::general_purpose};
 use crate::Error;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use reqwest::{Response, StatusCode};
 
-use crate::auth::{Account, AuthenticationExtras, LoginState, PhoneNumber, VerifyBody, VerifyCode};
+use crate::auth::account::{GsaResponse, INCORRECT_VERIFICATION_CODE, dict_string};
+use crate::auth::{Account, AuthenticationExtras, SendLogged, LoginState, PhoneNumber, VerifyBody, VerifyCode};
 
 impl Account {
+    #[tracing::instrument(skip_all)]
     pub async fn send_2fa_to_devices(&self) -> Result<LoginState, Error> {
-        let headers = self.build_2fa_headers(false).await;
+        let headers = self.build_2fa_headers(false).await?;
         
         let res = self
             .client
-            .get("https://gsa.apple.com/auth/verify/trusteddevice")
+            .get(self.endpoints.gsa_url("/auth/verify/trusteddevice"))
             .headers(headers)
-            .send()
+            .send_logged(self.debug_logging)
             .await?;
 
+        Self::check_2fa_rate_limit(&res)?;
+
         let status_code = res.status();
 
         if !status_code.is_success() {
@@ -26,8 +31,9 @@ impl Account {
         return Ok(LoginState::Needs2FAVerification);
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn send_sms_2fa_to_devices(&self, phone_id: u32) -> Result<LoginState, Error> {
-        let headers = self.build_2fa_headers(true).await;
+        let headers = self.build_2fa_headers(true).await?;
         
         let body = VerifyBody {
             phone_number: PhoneNumber { id: phone_id },
@@ -37,12 +43,14 @@ impl Account {
 
         let res = self
             .client
-            .put("https://gsa.apple.com/auth/verify/phone")
+            .put(self.endpoints.gsa_url("/auth/verify/phone"))
             .headers(headers)
             .json(&body)
-            .send()
+            .send_logged(self.debug_logging)
             .await?;
 
+        Self::check_2fa_rate_limit(&res)?;
+
         let status_code = res.status();
 
         if !status_code.is_success() {
@@ -53,99 +61,158 @@ impl Account {
     }
 
     pub async fn get_auth_extras(&self) -> Result<AuthenticationExtras, Error> {
-        let headers = self.build_2fa_headers(true);
+        let headers = self.build_2fa_headers(true).await?;
 
         let req = self
             .client
-            .get("https://gsa.apple.com/auth")
-            .headers(headers.await)
+            .get(self.endpoints.gsa_url("/auth"))
+            .headers(headers)
             .header("Accept", "application/json")
-            .send()
+            .send_logged(self.debug_logging)
             .await?;
         let status = req.status().as_u16();
         let mut new_state = req.json::<AuthenticationExtras>().await?;
         if status == 201 {
-            new_state.new_state = Some(LoginState::NeedsSMS2FAVerification(VerifyBody {
-                phone_number: PhoneNumber {
-                    id: new_state.trusted_phone_numbers.first().unwrap().id,
-                },
-                mode: "sms".to_string(),
-                security_code: None,
-            }));
+            new_state.new_state = new_state.trusted_phone_numbers.first().map(|phone| {
+                LoginState::NeedsSMS2FAVerification(VerifyBody {
+                    phone_number: PhoneNumber { id: phone.id },
+                    mode: "sms".to_string(),
+                    security_code: None,
+                })
+            });
         }
 
         Ok(new_state)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn verify_2fa(&self, code: String) -> Result<LoginState, Error> {
-        let headers = self.build_2fa_headers(false);
-        // println!("Recieved code: {}", code);
+        let headers = self.build_2fa_headers(false).await?;
         let res = self
             .client
-            .get("https://gsa.apple.com/grandslam/GsService2/validate")
-            .headers(headers.await)
+            .get(self.endpoints.gsa_url("/grandslam/GsService2/validate"))
+            .headers(headers)
             .header(
-                HeaderName::from_str("security-code").unwrap(),
-                HeaderValue::from_str(&code).unwrap(),
+                HeaderName::from_static("security-code"),
+                HeaderValue::from_str(&code)?,
             )
-            .send()
+            .send_logged(self.debug_logging)
             .await?;
 
-        let res: plist::Dictionary = plist::from_bytes(res.text().await?.as_bytes())?;
+        Self::check_2fa_rate_limit(&res)?;
 
-        super::check_error(&res)?;
+        let status = res.status().as_u16();
+        let body: plist::Dictionary = plist::from_bytes(res.text().await?.as_bytes()).map_err(|e| {
+            Error::UnexpectedResponse { reason: format!("body is not a plist: {}", e), status: Some(status) }
+        })?;
+
+        super::check_error(&GsaResponse { status, body })?;
 
         Ok(LoginState::NeedsLogin)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn verify_sms_2fa(
         &self,
         code: String,
         mut body: VerifyBody,
     ) -> Result<LoginState, Error> {
-        let headers = self.build_2fa_headers(true).await;
-        // println!("Recieved code: {}", code);
+        let headers = self.build_2fa_headers(true).await?;
 
         body.security_code = Some(VerifyCode { code });
 
         let res = self
             .client
-            .post("https://gsa.apple.com/auth/verify/phone/securitycode")
+            .post(self.endpoints.gsa_url("/auth/verify/phone/securitycode"))
             .headers(headers)
             .json(&body)
-            .send()
+            .send_logged(self.debug_logging)
             .await?;
 
-        let status_code = res.status();
+        Self::check_2fa_rate_limit(&res)?;
 
-        // TODO: 423 http code may occur, in this case we to ask for sending
-        // last code sent (unlikely it would even work), or try again later
-        if !status_code.is_success() {
+        let status = res.status();
+        if status.is_success() {
+            return Ok(LoginState::NeedsLogin);
+        }
+
+        let body = res.text().await?;
+        if status == StatusCode::BAD_REQUEST && Self::is_incorrect_sms_code(&body) {
             return Err(Error::Bad2faCode);
         }
 
-        Ok(LoginState::NeedsLogin)
+        tracing::warn!(status = status.as_u16(), "SMS code verification failed");
+        Err(Error::UnexpectedResponse {
+            reason: "SMS code verification failed".to_string(),
+            status: Some(status.as_u16()),
+        })
+    }
+
+    // A wrong code comes back as `{"service_errors": [{"code": "-21669", ...}]}`,
+    // anything else going wrong shouldn't send the user off retyping the code
+    fn is_incorrect_sms_code(body: &str) -> bool {
+        let Ok(body) = serde_json::from_str::<serde_json::Value>(body) else {
+            return false;
+        };
+
+        body.get("service_errors")
+            .and_then(|errors| errors.as_array())
+            .is_some_and(|errors| {
+                errors.iter().any(|error| {
+                    let code = error.get("code");
+                    let code = code
+                        .and_then(|c| c.as_str())
+                        .and_then(|c| c.parse::<i64>().ok())
+                        .or_else(|| code.and_then(|c| c.as_i64()));
+
+                    code == Some(INCORRECT_VERIFICATION_CODE)
+                })
+            })
     }
     
-    pub async fn build_2fa_headers(&self, sms: bool) -> HeaderMap {
-        let spd = self.spd.as_ref().unwrap();
-        let dsid = spd.get("adsid").unwrap().as_string().unwrap();
-        let token = spd.get("GsIdmsToken").unwrap().as_string().unwrap();
+    // 423 means too many wrong codes, 429 too many codes sent, both can carry a Retry-After
+    fn check_2fa_rate_limit(res: &Response) -> Result<(), Error> {
+        let retry_after = res
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| Self::parse_retry_after(v, SystemTime::now()));
+
+        match res.status() {
+            StatusCode::LOCKED => Err(Error::TwoFactorLocked(retry_after)),
+            StatusCode::TOO_MANY_REQUESTS => Err(Error::TwoFactorRateLimited(retry_after)),
+            _ => Ok(()),
+        }
+    }
+
+    // Either delta-seconds or an HTTP date, a date already past means retrying now
+    fn parse_retry_after(value: &str, now: SystemTime) -> Option<u64> {
+        let value = value.trim();
+
+        if let Ok(seconds) = value.parse::<u64>() {
+            return Some(seconds);
+        }
+
+        let at = httpdate::parse_http_date(value).ok()?;
+        Some(at.duration_since(now).map(|wait| wait.as_secs()).unwrap_or(0))
+    }
+
+    pub async fn build_2fa_headers(&self, sms: bool) -> Result<HeaderMap, Error> {
+        let spd = self.logged_in_spd()?;
+        let dsid = dict_string(spd, "adsid", None)?;
+        let token = dict_string(spd, "GsIdmsToken", None)?;
 
         let identity_token = general_purpose::STANDARD.encode(format!("{}:{}", dsid, token));
 
-        let valid_anisette = self.get_anisette().await;
+        let valid_anisette = self.get_anisette().await?;
 
         let mut headers = HeaderMap::new();
-        valid_anisette
-            .generate_headers(false, true, true)
-            .iter()
-            .for_each(|(k, v)| {
-                headers.append(
-                    HeaderName::from_bytes(k.as_bytes()).unwrap(),
-                    HeaderValue::from_str(v).unwrap(),
-                );
-            });
+        for (k, v) in valid_anisette.generate_headers(false, true, true)? {
+            headers.append(
+                HeaderName::from_bytes(k.as_bytes())?,
+                HeaderValue::from_str(&v)?,
+            );
+        }
 
         if !sms {
             headers.insert(
@@ -170,14 +237,47 @@ impl Account {
         headers.insert("Accept-Language", HeaderValue::from_str("en-us").unwrap());
         headers.append(
             "X-Apple-Identity-Token",
-            HeaderValue::from_str(&identity_token).unwrap(),
+            HeaderValue::from_str(&identity_token)?,
         );
 
         headers.insert(
             "Loc",
-            HeaderValue::from_str(&valid_anisette.get_header("x-apple-locale").unwrap()).unwrap(),
+            HeaderValue::from_str(&valid_anisette.get_header("x-apple-locale")?)?,
         );
 
-        headers
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_incorrect_code_error_is_a_bad_code() {
+        let incorrect = r#"{"service_errors":[{"code":"-21669","title":"Incorrect Verification Code","message":"Incorrect verification code."}],"hasError":true}"#;
+        assert!(Account::is_incorrect_sms_code(incorrect));
+        assert!(Account::is_incorrect_sms_code(r#"{"service_errors":[{"code":-21669}]}"#));
+
+        for body in [
+            r#"{"service_errors":[{"code":"-22406","message":"Your session has expired."}]}"#,
+            r#"{"service_errors":[]}"#,
+            r#"{"hasError":true}"#,
+            "<html>Bad Request</html>",
+            "",
+        ] {
+            assert!(!Account::is_incorrect_sms_code(body), "{}", body);
+        }
+    }
+
+    #[test]
+    fn retry_after_is_seconds_or_a_date() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+
+        assert_eq!(Account::parse_retry_after("120", now), Some(120));
+        assert_eq!(Account::parse_retry_after(" 30 ", now), Some(30));
+        assert_eq!(Account::parse_retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now), Some(120));
+        assert_eq!(Account::parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now), Some(0));
+        assert_eq!(Account::parse_retry_after("soon", now), None);
     }
 }
@@ -1,41 +1,49 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 use std::time::SystemTime;
 
-use omnisette::{AnisetteConfiguration, AnisetteHeaders};
+use omnisette::AnisetteConfiguration;
 
 use crate::Error;
 
+use crate::auth::anisette_provider::{AnisetteProvider, OmnisetteProvider};
+
+// Headers that stay the same for as long as the provisioned device does
+const DEVICE_IDENTITY_HEADERS: [&str; 2] = ["x-mme-device-id", "x-apple-i-md-lu"];
+
 #[derive(Debug, Clone)]
 pub struct AnisetteData {
     pub base_headers: HashMap<String, String>,
     pub generated_at: SystemTime,
-    pub config: AnisetteConfiguration,
+    pub provider: Arc<dyn AnisetteProvider>,
 }
 
 impl AnisetteData {
     pub async fn new(config: AnisetteConfiguration) -> Result<Self, Error> {
-        let mut b = AnisetteHeaders::get_anisette_headers_provider(config.clone())?;
-        let base_headers = b.provider.get_authentication_headers().await?;
+        Self::with_provider(Arc::new(OmnisetteProvider::new(config))).await
+    }
+
+    pub async fn with_provider(provider: Arc<dyn AnisetteProvider>) -> Result<Self, Error> {
+        let base_headers = provider.get_headers().await?;
 
         Ok(AnisetteData {
             base_headers,
             generated_at: SystemTime::now(),
-            config
+            provider
         })
     }
 
+    // A clock that went backwards counts as stale, it's safer to regenerate
     pub fn needs_refresh(&self) -> bool {
-        let elapsed = self.generated_at.elapsed().unwrap();
-        elapsed.as_secs() > 60
+        !self.generated_at.elapsed().is_ok_and(|elapsed| elapsed.as_secs() <= 60)
     }
 
     pub fn is_valid(&self) -> bool {
-        let elapsed = self.generated_at.elapsed().unwrap();
-        elapsed.as_secs() < 90
+        self.generated_at.elapsed().is_ok_and(|elapsed| elapsed.as_secs() < 90)
     }
 
     pub async fn refresh(&self) -> Result<Self, crate::Error> {
-        Self::new(self.config.clone()).await
+        Self::with_provider(self.provider.clone()).await
     }
 
     pub fn generate_headers(
@@ -43,9 +51,9 @@ impl AnisetteData {
         cpd: bool,
         client_info: bool,
         app_info: bool,
-    ) -> HashMap<String, String> {
+    ) -> Result<HashMap<String, String>, Error> {
         if !self.is_valid() {
-            panic!("Invalid data!")
+            return Err(Error::InvalidAnisette("headers have expired".to_string()));
         }
         
         let mut headers = self.base_headers.clone();
@@ -56,13 +64,17 @@ impl AnisetteData {
                 Some(v) => {
                     let temp = v.as_str();
 
-                    temp.replace(
-                        temp.split('<').nth(3).unwrap().split('>').nth(0).unwrap(),
-                        "com.apple.AuthKit/1 (com.apple.dt.Xcode/3594.4.19)",
-                    )
+                    // <device model> <OS;build> <client> are the three parts, swap the client
+                    let client = temp
+                        .split('<')
+                        .nth(3)
+                        .and_then(|s| s.split('>').next())
+                        .ok_or_else(|| Error::InvalidAnisette(format!("unexpected X-Mme-Client-Info {:?}", temp)))?;
+
+                    temp.replace(client, "com.apple.AuthKit/1 (com.apple.dt.Xcode/3594.4.19)")
                 }
                 None => {
-                    return headers;
+                    return Ok(headers);
                 }
             };
             headers.insert("X-Mme-Client-Info".to_owned(), client_info.to_owned());
@@ -85,21 +97,21 @@ impl AnisetteData {
             headers.insert("svct".to_owned(), "iCloud".to_owned());
         }
 
-        headers
+        Ok(headers)
     }
 
-    pub fn to_plist(&self, cpd: bool, client_info: bool, app_info: bool) -> plist::Dictionary {
+    pub fn to_plist(&self, cpd: bool, client_info: bool, app_info: bool) -> Result<plist::Dictionary, Error> {
         let mut plist = plist::Dictionary::new();
-        for (key, value) in self.generate_headers(cpd, client_info, app_info).iter() {
+        for (key, value) in self.generate_headers(cpd, client_info, app_info)?.iter() {
             plist.insert(key.to_owned(), plist::Value::String(value.to_owned()));
         }
 
-        plist
+        Ok(plist)
     }
 
     pub fn get_header(&self, header: &str) -> Result<String, Error> {
         let headers = self
-            .generate_headers(true, true, true)
+            .generate_headers(true, true, true)?
             .iter()
             .map(|(k, v)| (k.to_lowercase(), v.to_lowercase()))
             .collect::<HashMap<String, String>>();
@@ -109,4 +121,85 @@ impl AnisetteData {
             None => Err(Error::DeveloperSessionRequestFailed),
         }
     }
+
+    pub fn device_identity(&self) -> BTreeMap<String, String> {
+        self.base_headers
+            .iter()
+            .map(|(k, v)| (k.to_lowercase(), v.clone()))
+            .filter(|(k, _)| DEVICE_IDENTITY_HEADERS.contains(&k.as_str()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::auth::anisette_provider::MockProvider;
+
+    fn anisette(headers: &[(&str, &str)], age: Duration) -> AnisetteData {
+        AnisetteData {
+            base_headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            generated_at: SystemTime::now() - age,
+            provider: Arc::new(MockProvider::new("test")),
+        }
+    }
+
+    #[tokio::test]
+    async fn fresh_mock_headers_are_usable() {
+        let data = AnisetteData::with_provider(Arc::new(MockProvider::new("test"))).await.unwrap();
+
+        let headers = data.generate_headers(true, true, true).unwrap();
+        assert!(headers["X-Mme-Client-Info"].contains("com.apple.AuthKit/1 (com.apple.dt.Xcode/3594.4.19)"));
+        assert_eq!(headers["X-Apple-App-Info"], "com.apple.gs.xcode.auth");
+        assert!(data.to_plist(true, false, false).unwrap().contains_key("bootstrap"));
+        assert_eq!(data.device_identity().len(), 2);
+    }
+
+    #[test]
+    fn stale_headers_are_rejected() {
+        let data = anisette(&[("X-Apple-I-MD", "md")], Duration::from_secs(120));
+
+        assert!(!data.is_valid());
+        assert!(data.needs_refresh());
+        assert!(matches!(data.generate_headers(true, true, true), Err(Error::InvalidAnisette(_))));
+        assert!(matches!(data.to_plist(true, false, false), Err(Error::InvalidAnisette(_))));
+        assert!(matches!(data.get_header("x-apple-i-md"), Err(Error::InvalidAnisette(_))));
+    }
+
+    #[test]
+    fn headers_from_the_future_are_rejected() {
+        let data = AnisetteData {
+            generated_at: SystemTime::now() + Duration::from_secs(3600),
+            ..anisette(&[], Duration::ZERO)
+        };
+
+        assert!(!data.is_valid());
+        assert!(matches!(data.generate_headers(false, false, false), Err(Error::InvalidAnisette(_))));
+    }
+
+    #[test]
+    fn malformed_client_info_is_an_error() {
+        for client_info in ["", "no brackets at all", "<only> <two>"] {
+            let data = anisette(&[("X-Mme-Client-Info", client_info)], Duration::ZERO);
+
+            assert!(
+                matches!(data.generate_headers(false, true, false), Err(Error::InvalidAnisette(_))),
+                "{:?}",
+                client_info
+            );
+            // Left alone when it isn't asked for
+            assert!(data.generate_headers(false, false, false).is_ok());
+        }
+    }
+
+    #[test]
+    fn missing_headers_never_panic() {
+        let data = anisette(&[], Duration::ZERO);
+
+        assert!(data.generate_headers(true, true, true).is_ok());
+        assert!(data.device_identity().is_empty());
+        assert!(data.get_header("x-mme-client-info").is_err());
+    }
 }
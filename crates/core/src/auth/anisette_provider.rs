@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use base64::{Engine, engine::general_purpose};
+use futures::future::BoxFuture;
+use omnisette::{AnisetteConfiguration, AnisetteHeaders};
+use sha2::{Digest, Sha256};
+
+use crate::Error;
+
+/// A source of anisette headers.
+///
+/// `AnisetteData` asks its provider for a fresh set of headers whenever the
+/// current ones get old, implement this to get them from somewhere other
+/// than omnisette.
+pub trait AnisetteProvider: Send + Sync + fmt::Debug {
+    fn get_headers(&self) -> BoxFuture<'_, Result<HashMap<String, String>, Error>>;
+}
+
+// MARK: - Omnisette
+
+/// Gets headers through omnisette, local provisioning or a remote v3 server
+/// depending on the configuration.
+#[derive(Debug, Clone)]
+pub struct OmnisetteProvider {
+    config: AnisetteConfiguration,
+}
+
+impl OmnisetteProvider {
+    pub fn new(config: AnisetteConfiguration) -> Self {
+        Self { config }
+    }
+}
+
+impl AnisetteProvider for OmnisetteProvider {
+    fn get_headers(&self) -> BoxFuture<'_, Result<HashMap<String, String>, Error>> {
+        Box::pin(async move {
+            let mut b = AnisetteHeaders::get_anisette_headers_provider(self.config.clone())?;
+            Ok(b.provider.get_authentication_headers().await?)
+        })
+    }
+}
+
+// MARK: - Static
+
+/// Always hands back the same headers, e.g. ones recorded from a real device.
+#[derive(Debug, Clone)]
+pub struct StaticProvider {
+    headers: HashMap<String, String>,
+}
+
+impl StaticProvider {
+    pub fn new(headers: HashMap<String, String>) -> Self {
+        Self { headers }
+    }
+}
+
+impl AnisetteProvider for StaticProvider {
+    fn get_headers(&self) -> BoxFuture<'_, Result<HashMap<String, String>, Error>> {
+        Box::pin(async move { Ok(self.headers.clone()) })
+    }
+}
+
+// MARK: - Mock
+
+/// Makes up well-formed headers derived from a seed, without touching the
+/// network. Apple won't accept them, but everything up to the request will
+/// behave as it does with real ones, which is what offline tests need.
+#[derive(Debug, Clone)]
+pub struct MockProvider {
+    seed: String,
+}
+
+impl MockProvider {
+    // Fixed so the same seed always gives the exact same headers
+    const CLIENT_TIME: &'static str = "2024-01-01T00:00:00Z";
+
+    pub fn new(seed: impl Into<String>) -> Self {
+        Self { seed: seed.into() }
+    }
+
+    fn derive(&self, label: &str) -> [u8; 32] {
+        Sha256::new()
+            .chain_update(self.seed.as_bytes())
+            .chain_update(label.as_bytes())
+            .finalize()
+            .into()
+    }
+
+    fn device_id(&self) -> String {
+        let bytes = self.derive("device-id");
+        let hex = hex::encode_upper(&bytes[..16]);
+        format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+    }
+}
+
+impl AnisetteProvider for MockProvider {
+    fn get_headers(&self) -> BoxFuture<'_, Result<HashMap<String, String>, Error>> {
+        Box::pin(async move {
+            let b64 = |label: &str| general_purpose::STANDARD.encode(self.derive(label));
+
+            let headers = [
+                ("X-Apple-I-MD", b64("md")),
+                ("X-Apple-I-MD-M", b64("md-m")),
+                ("X-Apple-I-MD-RINFO", "17106176".to_string()),
+                ("X-Apple-I-MD-LU", hex::encode_upper(self.derive("md-lu"))),
+                ("X-Apple-I-SRL-NO", "0".to_string()),
+                ("X-Mme-Device-Id", self.device_id()),
+                ("X-Apple-I-Client-Time", Self::CLIENT_TIME.to_string()),
+                ("X-Apple-I-TimeZone", "UTC".to_string()),
+                ("X-Apple-Locale", "en_US".to_string()),
+                (
+                    "X-Mme-Client-Info",
+                    "<MacBookPro13,2> <macOS;13.1;22C65> <com.apple.AuthKit/1 (com.apple.dt.Xcode/3594.4.19)>".to_string(),
+                ),
+            ];
+
+            Ok(headers.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+        })
+    }
+}
+
+// MARK: - Failover
+
+/// The public server omnisette uses when nothing else is configured.
+pub const DEFAULT_ANISETTE_SERVERS: &[&str] = &["https://ani.sidestore.io"];
+
+/// How a server did in `FailoverProvider::check_servers`.
+#[derive(Debug, Clone)]
+pub struct ServerHealth {
+    pub url: String,
+    pub latency: Option<Duration>,
+    pub error: Option<String>,
+}
+
+impl ServerHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Gets headers from an ordered list of anisette v3 servers. The first
+/// server is used until it errors out or takes too long, then the others
+/// are health checked and the fastest one that is up takes over.
+#[derive(Debug)]
+pub struct FailoverProvider {
+    config: AnisetteConfiguration,
+    // Swapped in place by `set_servers`, so accounts sharing the provider follow along
+    servers: RwLock<Vec<String>>,
+    preferred: AtomicUsize,
+    timeout: Duration,
+    client: reqwest::Client,
+    // How headers are fetched from one server, tests swap omnisette out
+    connect: fn(&AnisetteConfiguration, &str) -> Arc<dyn AnisetteProvider>,
+}
+
+impl FailoverProvider {
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+
+    /// `config` supplies everything but the server, e.g. the configuration path.
+    pub fn new(config: AnisetteConfiguration, servers: Vec<String>) -> Self {
+        Self {
+            config,
+            servers: RwLock::new(Self::or_default(servers)),
+            preferred: AtomicUsize::new(0),
+            timeout: Self::DEFAULT_TIMEOUT,
+            client: reqwest::Client::new(),
+            connect: |config, url| Arc::new(OmnisetteProvider::new(config.clone().set_anisette_url_v3(url.to_string()))),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Health checks go through `client`, pass `NetworkOptions::client` so
+    /// they use the same proxy and certificates as everything else.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn servers(&self) -> Vec<String> {
+        self.servers.read().unwrap().clone()
+    }
+
+    /// Replaces the servers, starting over from the first one on the next request.
+    pub fn set_servers(&self, servers: Vec<String>) {
+        *self.servers.write().unwrap() = Self::or_default(servers);
+        self.preferred.store(0, Ordering::Relaxed);
+    }
+
+    fn or_default(servers: Vec<String>) -> Vec<String> {
+        if servers.is_empty() {
+            DEFAULT_ANISETTE_SERVERS.iter().map(|s| s.to_string()).collect()
+        } else {
+            servers
+        }
+    }
+
+    /// Pings every server's `/v3/client_info` at once and reports how long each took.
+    pub async fn check_servers(&self) -> Vec<ServerHealth> {
+        futures::future::join_all(self.servers().into_iter().map(|url| async move {
+            let started = Instant::now();
+            let result = self
+                .client
+                .get(format!("{}/v3/client_info", url.trim_end_matches('/')))
+                .timeout(self.timeout)
+                .send()
+                .await
+                .and_then(|res| res.error_for_status());
+
+            match result {
+                Ok(_) => ServerHealth { url, latency: Some(started.elapsed()), error: None },
+                Err(e) => ServerHealth { url, latency: None, error: Some(e.to_string()) },
+            }
+        }))
+        .await
+    }
+
+    async fn get_headers_from(&self, url: &str) -> Result<HashMap<String, String>, Error> {
+        let provider = (self.connect)(&self.config, url);
+
+        tokio::time::timeout(self.timeout, provider.get_headers())
+            .await
+            .map_err(|_| Error::InvalidAnisette(format!("timed out after {}s", self.timeout.as_secs())))?
+    }
+}
+
+impl AnisetteProvider for FailoverProvider {
+    fn get_headers(&self) -> BoxFuture<'_, Result<HashMap<String, String>, Error>> {
+        Box::pin(async move {
+            // Starts out as the first server in the list, then whichever worked last
+            let servers = self.servers();
+            let preferred = self.preferred.load(Ordering::Relaxed);
+            let preferred_url = servers.get(preferred).unwrap_or(&servers[0]);
+
+            let mut errors = Vec::new();
+            match self.get_headers_from(preferred_url).await {
+                Ok(headers) => return Ok(headers),
+                Err(e) => {
+                    tracing::warn!(server = %preferred_url, error = %e, "anisette server failed, failing over");
+                    errors.push(format!("{}: {}", preferred_url, e));
+                }
+            }
+
+            // Fail over to the rest of the servers that are up, fastest first
+            let (mut candidates, down): (Vec<_>, Vec<_>) = self.check_servers().await
+                .into_iter()
+                .filter(|h| &h.url != preferred_url)
+                .partition(|h| h.is_healthy());
+
+            errors.extend(down.into_iter().map(|h| format!("{}: {}", h.url, h.error.unwrap_or_default())));
+            candidates.sort_by_key(|h| h.latency);
+
+            for candidate in candidates {
+                match self.get_headers_from(&candidate.url).await {
+                    Ok(headers) => {
+                        tracing::info!(server = %candidate.url, "switched anisette server");
+                        if let Some(index) = self.servers().iter().position(|s| *s == candidate.url) {
+                            self.preferred.store(index, Ordering::Relaxed);
+                        }
+                        return Ok(headers);
+                    }
+                    Err(e) => errors.push(format!("{}: {}", candidate.url, e)),
+                }
+            }
+
+            Err(Error::AnisetteUnavailable(errors.join(", ")))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    // Any server whose URL ends in this hands out errors instead of headers
+    const BROKEN: &str = "/broken";
+
+    #[derive(Debug)]
+    struct BrokenProvider;
+
+    impl AnisetteProvider for BrokenProvider {
+        fn get_headers(&self) -> BoxFuture<'_, Result<HashMap<String, String>, Error>> {
+            Box::pin(async { Err(Error::InvalidAnisette("provisioning failed".to_string())) })
+        }
+    }
+
+    fn connect(_: &AnisetteConfiguration, url: &str) -> Arc<dyn AnisetteProvider> {
+        if url.ends_with(BROKEN) {
+            Arc::new(BrokenProvider)
+        } else {
+            Arc::new(MockProvider::new(url))
+        }
+    }
+
+    fn failover(servers: &[&str]) -> FailoverProvider {
+        FailoverProvider {
+            connect,
+            ..FailoverProvider::new(
+                AnisetteConfiguration::default(),
+                servers.iter().map(|s| s.to_string()).collect(),
+            )
+            .with_timeout(Duration::from_secs(5))
+        }
+    }
+
+    // Answers every request with an empty 200 and counts them
+    async fn up_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+
+        let counter = hits.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                let mut buf = [0u8; 4096];
+                stream.read(&mut buf).await.ok();
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                    .await
+                    .ok();
+            }
+        });
+
+        (url, hits)
+    }
+
+    // Nothing listens here any more, so connecting is refused
+    async fn down_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    async fn headers_of(provider: &dyn AnisetteProvider) -> HashMap<String, String> {
+        provider.get_headers().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_the_next_server_that_is_up() {
+        let (first, _) = up_server().await;
+        let first = format!("{}{}", first, BROKEN);
+        let (second, second_hits) = up_server().await;
+        let down = down_server().await;
+
+        let provider = failover(&[&first, &down, &second]);
+
+        let headers = provider.get_headers().await.unwrap();
+        assert_eq!(headers, headers_of(&MockProvider::new(second.as_str())).await);
+        assert_eq!(provider.preferred.load(Ordering::Relaxed), 2);
+
+        // Sticks with the server that worked, without checking the others again
+        let health_checks = second_hits.load(Ordering::Relaxed);
+        provider.get_headers().await.unwrap();
+        assert_eq!(second_hits.load(Ordering::Relaxed), health_checks);
+    }
+
+    #[tokio::test]
+    async fn health_reports_latency_or_the_error() {
+        let (up, _) = up_server().await;
+        let down = down_server().await;
+
+        let health = failover(&[&up, &down]).check_servers().await;
+
+        assert_eq!(health.len(), 2);
+        assert_eq!(health[0].url, up);
+        assert!(health[0].is_healthy());
+        assert!(health[0].latency.is_some());
+        assert_eq!(health[1].url, down);
+        assert!(!health[1].is_healthy());
+        assert!(health[1].latency.is_none());
+        assert!(health[1].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn unavailable_when_every_server_fails() {
+        let (broken, _) = up_server().await;
+        let broken = format!("{}{}", broken, BROKEN);
+        let down = down_server().await;
+
+        let provider = failover(&[&broken, &down]);
+
+        match provider.get_headers().await {
+            Err(Error::AnisetteUnavailable(reason)) => {
+                assert!(reason.contains(&broken), "{}", reason);
+                assert!(reason.contains(&down), "{}", reason);
+            }
+            other => panic!("expected AnisetteUnavailable, got {:?}", other),
+        }
+        assert_eq!(provider.preferred.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn new_servers_reach_everyone_sharing_the_provider() {
+        let (first, _) = up_server().await;
+        let first = format!("{}{}", first, BROKEN);
+        let (second, _) = up_server().await;
+        let (third, _) = up_server().await;
+
+        let provider = Arc::new(failover(&[&first, &second]));
+        let shared: Arc<dyn AnisetteProvider> = provider.clone();
+        shared.get_headers().await.unwrap();
+        assert_eq!(provider.preferred.load(Ordering::Relaxed), 1);
+
+        provider.set_servers(vec![third.clone()]);
+
+        assert_eq!(shared.get_headers().await.unwrap(), headers_of(&MockProvider::new(third.as_str())).await);
+        assert_eq!(provider.preferred.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn no_servers_means_the_default_ones() {
+        let provider = FailoverProvider::new(AnisetteConfiguration::default(), Vec::new());
+
+        assert_eq!(provider.servers(), DEFAULT_ANISETTE_SERVERS);
+    }
+
+    #[tokio::test]
+    async fn static_headers_never_change() {
+        let headers: HashMap<String, String> = [("X-Apple-I-MD".to_string(), "recorded".to_string())].into();
+        let provider = StaticProvider::new(headers.clone());
+
+        assert_eq!(headers_of(&provider).await, headers);
+        assert_eq!(headers_of(&provider).await, headers);
+    }
+
+    #[tokio::test]
+    async fn mock_headers_follow_the_seed() {
+        let first = headers_of(&MockProvider::new("seed")).await;
+
+        assert_eq!(first, headers_of(&MockProvider::new("seed")).await);
+        assert_ne!(first["X-Mme-Device-Id"], headers_of(&MockProvider::new("other")).await["X-Mme-Device-Id"]);
+        assert_eq!(first["X-Mme-Device-Id"].len(), 36);
+    }
+}
@@ -0,0 +1,42 @@
+/// Where an `Account` sends its requests. Defaults to Apple's servers,
+/// point it somewhere else to talk to a stand-in, e.g. in tests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoints {
+    /// Base of the GrandSlam auth and 2FA endpoints.
+    pub gsa: String,
+    /// Base of the QH and v1 developer portal endpoints.
+    pub developer_services: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            gsa: "https://gsa.apple.com".to_string(),
+            developer_services: "https://developerservices2.apple.com/services".to_string(),
+        }
+    }
+}
+
+impl Endpoints {
+    /// Serves everything from one host, laid out the same way as Apple's.
+    pub fn with_base(base: &str) -> Self {
+        let base = base.trim_end_matches('/');
+
+        Self {
+            gsa: base.to_string(),
+            developer_services: format!("{}/services", base),
+        }
+    }
+
+    pub fn gsa_service(&self) -> String {
+        self.gsa_url("/grandslam/GsService2")
+    }
+
+    pub fn gsa_url(&self, path: &str) -> String {
+        format!("{}{}", self.gsa, path)
+    }
+
+    pub fn developer_url(&self, path: &str) -> String {
+        format!("{}{}", self.developer_services, path)
+    }
+}
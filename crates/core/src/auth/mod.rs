@@ -1,26 +1,45 @@
 pub mod account;
 pub mod anisette_data;
+pub mod anisette_provider;
+mod endpoints;
+mod network;
+
+pub use account::LoginFlow;
+pub use endpoints::Endpoints;
+pub use network::NetworkOptions;
+pub(crate) use network::SendLogged;
 
 use serde::{Deserialize, Serialize};
 use omnisette::AnisetteConfiguration;
-use reqwest::{Certificate, Client, ClientBuilder};
+use reqwest::Client;
 use tokio::sync::Mutex;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use crate::Error;
 
+use crate::auth::account::{AnisetteRefresher, Traffic};
 use crate::auth::anisette_data::AnisetteData;
+use crate::auth::anisette_provider::AnisetteProvider;
 
-const GSA_ENDPOINT: &str = "https://gsa.apple.com/grandslam/GsService2";
-const APPLE_ROOT: &[u8] = include_bytes!("./apple_root.der");
 
 #[derive(Debug, Clone)]
 pub struct Account {
-    pub anisette: Arc<Mutex<AnisetteData>>,
+    // shared between clones, refreshes when a request finds the headers getting old
+    anisette: Arc<AnisetteRefresher>,
     // pub spd:  Option<plist::Dictionary>,
     //mutable spd
     pub spd: Option<plist::Dictionary>,
+    // needed along with the PET to log in again without the password
+    username: Option<String>,
+    // shared between clones so sessions built from the same login reuse tokens
+    app_tokens: Arc<Mutex<HashMap<String, AppToken>>>,
+    endpoints: Endpoints,
+    // shared between clones so a recording keeps counting up across them
+    traffic: Traffic,
     client: Client,
+    debug_logging: bool,
 }
 
 impl Account {
@@ -28,20 +47,49 @@ impl Account {
         let anisette = AnisetteData::new(config).await?;
         Ok(Self::new_with_anisette(anisette)?)
     }
+
+    pub async fn with_provider(provider: Arc<dyn AnisetteProvider>) -> Result<Self, Error> {
+        let anisette = AnisetteData::with_provider(provider).await?;
+        Ok(Self::new_with_anisette(anisette)?)
+    }
     
     fn new_with_anisette(anisette: AnisetteData) -> Result<Self, Error> {
-        let client = ClientBuilder::new()
-            .add_root_certificate(Certificate::from_der(APPLE_ROOT)?)
-            // uncomment when debugging w/ charles proxy
-            // .danger_accept_invalid_certs(true)
-            .http1_title_case_headers()
-            .connection_verbose(true)
-            .build()?;
-        Ok(Account {
-            anisette: Arc::new(Mutex::new(anisette)),
+        Ok(Self::with_transport(anisette, Endpoints::default(), Self::apple_client()?))
+    }
+
+    /// Sends every request to `endpoints` through `client`, for talking to
+    /// something other than Apple, like a local stand-in server in tests.
+    pub fn with_transport(anisette: AnisetteData, endpoints: Endpoints, client: Client) -> Self {
+        Account {
+            anisette: AnisetteRefresher::new(anisette),
             spd: None,
+            username: None,
+            app_tokens: Arc::new(Mutex::new(HashMap::new())),
+            endpoints,
+            traffic: Traffic::default(),
             client,
-        })
+            debug_logging: false,
+        }
+    }
+
+    /// Like `with_transport` against Apple's servers, reaching them the way `network` says.
+    pub fn with_network(anisette: AnisetteData, network: &NetworkOptions) -> Result<Self, Error> {
+        let mut account = Self::with_transport(anisette, Endpoints::default(), network.client()?);
+        account.debug_logging = network.debug_logging;
+        Ok(account)
+    }
+
+    /// The client used by default, which only trusts Apple's root certificate.
+    pub fn apple_client() -> Result<Client, Error> {
+        NetworkOptions::default().client()
+    }
+
+    pub fn set_debug_logging(&mut self, enabled: bool) {
+        self.debug_logging = enabled;
+    }
+
+    pub fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
     }
 }
 
@@ -117,10 +165,23 @@ pub struct AppToken {
     pub app_tokens: plist::Dictionary,
     pub auth_token: String,
     pub app: String,
+    pub expires_at: Option<SystemTime>,
+}
+
+impl AppToken {
+    // Refresh a little early so a token doesn't expire mid-request
+    const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => SystemTime::now() + Self::EXPIRY_MARGIN >= expires_at,
+            None => true,
+        }
+    }
 }
 //Just make it return a custom enum, with LoggedIn(account: AppleAccount) or Needs2FA(FinishLoginDel: fn(i32) -> TFAResponse)
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum LoginState {
     LoggedIn,
     // NeedsSMS2FASent(Send2FAToDevices),
@@ -151,7 +212,7 @@ pub struct VerifyBody {
 }
 
 #[repr(C)]
-#[derive(Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TrustedPhoneNumber {
     pub number_with_dial_code: String,
@@ -160,6 +221,13 @@ pub struct TrustedPhoneNumber {
     pub id: u32,
 }
 
+impl TrustedPhoneNumber {
+    /// The number with everything but the last two digits hidden, for showing in pickers.
+    pub fn masked_number(&self) -> String {
+        format!("•••• ••{}", self.last_two_digits)
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AuthenticationExtras {
@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use reqwest::{Certificate, Client, ClientBuilder, Proxy, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+
+use crate::Error;
+
+const APPLE_ROOT: &[u8] = include_bytes!("./apple_root.der");
+
+// Headers that carry tokens or one time passwords, never logged
+const SECRET_HEADERS: [&str; 8] = [
+    "x-apple-gs-token",
+    "x-apple-identity-token",
+    "x-apple-i-identity-id",
+    "x-apple-i-md",
+    "x-apple-i-md-m",
+    "security-code",
+    "authorization",
+    "cookie",
+];
+
+/// How an `Account` reaches Apple, for networks that need a proxy or that
+/// intercept TLS with their own certificate authority.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkOptions {
+    /// `http://`, `https://`, `socks5://` or `socks5h://` proxy for all Apple traffic.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// PEM or DER root certificates to trust on top of Apple's.
+    pub root_certificates: Vec<PathBuf>,
+    /// Also logs the headers of every request, with secret ones redacted.
+    pub debug_logging: bool,
+}
+
+impl NetworkOptions {
+    pub fn client(&self) -> Result<Client, Error> {
+        let mut builder = ClientBuilder::new()
+            .add_root_certificate(Certificate::from_der(APPLE_ROOT)?)
+            .http1_title_case_headers()
+            .connection_verbose(true);
+
+        for path in &self.root_certificates {
+            builder = builder.add_root_certificate(Self::load_certificate(path)?);
+        }
+
+        if let Some(proxy) = self.proxy.as_deref().filter(|p| !p.trim().is_empty()) {
+            builder = builder.proxy(Proxy::all(proxy.trim())?);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    fn load_certificate(path: &PathBuf) -> Result<Certificate, Error> {
+        let bytes = fs::read(path)?;
+
+        let certificate = if bytes.starts_with(b"-----BEGIN") {
+            Certificate::from_pem(&bytes)
+        } else {
+            Certificate::from_der(&bytes)
+        };
+
+        certificate.map_err(|e| Error::Certificate(format!("{}: {}", path.display(), e)))
+    }
+}
+
+pub(crate) trait SendLogged {
+    async fn send_logged(self, debug_logging: bool) -> Result<Response, reqwest::Error>;
+}
+
+impl SendLogged for RequestBuilder {
+    /// Sends the request inside an `http` span. Headers are only logged with
+    /// `debug_logging` on, and secret ones never are.
+    async fn send_logged(self, debug_logging: bool) -> Result<Response, reqwest::Error> {
+        let (client, request) = self.build_split();
+        let request = request?;
+
+        let span = tracing::debug_span!("http", method = %request.method(), url = %request.url());
+
+        async move {
+            if debug_logging {
+                for (name, value) in request.headers() {
+                    let value = if SECRET_HEADERS.contains(&name.as_str()) {
+                        "<redacted>"
+                    } else {
+                        value.to_str().unwrap_or("<binary>")
+                    };
+                    tracing::debug!(header = %name, value, "request header");
+                }
+            }
+
+            let started = Instant::now();
+
+            match client.execute(request).await {
+                Ok(response) => {
+                    tracing::debug!(status = %response.status(), elapsed_ms = started.elapsed().as_millis() as u64, "response");
+                    Ok(response)
+                }
+                Err(e) => {
+                    tracing::warn!(elapsed_ms = started.elapsed().as_millis() as u64, error = %e, "request failed");
+                    Err(e)
+                }
+            }
+        }
+        .instrument(span)
+        .await
+    }
+}
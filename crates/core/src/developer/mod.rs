@@ -12,8 +12,8 @@ use crate::developer::qh::ResponseMeta;
 
 #[macro_export]
 macro_rules! developer_endpoint {
-    ($endpoint:expr) => {
-        format!("https://developerservices2.apple.com/services{}", $endpoint)
+    ($session:expr, $endpoint:expr) => {
+        $session.account.endpoints().developer_url($endpoint)
     };
 }
 
@@ -49,13 +49,18 @@ impl SessionRequestTrait for DeveloperSession {
         let response = self.account.qh_send_request(url, Some(request)).await;
         let response = match response {
             Ok(resp) => resp,
-            Err(_) => return Err(Error::DeveloperSessionRequestFailed),
+            Err(e @ Error::Traffic(_)) => return Err(e),
+            Err(e) => {
+                tracing::warn!(url, error = %e, "developer request failed");
+                return Err(Error::DeveloperSessionRequestFailed);
+            }
         };
         
         let response_data: ResponseMeta = plist::from_value(&Value::Dictionary(response.clone()))?;
         if response_data.result_code.as_signed().unwrap_or(0) != 0 {
             let msg = response_data.result_string.as_deref().unwrap_or("Unknown");
             let code = response_data.result_code.as_signed().unwrap_or(0);
+            tracing::warn!(url, code, msg, "developer request returned an error");
             return Err(Error::DeveloperSession(code, msg.to_string()));
         }
 
@@ -66,7 +71,11 @@ impl SessionRequestTrait for DeveloperSession {
         let response = self.account.v1_send_request(url, body, request_type).await;
         let response = match response {
             Ok(resp) => resp,
-            Err(_) => return Err(Error::DeveloperSessionRequestFailed),
+            Err(e @ Error::Traffic(_)) => return Err(e),
+            Err(e) => {
+                tracing::warn!(url, error = %e, "developer request failed");
+                return Err(Error::DeveloperSessionRequestFailed);
+            }
         };
         
         let response_data: serde_json::Value = serde_json::from_value(response.clone())?;
@@ -8,7 +8,7 @@ use super::{DeveloperSession, ResponseMeta};
 
 impl DeveloperSession {
     pub async fn qh_get_account_info(&self, team_id: &str) -> Result<ViewDeveloperResponse, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/viewDeveloper.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/viewDeveloper.action");
         
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
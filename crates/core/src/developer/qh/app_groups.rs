@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use plist::{Dictionary, Value};
 
 use crate::Error;
@@ -9,7 +9,7 @@ use super::{DeveloperSession, ResponseMeta};
 
 impl DeveloperSession {
     pub async fn qh_list_app_groups(&self, team_id: &str) -> Result<AppGroupsResponse, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/listApplicationGroups.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/listApplicationGroups.action");
 
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -21,7 +21,7 @@ impl DeveloperSession {
     }
     
     pub async fn qh_add_app_group(&self, team_id: &str, name: &str, identifier: &str) -> Result<AppGroupResponse, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/addApplicationGroup.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/addApplicationGroup.action");
         
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -53,7 +53,7 @@ impl DeveloperSession {
     }
 
     pub async fn qh_assign_app_group(&self, team_id: &str, app_id_id: &str, app_group_ids: &Vec<String>) -> Result<ResponseMeta, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/assignApplicationGroupToAppId.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/assignApplicationGroupToAppId.action");
         
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -86,12 +86,12 @@ pub struct AppGroupResponse {
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ApplicationGroup {
     pub application_group: String, // this is the actual identifier
     pub name: String,
     pub status: String,
-    prefix: String,
+    pub prefix: String,
     pub identifier: String, // this is the group.identifier
 }
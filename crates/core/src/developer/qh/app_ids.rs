@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use plist::{Dictionary, Integer, Value};
 
 use crate::Error;
@@ -9,7 +9,7 @@ use super::{DeveloperSession, ResponseMeta};
 
 impl DeveloperSession {
     pub async fn qh_list_app_ids(&self, team_id: &str) -> Result<AppIDsResponse, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/listAppIds.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/listAppIds.action");
 
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -21,7 +21,7 @@ impl DeveloperSession {
     }
 
     pub async fn qh_add_app_id(&self, team_id: &str, name: &str, identifier: &str) -> Result<AppIDResponse, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/addAppId.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/addAppId.action");
         
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -35,7 +35,7 @@ impl DeveloperSession {
     }
     
     pub async fn qh_delete_app_id(&self, team_id: &str, app_id_id: &str) -> Result<ResponseMeta, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/deleteAppId.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/deleteAppId.action");
         
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -48,7 +48,7 @@ impl DeveloperSession {
     }
     
     pub async fn qh_update_app_id(&self, team_id: &str, app_id_id: &str, features: Dictionary) -> Result<AppIDResponse, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/updateAppId.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/updateAppId.action");
         
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -102,27 +102,27 @@ pub struct AppIDResponse {
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct AppID {
     pub app_id_id: String,
-    name: String,
-    app_id_platform: String,
-    prefix: String,
+    pub name: String,
+    pub app_id_platform: String,
+    pub prefix: String,
     pub identifier: String,
-    is_wild_card: bool,
+    pub is_wild_card: bool,
     is_duplicate: bool,
     features: Features,
-    enabled_features: Option<Vec<String>>,
+    pub enabled_features: Option<Vec<String>>,
     is_dev_push_enabled: bool,
     is_prod_push_enabled: bool,
-    associated_application_groups_count: Option<Integer>,
+    pub associated_application_groups_count: Option<Integer>,
     associated_cloud_containers_count: Option<Integer>,
     associated_identifiers_count: Option<Integer>,
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct Features {
     push: bool,
@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use plist::{Data, Date, Dictionary, Integer, Value};
 use uuid::Uuid;
 
@@ -9,7 +9,7 @@ use super::{DeveloperSession, ResponseMeta};
 
 impl DeveloperSession {
     pub async fn qh_list_certs(&self, team_id: &str) -> Result<CertsResponse, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/listAllDevelopmentCerts.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/listAllDevelopmentCerts.action");
         
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -21,7 +21,7 @@ impl DeveloperSession {
     }
     
     pub async fn qh_revoke_cert(&self, team_id: &str, serial_number: &str) -> Result<ResponseMeta, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/revokeDevelopmentCert.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/revokeDevelopmentCert.action");
         
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -34,7 +34,7 @@ impl DeveloperSession {
     }
 
     pub async fn qh_submit_cert_csr(&self, team_id: &str, csr_data: String, machine_name: &str) -> Result<CsrResponse, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/submitDevelopmentCSR.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/submitDevelopmentCSR.action");
         
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -68,7 +68,7 @@ pub struct CsrResponse {
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Cert {
     pub name: String,
@@ -79,6 +79,7 @@ pub struct Cert {
     pub expiration_date: Date,
     certificate_platform: Option<String>,
     pub cert_type: Option<CertType>,
+    #[serde(skip_serializing)]
     pub cert_content: Data,
     pub machine_id: Option<String>,
     pub machine_name: Option<String>,
@@ -112,7 +113,7 @@ pub struct Csr {
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct CertType {
     certificate_type_display_id: String,
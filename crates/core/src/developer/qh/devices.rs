@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use plist::{Dictionary, Date, Value};
 
 use crate::Error;
@@ -8,7 +8,7 @@ use super::{DeveloperSession, ResponseMeta};
 
 impl DeveloperSession {
     pub async fn qh_list_devices(&self, team_id: &str) -> Result<DevicesResponse, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/listDevices.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/listDevices.action");
         
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -20,7 +20,7 @@ impl DeveloperSession {
     }
     
     pub async fn qh_add_device(&self, team_id: &str, device_name: &str, device_udid: &str) -> Result<DeviceResponse, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/addDevice.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/addDevice.action");
         
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -71,7 +71,7 @@ pub struct DeviceResponse {
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Device {
     device_id: String,
@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use plist::{Data, Date, Dictionary, Value};
 
 use crate::Error;
@@ -8,7 +8,7 @@ use super::{DeveloperSession, ResponseMeta};
 
 impl DeveloperSession {
     pub async fn qh_get_profile(&self, team_id: &str, app_id_id: &str) -> Result<ProfilesResponse, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/ios/downloadTeamProvisioningProfile.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/ios/downloadTeamProvisioningProfile.action");
 
         let mut body = Dictionary::new();
         body.insert("teamId".to_string(), Value::String(team_id.to_string()));
@@ -31,7 +31,7 @@ pub struct ProfilesResponse {
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Profile {
     provisioning_profile_id: String,
@@ -47,6 +47,7 @@ pub struct Profile {
     managing_app: Option<String>,
     // app_id: AppID,
     app_id_id: String,
+    #[serde(skip_serializing)]
     pub encoded_profile: Data,
     pub filename: String,
     is_template_profile: bool,
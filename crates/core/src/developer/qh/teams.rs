@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use plist::{Date, Integer, Value};
 
 use crate::Error;
@@ -8,7 +8,7 @@ use super::{DeveloperSession, ResponseMeta};
 
 impl DeveloperSession {
     pub async fn qh_list_teams(&self) -> Result<TeamsResponse, Error> {
-        let endpoint = developer_endpoint!("/QH65B2/listTeams.action");
+        let endpoint = developer_endpoint!(self, "/QH65B2/listTeams.action");
         
         let response = self.qh_send_request(&endpoint, None).await?;
         let response_data: TeamsResponse = plist::from_value(&Value::Dictionary(response))?;
@@ -27,7 +27,7 @@ pub struct TeamsResponse {
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Team {
     pub status: String,
@@ -44,7 +44,7 @@ pub struct Team {
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 struct Membership {
     membership_id: String,
@@ -58,7 +58,7 @@ struct Membership {
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 struct TeamMember {
     team_member_id: String,
@@ -72,7 +72,7 @@ struct TeamMember {
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 struct TeamProvisionSettings {
     can_developer_role_register_devices: bool,
@@ -10,7 +10,7 @@ use crate::Error;
 
 impl DeveloperSession {
     pub async fn v1_list_app_ids(&self, team: &str) -> Result<AppIDsResponse, Error> {
-        let endpoint = developer_endpoint!("/v1/bundleIds");
+        let endpoint = developer_endpoint!(self, "/v1/bundleIds");
 
         let body = json!({ 
             "teamId": team,
@@ -36,7 +36,7 @@ impl DeveloperSession {
         let response_data = self.v1_get_app_id(team, app_id).await?;        
         let app_id = response_data.ok_or(Error::DeveloperSessionRequestFailed)?;
 
-        let endpoint = developer_endpoint!(&format!("/v1/bundleIds/{}", app_id.id));
+        let endpoint = developer_endpoint!(self, &format!("/v1/bundleIds/{}", app_id.id));
 
         let bundle_id_capabilities: Vec<Value> = capabilities.into_iter().map(|capability_id| {
             json!({
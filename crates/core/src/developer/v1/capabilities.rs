@@ -10,7 +10,7 @@ use crate::Error;
 
 impl DeveloperSession {
     pub async fn v1_list_capabilities(&self, team: &str) -> Result<CapabilitiesResponse, Error> {
-        let endpoint = developer_endpoint!("/v1/capabilities");
+        let endpoint = developer_endpoint!(self, "/v1/capabilities");
 
         let body = json!({ 
             "teamId": team,
@@ -9,6 +9,7 @@ use crate::auth::account::request::RequestType;
 
 pub use apple_codesign::{SigningSettings, SettingsScope, UnifiedSigner, AppleCodesignError};
 pub use omnisette::AnisetteConfiguration;
+pub use auth::anisette_provider::{AnisetteProvider, DEFAULT_ANISETTE_SERVERS, FailoverProvider, MockProvider, OmnisetteProvider, ServerHealth, StaticProvider};
 
 pub use utils::MachO;
 pub use utils::MobileProvision;
@@ -44,8 +45,34 @@ pub enum Error {
     ExtraStep(String),
     #[error("Bad 2FA code")]
     Bad2faCode,
+    #[error("Too many verification codes requested, {}", retry_after_message(.0))]
+    TwoFactorRateLimited(Option<u64>),
+    #[error("Verification is locked after too many attempts, {}", retry_after_message(.0))]
+    TwoFactorLocked(Option<u64>),
+    #[error("Apple ID is locked: {0}")]
+    AccountLocked(String),
+    #[error("Login step {0} is not valid in state {1}")]
+    InvalidLoginStep(String, String),
+    #[error("Saved session error: {0}")]
+    Session(String),
+    #[error("Login with the stored PET failed: {0}")]
+    PetLogin(String),
     #[error("Failed to parse")]
     Parse,
+    #[error("Response is missing or has an invalid {key}{}", status_message(.status))]
+    ResponseKey { key: String, status: Option<u16> },
+    #[error("Unexpected response: {reason}{}", status_message(.status))]
+    UnexpectedResponse { reason: String, status: Option<u16> },
+    #[error("Account is not logged in")]
+    NotLoggedIn,
+    #[error("Invalid anisette data: {0}")]
+    InvalidAnisette(String),
+    #[error("No anisette server is available: {0}")]
+    AnisetteUnavailable(String),
+    #[error("Traffic fixture error: {0}")]
+    Traffic(String),
+    #[error("Mach-O error: {0}")]
+    MachO(String),
 
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
@@ -59,6 +86,10 @@ pub enum Error {
     X509(#[from] x509_certificate::X509CertificateError),
     #[error("Reqwest error: {0}")]
     Reqwest(#[from] reqwest::Error),
+    #[error("Invalid header value: {0}")]
+    HeaderValue(#[from] reqwest::header::InvalidHeaderValue),
+    #[error("Invalid header name: {0}")]
+    HeaderName(#[from] reqwest::header::InvalidHeaderName),
     #[error("Anisette error: {0}")]
     Anisette(#[from] omnisette::AnisetteError),
     #[error("Serde JSON error: {0}")]
@@ -72,3 +103,18 @@ pub enum Error {
     #[error("RCGen error: {0}")]
     RcGen(#[from] rcgen::RcgenError),
 }
+
+// `Retry-After` is in seconds, round up so "0 minutes" never shows up
+fn retry_after_message(retry_after: &Option<u64>) -> String {
+    match retry_after {
+        Some(secs) => format!("wait {} minutes before trying again", secs.div_ceil(60).max(1)),
+        None => "wait a while before trying again".to_string(),
+    }
+}
+
+fn status_message(status: &Option<u16>) -> String {
+    match status {
+        Some(status) => format!(" (HTTP {})", status),
+        None => String::new(),
+    }
+}
@@ -10,6 +10,8 @@ use x509_certificate::{CapturedX509Certificate, X509Certificate};
 
 use crate::{Error, developer::{DeveloperSession, qh::certs::Cert}};
 
+const DEFAULT_MACHINE_NAME: &str = "AltStore";
+
 pub struct CertificateIdentity {
     pub cert: Option<CapturedX509Certificate>,
     pub key: Option<Box<dyn PrivateKey>>,
@@ -45,7 +47,7 @@ impl CertificateIdentity {
         machine_name: Option<String>,
         team_id: &String,
     ) -> Result<Self, Error> {
-        let machine_name = machine_name.unwrap_or_else(|| "AltStore".to_string());
+        let machine_name = machine_name.unwrap_or_else(|| DEFAULT_MACHINE_NAME.to_string());
 
         let key_path = Self::key_dir(config_path, &team_id)?.join("key.pem");
 
@@ -72,25 +74,20 @@ impl CertificateIdentity {
             let priv_key = RsaPrivateKey::from_pkcs8_pem(&key_string)?;
 
             if let Some(cert) = cert.find_certificate(certs.clone(), &priv_key, &machine_name).await? {
-                let cert_pem = encode_string("CERTIFICATE", LineEnding::LF, cert.cert_content.as_ref()).unwrap();
-                let key_pem = priv_key.to_pkcs8_pem(Default::default())?.to_string();
-
-                [cert_pem.into_bytes(), key_pem.into_bytes()]
+                Self::encode_key_pair(&cert, &priv_key)?
             } else {
                 let (cert, priv_key) = cert.request_new_certificate(session, team_id, &machine_name, certs).await?;
-                let cert_pem = encode_string("CERTIFICATE", LineEnding::LF, cert.cert_content.as_ref()).unwrap();
-                let key_pem = priv_key.to_pkcs8_pem(Default::default())?.to_string();
+                let key_pair = Self::encode_key_pair(&cert, &priv_key)?;
 
-                fs::write(&key_path, &key_pem)?;
-                [cert_pem.into_bytes(), key_pem.into_bytes()]
+                fs::write(&key_path, &key_pair[1])?;
+                key_pair
             }
         } else {
             let (cert, priv_key) = cert.request_new_certificate(session, team_id, &machine_name, certs).await?;
-            let cert_pem = encode_string("CERTIFICATE", LineEnding::LF, cert.cert_content.as_ref()).unwrap();
-            let key_pem = priv_key.to_pkcs8_pem(Default::default())?.to_string();
+            let key_pair = Self::encode_key_pair(&cert, &priv_key)?;
 
-            fs::write(&key_path, &key_pem)?;
-            [cert_pem.into_bytes(), key_pem.into_bytes()]
+            fs::write(&key_path, &key_pair[1])?;
+            key_pair
         };
 
         // TODO: this may be horrendious
@@ -105,6 +102,66 @@ impl CertificateIdentity {
         Ok(cert)
     }
 
+    /// Looks up the certificate issued for the key `new_with_session` stored for
+    /// `team_id`, without requesting a new one if it's missing.
+    pub async fn find_stored_certificate(
+        session: &DeveloperSession,
+        config_path: PathBuf,
+        machine_name: Option<String>,
+        team_id: &String,
+    ) -> Result<Option<(Cert, [Vec<u8>; 2])>, Error> {
+        let machine_name = machine_name.unwrap_or_else(|| DEFAULT_MACHINE_NAME.to_string());
+        let key_path = Self::key_dir(config_path, &team_id)?.join("key.pem");
+
+        if !key_path.exists() {
+            return Ok(None);
+        }
+
+        let key_string = fs::read_to_string(&key_path)?;
+        let priv_key = RsaPrivateKey::from_pkcs8_pem(&key_string)?;
+
+        let certs = session
+            .qh_list_certs(&team_id)
+            .await?
+            .certificates;
+
+        match Self::match_certificate(certs, &priv_key, &machine_name)? {
+            Some(cert) => {
+                let key_pair = Self::encode_key_pair(&cert, &priv_key)?;
+                Ok(Some((cert, key_pair)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Submits a CSR for a freshly generated key and returns the issued certificate
+    /// along with its PEM encoded certificate and key. Unlike `new_with_session`, this
+    /// never revokes existing certificates when the team is at its limit.
+    pub async fn request_certificate(
+        session: &DeveloperSession,
+        team_id: &String,
+        machine_name: &str,
+    ) -> Result<(Cert, [Vec<u8>; 2]), Error> {
+        let (cert_csr, priv_key) = Self::generate_csr()?;
+
+        let cert_request = session
+            .qh_submit_cert_csr(&team_id, cert_csr, machine_name)
+            .await?
+            .cert_request;
+
+        let cert = session
+            .qh_list_certs(&team_id)
+            .await?
+            .certificates
+            .into_iter()
+            .find(|c| c.certificate_id == cert_request.certificate_id)
+            .ok_or(Error::CertificatePemMissing)?;
+
+        let key_pair = Self::encode_key_pair(&cert, &priv_key)?;
+
+        Ok((cert, key_pair))
+    }
+
     // <config_path>/keys/<team_id>
     fn key_dir(path: PathBuf, team_id: &String) -> Result<PathBuf, Error> {
         let dir = path.join("keys").join(team_id);
@@ -115,12 +172,13 @@ impl CertificateIdentity {
     }
 
     fn set_machine_id(&mut self, machine_id: String) {
-        println!("Setting machine id: {}", machine_id);
+        // Doubles as the P12 password, so only say that it was found
+        tracing::debug!("machine id set");
         self.machine_id = Some(machine_id);
     }
 
     fn set_serial_number(&mut self, serial_number: String) {
-        println!("Setting serial number: {}", serial_number);
+        tracing::debug!(%serial_number, "serial number set");
         self.serial_number = Some(serial_number);
     }
 
@@ -157,18 +215,18 @@ impl CertificateIdentity {
          for pem in pem::parse_many(contents).map_err(Error::Pem)? {
             match pem.tag() {
                 "CERTIFICATE" => {
-                    println!("CERTIFICATE loaded!"); // TODO: REMOVE SOME DEBUG STATEMENTS IF THIS WORKS WONDERFULY
+                    tracing::debug!("certificate loaded");
                     self.cert = Some(CapturedX509Certificate::from_der(pem.contents())?);
                 }
                 "PRIVATE KEY" => {
-                    println!("PRIVATE KEY loaded!"); // TODO: REMOVE SOME DEBUG STATEMENTS IF THIS WORKS WONDERFULY
+                    tracing::debug!("private key loaded");
                     self.key = Some(Box::new(InMemoryPrivateKey::from_pkcs8_der(pem.contents())?));
                 }
                 "RSA PRIVATE KEY" => {
-                    println!("RSA PRIVATE KEY loaded!"); // TODO: REMOVE SOME DEBUG STATEMENTS IF THIS WORKS WONDERFULY
+                    tracing::debug!("rsa private key loaded");
                     self.key = Some(Box::new(InMemoryPrivateKey::from_pkcs1_der(pem.contents())?));
                 }
-                tag => println!("(unhandled PEM tag {}; ignoring)", tag),
+                tag => tracing::warn!(tag, "ignoring unhandled PEM tag"),
             }
         }
 
@@ -199,6 +257,25 @@ impl CertificateIdentity {
         certs: Vec<Cert>,
         priv_key: &RsaPrivateKey,
         machine_name: &str,
+    ) -> Result<Option<Cert>, Error> {
+        let Some(cert) = Self::match_certificate(certs, priv_key, machine_name)? else {
+            return Ok(None);
+        };
+
+        // We need to save the machine_id for our P12
+        if let Some(ref machine_id) = cert.machine_id {
+            self.set_machine_id(machine_id.clone());
+        }
+
+        self.set_serial_number(cert.serial_number.clone());
+
+        Ok(Some(cert))
+    }
+
+    fn match_certificate(
+        certs: Vec<Cert>,
+        priv_key: &RsaPrivateKey,
+        machine_name: &str,
     ) -> Result<Option<Cert>, Error> {
         let pub_key_der_obj = priv_key
             .to_public_key()
@@ -210,13 +287,6 @@ impl CertificateIdentity {
             if cert.machine_name.as_deref() == Some(machine_name) {
                 let parsed_cert = X509Certificate::from_der(&cert.cert_content)?;
                 if pub_key_der_obj == parsed_cert.public_key_data().as_ref() {
-                    // We need to save the machine_id for our P12
-                    if let Some(ref machine_id) = cert.machine_id {
-                        self.set_machine_id(machine_id.clone());
-                    }
-
-                    self.set_serial_number(cert.serial_number.clone());
-
                     return Ok(Some(cert));
                 }
             }
@@ -225,13 +295,15 @@ impl CertificateIdentity {
         Ok(None)
     }
 
-    async fn request_new_certificate(
-        &mut self,
-        session: &DeveloperSession,
-        team_id: &String,
-        machine_name: &String,
-        certs: Vec<Cert>,
-    ) -> Result<(Cert, RsaPrivateKey), Error> {
+    fn encode_key_pair(cert: &Cert, priv_key: &RsaPrivateKey) -> Result<[Vec<u8>; 2], Error> {
+        let cert_pem = encode_string("CERTIFICATE", LineEnding::LF, cert.cert_content.as_ref())
+            .map_err(|e| Error::Certificate(e.to_string()))?;
+        let key_pem = priv_key.to_pkcs8_pem(Default::default())?.to_string();
+
+        Ok([cert_pem.into_bytes(), key_pem.into_bytes()])
+    }
+
+    fn generate_csr() -> Result<(String, RsaPrivateKey), Error> {
         let priv_key = RsaPrivateKey::new(&mut OsRng, 2048)?;
         let priv_key_der = priv_key.to_pkcs8_der()?;
         let priv_key_pair = KeyPair::from_der(priv_key_der.as_bytes())?;
@@ -250,6 +322,18 @@ impl CertificateIdentity {
         let cert_csr = rcgen::Certificate::from_params(params)?
             .serialize_request_pem()?;
 
+        Ok((cert_csr, priv_key))
+    }
+
+    async fn request_new_certificate(
+        &mut self,
+        session: &DeveloperSession,
+        team_id: &String,
+        machine_name: &String,
+        certs: Vec<Cert>,
+    ) -> Result<(Cert, RsaPrivateKey), Error> {
+        let (cert_csr, priv_key) = Self::generate_csr()?;
+
         let cert_serial_numbers = certs
             .iter()
             .map(|c| c.serial_number.clone())
@@ -6,6 +6,15 @@ use plist::{Dictionary, Value};
 
 use crate::{Error, developer::v1::capabilities::Capability};
 
+const FAT_MAGIC: u32 = 0xcafebabe;
+const FAT_MAGIC_64: u32 = 0xcafebabf;
+const MH_MAGIC: u32 = 0xfeedface;
+const MH_MAGIC_64: u32 = 0xfeedfacf;
+
+const LC_SEGMENT: u32 = 0x1;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_CODE_SIGNATURE: u32 = 0x1d;
+
 /// Represents a Mach-O file and its entitlements.
 pub struct MachO {
     _macho_file: MachFile<'static>,
@@ -72,3 +81,521 @@ impl MachO {
         }
     }
 }
+
+// MARK: - Signature removal
+
+// apple-codesign can only parse, so this edits the load commands by hand. Only
+// little endian slices are handled, which is everything iOS runs.
+impl MachO {
+    /// Removes `LC_CODE_SIGNATURE` and the signature it points to from the binary
+    /// at `path`, slice by slice for fat binaries. Returns whether it was signed,
+    /// files that aren't Mach-O at all are left alone.
+    pub fn strip_code_signature<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
+        let path = path.as_ref();
+        let mut data = fs::read(path)?;
+
+        // Executables can be scripts, and a dylib name doesn't make it a binary
+        if !Self::is_macho(&data) {
+            return Ok(false);
+        }
+
+        let stripped = match read_u32_be(&data, 0)? {
+            FAT_MAGIC | FAT_MAGIC_64 => Self::strip_fat(&mut data)?,
+            _ => match Self::strip_thin(&mut data)? {
+                Some(new_len) => {
+                    data.truncate(new_len);
+                    true
+                }
+                None => false,
+            },
+        };
+
+        if stripped {
+            fs::write(path, &data)?;
+        }
+
+        Ok(stripped)
+    }
+
+    // Big endian magics are still Mach-O, `strip_thin` turns those down with an error
+    fn is_macho(data: &[u8]) -> bool {
+        matches!(read_u32_be(data, 0), Ok(FAT_MAGIC | FAT_MAGIC_64 | MH_MAGIC | MH_MAGIC_64))
+            || matches!(read_u32_le(data, 0), Ok(MH_MAGIC | MH_MAGIC_64))
+    }
+
+    // Slices stay where they are, we only shrink their sizes and zero what was
+    // cut so the alignment of every other slice is untouched.
+    fn strip_fat(data: &mut Vec<u8>) -> Result<bool, Error> {
+        let is_64 = read_u32_be(data, 0)? == FAT_MAGIC_64;
+        let nfat_arch = read_u32_be(data, 4)? as usize;
+        let arch_size = if is_64 { 32 } else { 20 };
+
+        let mut stripped = false;
+        let mut end = 8 + nfat_arch * arch_size;
+
+        for i in 0..nfat_arch {
+            let arch = 8 + i * arch_size;
+            let (offset, size) = if is_64 {
+                (read_u64_be(data, arch + 8)? as usize, read_u64_be(data, arch + 16)? as usize)
+            } else {
+                (read_u32_be(data, arch + 8)? as usize, read_u32_be(data, arch + 12)? as usize)
+            };
+
+            let slice = offset
+                .checked_add(size)
+                .and_then(|slice_end| data.get_mut(offset..slice_end))
+                .ok_or_else(|| Error::MachO("fat slice is out of bounds".into()))?;
+
+            let Some(new_size) = Self::strip_thin(slice)? else {
+                end = end.max(offset + size);
+                continue;
+            };
+
+            slice[new_size..].fill(0);
+
+            if is_64 {
+                write_u64_be(data, arch + 16, new_size as u64)?;
+            } else {
+                write_u32_be(data, arch + 12, new_size as u32)?;
+            }
+
+            end = end.max(offset + new_size);
+            stripped = true;
+        }
+
+        if stripped {
+            data.truncate(end);
+        }
+
+        Ok(stripped)
+    }
+
+    // Returns the new length of the slice, or `None` if it wasn't signed.
+    fn strip_thin(data: &mut [u8]) -> Result<Option<usize>, Error> {
+        let is_64 = match read_u32_le(data, 0)? {
+            MH_MAGIC_64 => true,
+            MH_MAGIC => false,
+            _ => return Err(Error::MachO("not a little endian Mach-O".into())),
+        };
+
+        let header_size = if is_64 { 32 } else { 28 };
+        let ncmds = read_u32_le(data, 16)?;
+        let sizeofcmds = read_u32_le(data, 20)? as usize;
+
+        let mut signature = None;
+        let mut linkedit = None;
+        let mut offset = header_size;
+
+        for _ in 0..ncmds {
+            let cmd = read_u32_le(data, offset)?;
+            let cmdsize = read_u32_le(data, offset + 4)? as usize;
+
+            if cmdsize == 0 {
+                return Err(Error::MachO("load command has a size of zero".into()));
+            }
+
+            match cmd {
+                LC_CODE_SIGNATURE => {
+                    let dataoff = read_u32_le(data, offset + 8)? as usize;
+                    let datasize = read_u32_le(data, offset + 12)? as usize;
+                    signature = Some((offset, cmdsize, dataoff, datasize));
+                }
+                LC_SEGMENT | LC_SEGMENT_64 if data.get(offset + 8..offset + 24) == Some(&b"__LINKEDIT\0\0\0\0\0\0"[..]) => {
+                    linkedit = Some(offset);
+                }
+                _ => {}
+            }
+
+            offset += cmdsize;
+        }
+
+        let Some((cmd_offset, cmdsize, dataoff, datasize)) = signature else {
+            return Ok(None);
+        };
+
+        let cmds_end = header_size + sizeofcmds;
+        if cmds_end > data.len() || cmd_offset + cmdsize > cmds_end {
+            return Err(Error::MachO("load commands are out of bounds".into()));
+        }
+
+        // Drop the load command by shifting the ones after it down
+        data.copy_within(cmd_offset + cmdsize..cmds_end, cmd_offset);
+        data[cmds_end - cmdsize..cmds_end].fill(0);
+        write_u32_le(data, 16, ncmds - 1)?;
+        write_u32_le(data, 20, (sizeofcmds - cmdsize) as u32)?;
+
+        // The signature sits at the end of __LINKEDIT, so it ends where the signature began
+        if let Some(mut segment) = linkedit {
+            if segment > cmd_offset {
+                segment -= cmdsize;
+            }
+
+            if is_64 {
+                let fileoff = read_u64_le(data, segment + 40)? as usize;
+                write_u64_le(data, segment + 48, dataoff.saturating_sub(fileoff) as u64)?;
+            } else {
+                let fileoff = read_u32_le(data, segment + 32)? as usize;
+                write_u32_le(data, segment + 36, dataoff.saturating_sub(fileoff) as u32)?;
+            }
+        }
+
+        if dataoff + datasize >= data.len() {
+            return Ok(Some(dataoff.min(data.len())));
+        }
+
+        data[dataoff..dataoff + datasize].fill(0);
+
+        Ok(Some(data.len()))
+    }
+}
+
+fn field<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], Error> {
+    data.get(offset..offset + N)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| Error::MachO("unexpected end of file".into()))
+}
+
+fn field_mut<const N: usize>(data: &mut [u8], offset: usize) -> Result<&mut [u8], Error> {
+    data.get_mut(offset..offset + N)
+        .ok_or_else(|| Error::MachO("unexpected end of file".into()))
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> Result<u32, Error> {
+    Ok(u32::from_be_bytes(field(data, offset)?))
+}
+
+fn read_u64_be(data: &[u8], offset: usize) -> Result<u64, Error> {
+    Ok(u64::from_be_bytes(field(data, offset)?))
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Result<u32, Error> {
+    Ok(u32::from_le_bytes(field(data, offset)?))
+}
+
+fn read_u64_le(data: &[u8], offset: usize) -> Result<u64, Error> {
+    Ok(u64::from_le_bytes(field(data, offset)?))
+}
+
+fn write_u32_be(data: &mut [u8], offset: usize, value: u32) -> Result<(), Error> {
+    field_mut::<4>(data, offset)?.copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+fn write_u64_be(data: &mut [u8], offset: usize, value: u64) -> Result<(), Error> {
+    field_mut::<8>(data, offset)?.copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+fn write_u32_le(data: &mut [u8], offset: usize, value: u32) -> Result<(), Error> {
+    field_mut::<4>(data, offset)?.copy_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+fn write_u64_le(data: &mut [u8], offset: usize, value: u64) -> Result<(), Error> {
+    field_mut::<8>(data, offset)?.copy_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+    const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+    const MH_EXECUTE: u32 = 2;
+
+    const HEADER_SIZE: usize = 32;
+    const SEGMENT_SIZE: usize = 72;
+    const TEXT_SIZE: usize = 0x4000;
+    const LINKEDIT_DATA_SIZE: usize = 0x100;
+    const SIGNATURE_SIZE: usize = 0x200;
+    const FAT_ALIGN: usize = 0x4000;
+
+    fn push_segment(cmds: &mut Vec<u8>, name: &str, vmaddr: u64, fileoff: usize, filesize: usize) {
+        let mut segname = [0u8; 16];
+        segname[..name.len()].copy_from_slice(name.as_bytes());
+
+        cmds.extend_from_slice(&LC_SEGMENT_64.to_le_bytes());
+        cmds.extend_from_slice(&(SEGMENT_SIZE as u32).to_le_bytes());
+        cmds.extend_from_slice(&segname);
+        cmds.extend_from_slice(&vmaddr.to_le_bytes());
+        cmds.extend_from_slice(&0x4000u64.to_le_bytes());
+        cmds.extend_from_slice(&(fileoff as u64).to_le_bytes());
+        cmds.extend_from_slice(&(filesize as u64).to_le_bytes());
+        // maxprot, initprot, nsects, flags
+        for value in [1u32, 1, 0, 0] {
+            cmds.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    // __TEXT covers the header, __LINKEDIT holds some symbol data and then the
+    // signature, laid out like the linker does
+    fn thin(cputype: u32, signed: bool) -> Vec<u8> {
+        let signature_size = if signed { SIGNATURE_SIZE } else { 0 };
+
+        let mut cmds = Vec::new();
+        push_segment(&mut cmds, "__TEXT", 0x1_0000_0000, 0, TEXT_SIZE);
+        push_segment(&mut cmds, "__LINKEDIT", 0x1_0000_4000, TEXT_SIZE, LINKEDIT_DATA_SIZE + signature_size);
+        if signed {
+            for value in [LC_CODE_SIGNATURE, 16, (TEXT_SIZE + LINKEDIT_DATA_SIZE) as u32, SIGNATURE_SIZE as u32] {
+                cmds.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        let ncmds = if signed { 3u32 } else { 2 };
+        let mut data = Vec::new();
+        for value in [MH_MAGIC_64, cputype, 0, MH_EXECUTE, ncmds, cmds.len() as u32, 0, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&cmds);
+
+        data.resize(TEXT_SIZE, 0);
+        data.resize(TEXT_SIZE + LINKEDIT_DATA_SIZE, 0xaa);
+
+        if signed {
+            // An empty embedded signature superblob
+            for value in [0xfade0cc0u32, 12, 0] {
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            data.resize(TEXT_SIZE + LINKEDIT_DATA_SIZE + SIGNATURE_SIZE, 0);
+        }
+
+        data
+    }
+
+    fn fat(slices: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&FAT_MAGIC.to_be_bytes());
+        data.extend_from_slice(&(slices.len() as u32).to_be_bytes());
+
+        let mut offset = FAT_ALIGN;
+        let mut offsets = Vec::new();
+        for (cputype, slice) in slices {
+            for value in [*cputype, 0, offset as u32, slice.len() as u32, 14] {
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            offsets.push(offset);
+            offset = (offset + slice.len()).next_multiple_of(FAT_ALIGN);
+        }
+
+        for ((_, slice), offset) in slices.iter().zip(offsets) {
+            data.resize(offset, 0);
+            data.extend_from_slice(slice);
+        }
+
+        data
+    }
+
+    fn load_commands(data: &[u8]) -> Vec<(u32, usize)> {
+        let ncmds = read_u32_le(data, 16).unwrap();
+        let mut offset = HEADER_SIZE;
+
+        (0..ncmds)
+            .map(|_| {
+                let cmd = read_u32_le(data, offset).unwrap();
+                let at = offset;
+                offset += read_u32_le(data, offset + 4).unwrap() as usize;
+                (cmd, at)
+            })
+            .collect()
+    }
+
+    fn linkedit_filesize(data: &[u8]) -> u64 {
+        load_commands(data)
+            .into_iter()
+            .find(|(cmd, at)| *cmd == LC_SEGMENT_64 && data[at + 8..at + 18] == *b"__LINKEDIT")
+            .map(|(_, at)| read_u64_le(data, at + 48).unwrap())
+            .unwrap()
+    }
+
+    fn assert_unsigned_slice(slice: &[u8]) {
+        assert!(load_commands(slice).iter().all(|(cmd, _)| *cmd != LC_CODE_SIGNATURE));
+        assert_eq!(linkedit_filesize(slice), LINKEDIT_DATA_SIZE as u64);
+        assert_eq!(read_u32_le(slice, 20).unwrap() as usize, 2 * SEGMENT_SIZE);
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("plume-macho-{}-{}", std::process::id(), name))
+    }
+
+    // Deterministic so a failure can be reproduced
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn fixtures_parse() {
+        let signed = thin(CPU_TYPE_ARM64, true);
+        let macho = MachFile::parse(&signed).unwrap();
+        assert!(macho.nth_macho(0).unwrap().code_signature().unwrap().is_some());
+
+        let universal = fat(&[(CPU_TYPE_ARM64, thin(CPU_TYPE_ARM64, true)), (CPU_TYPE_X86_64, thin(CPU_TYPE_X86_64, true))]);
+        assert!(MachFile::parse(&universal).unwrap().nth_macho(1).is_ok());
+    }
+
+    #[test]
+    fn strips_a_thin_binary() {
+        let mut data = thin(CPU_TYPE_ARM64, true);
+
+        let new_len = MachO::strip_thin(&mut data).unwrap().unwrap();
+        data.truncate(new_len);
+
+        assert_eq!(data.len(), TEXT_SIZE + LINKEDIT_DATA_SIZE);
+        assert_unsigned_slice(&data);
+        // Symbol data in __LINKEDIT is left alone
+        assert!(data[TEXT_SIZE..].iter().all(|b| *b == 0xaa));
+
+        let macho = MachFile::parse(&data).unwrap();
+        assert!(macho.nth_macho(0).unwrap().code_signature().unwrap().is_none());
+    }
+
+    #[test]
+    fn leaves_an_unsigned_binary_alone() {
+        let mut data = thin(CPU_TYPE_ARM64, false);
+        let original = data.clone();
+
+        assert_eq!(MachO::strip_thin(&mut data).unwrap(), None);
+        assert_eq!(data, original);
+
+        let path = temp_path("unsigned");
+        fs::write(&path, &original).unwrap();
+        assert!(!MachO::strip_code_signature(&path).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), original);
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn strips_every_slice_of_a_fat_binary() {
+        let mut data = fat(&[(CPU_TYPE_ARM64, thin(CPU_TYPE_ARM64, true)), (CPU_TYPE_X86_64, thin(CPU_TYPE_X86_64, true))]);
+        let second_offset = read_u32_be(&data, 8 + 20 + 8).unwrap() as usize;
+
+        assert!(MachO::strip_fat(&mut data).unwrap());
+
+        for i in 0..2 {
+            let arch = 8 + i * 20;
+            let offset = read_u32_be(&data, arch + 8).unwrap() as usize;
+            let size = read_u32_be(&data, arch + 12).unwrap() as usize;
+
+            assert_eq!(size, TEXT_SIZE + LINKEDIT_DATA_SIZE);
+            assert_unsigned_slice(&data[offset..offset + size]);
+        }
+
+        // Slices stay where they were
+        assert_eq!(read_u32_be(&data, 8 + 20 + 8).unwrap() as usize, second_offset);
+        assert_eq!(data.len(), second_offset + TEXT_SIZE + LINKEDIT_DATA_SIZE);
+
+        let macho = MachFile::parse(&data).unwrap();
+        for i in 0..2 {
+            assert!(macho.nth_macho(i).unwrap().code_signature().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn strips_only_the_signed_slices() {
+        let mut data = fat(&[(CPU_TYPE_ARM64, thin(CPU_TYPE_ARM64, true)), (CPU_TYPE_X86_64, thin(CPU_TYPE_X86_64, false))]);
+        let unsigned_offset = read_u32_be(&data, 8 + 20 + 8).unwrap() as usize;
+
+        assert!(MachO::strip_fat(&mut data).unwrap());
+
+        assert_eq!(read_u32_be(&data, 8 + 12).unwrap() as usize, TEXT_SIZE + LINKEDIT_DATA_SIZE);
+        assert_eq!(read_u32_be(&data, 8 + 20 + 12).unwrap() as usize, TEXT_SIZE + LINKEDIT_DATA_SIZE);
+        assert_eq!(&data[unsigned_offset..], &thin(CPU_TYPE_X86_64, false)[..]);
+    }
+
+    #[test]
+    fn strips_through_the_file() {
+        let path = temp_path("signed");
+        fs::write(&path, thin(CPU_TYPE_ARM64, true)).unwrap();
+
+        assert!(MachO::strip_code_signature(&path).unwrap());
+        // Already stripped, nothing left to do
+        assert!(!MachO::strip_code_signature(&path).unwrap());
+
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        assert_unsigned_slice(&data);
+        assert!(MachFile::parse(&data).unwrap().nth_macho(0).unwrap().code_signature().unwrap().is_none());
+    }
+
+    #[test]
+    fn skips_files_that_are_not_mach_o() {
+        let path = temp_path("script");
+
+        for contents in [&b"#!/bin/sh\nexec true\n"[..], b"", b"\xfe"] {
+            fs::write(&path, contents).unwrap();
+
+            assert!(!MachO::strip_code_signature(&path).unwrap());
+            assert_eq!(fs::read(&path).unwrap(), contents);
+        }
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn truncated_binaries_are_errors() {
+        let signed = thin(CPU_TYPE_ARM64, true);
+        let cmds_end = HEADER_SIZE + 2 * SEGMENT_SIZE + 16;
+
+        for len in 0..signed.len() {
+            let mut data = signed[..len].to_vec();
+            let result = MachO::strip_thin(&mut data);
+
+            if len < cmds_end {
+                assert!(matches!(result, Err(Error::MachO(_))), "{} bytes: {:?}", len, result);
+            } else {
+                assert!(matches!(result, Ok(_) | Err(Error::MachO(_))), "{} bytes: {:?}", len, result);
+            }
+        }
+
+        let universal = fat(&[(CPU_TYPE_ARM64, signed.clone()), (CPU_TYPE_X86_64, signed)]);
+        for len in (0..universal.len()).step_by(7) {
+            let mut data = universal[..len].to_vec();
+            let result = if len < 4 { MachO::strip_thin(&mut data).map(|_| false) } else { MachO::strip_fat(&mut data) };
+
+            assert!(matches!(result, Ok(_) | Err(Error::MachO(_))), "{} bytes: {:?}", len, result);
+            if len < FAT_ALIGN {
+                assert!(matches!(result, Err(Error::MachO(_))), "{} bytes: {:?}", len, result);
+            }
+        }
+    }
+
+    #[test]
+    fn garbage_is_an_error_not_a_panic() {
+        let mut state = 0x9e37_79b9_7f4a_7c15;
+
+        for round in 0..500 {
+            let len = (xorshift(&mut state) % 512) as usize;
+            let mut data: Vec<u8> = (0..len).map(|_| xorshift(&mut state) as u8).collect();
+
+            // Half the time, make it look like a Mach-O so the load commands get walked
+            if round % 2 == 0 && data.len() >= 4 {
+                data[..4].copy_from_slice(&MH_MAGIC_64.to_le_bytes());
+            }
+
+            let result = MachO::strip_thin(&mut data);
+            assert!(matches!(result, Ok(_) | Err(Error::MachO(_))), "{:?}", result);
+
+            let result = MachO::strip_fat(&mut data);
+            assert!(matches!(result, Ok(_) | Err(Error::MachO(_))), "{:?}", result);
+        }
+
+        // Corrupted load commands of an otherwise valid binary
+        let signed = thin(CPU_TYPE_ARM64, true);
+        for _ in 0..500 {
+            let mut data = signed.clone();
+            for _ in 0..4 {
+                let index = HEADER_SIZE - 16 + (xorshift(&mut state) as usize) % (2 * SEGMENT_SIZE + 32);
+                data[index] = xorshift(&mut state) as u8;
+            }
+
+            let result = MachO::strip_thin(&mut data);
+            assert!(matches!(result, Ok(_) | Err(Error::MachO(_))), "{:?}", result);
+        }
+
+        assert!(matches!(MachO::strip_thin(&mut []), Err(Error::MachO(_))));
+    }
+}
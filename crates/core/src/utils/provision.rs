@@ -2,7 +2,7 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use crate::Error;
-use plist::{Dictionary, Value};
+use plist::{Date, Dictionary, Value};
 
 use super::MachO;
 
@@ -137,6 +137,52 @@ impl MobileProvision {
         }
     }
 
+    pub fn name(&self) -> Option<String> {
+        self.get_string("Name")
+    }
+
+    pub fn uuid(&self) -> Option<String> {
+        self.get_string("UUID")
+    }
+
+    pub fn team_name(&self) -> Option<String> {
+        self.get_string("TeamName")
+    }
+
+    pub fn team_identifier(&self) -> Option<String> {
+        self.provisioning_plist
+            .as_dictionary()?
+            .get("TeamIdentifier")?
+            .as_array()?
+            .get(0)?
+            .as_string()
+            .map(|s| s.to_string())
+    }
+
+    pub fn expiration_date(&self) -> Option<Date> {
+        self.provisioning_plist
+            .as_dictionary()?
+            .get("ExpirationDate")?
+            .as_date()
+    }
+
+    pub fn provisioned_devices(&self) -> Vec<String> {
+        self.provisioning_plist
+            .as_dictionary()
+            .and_then(|d| d.get("ProvisionedDevices"))
+            .and_then(|v| v.as_array())
+            .map(|arr| arr.iter().filter_map(|v| v.as_string().map(|s| s.to_string())).collect())
+            .unwrap_or_default()
+    }
+
+    fn get_string(&self, key: &str) -> Option<String> {
+        self.provisioning_plist
+            .as_dictionary()?
+            .get(key)?
+            .as_string()
+            .map(|s| s.to_string())
+    }
+
     fn extract_plist_from_file(data: &[u8]) -> Result<Value, Error> {
         let start = data
             .windows(6)
@@ -0,0 +1,462 @@
+//! A stand-in for GSA and the developer portal, just enough of both to log in,
+//! fetch an app token and list teams without talking to Apple.
+
+// Not every test binary uses every helper
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use botan::{Cipher, CipherDirection};
+use cbc::cipher::{BlockEncryptMut, KeyIvInit, block_padding::Pkcs7};
+use hmac::{Hmac, Mac};
+use num_bigint::BigUint;
+use plist::{Dictionary, Value};
+use sha2::{Digest, Sha256};
+use srp::groups::G_2048;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use plume_core::{AnisetteProvider, MockProvider};
+use plume_core::auth::anisette_data::AnisetteData;
+use plume_core::auth::{Account, Endpoints};
+
+pub const APPLE_ID: &str = "tester@example.com";
+pub const PASSWORD: &str = "correct horse battery staple";
+pub const ADSID: &str = "000123-45-6789abcd";
+pub const GS_TOKEN: &str = "gs-idms-token";
+pub const PET: &str = "pet-token";
+pub const XCODE_APP: &str = "com.apple.gs.xcode.auth";
+pub const XCODE_TOKEN: &str = "xcode-app-token";
+pub const TEAM_ID: &str = "TEAM123456";
+pub const TEAM_NAME: &str = "Tester Team";
+
+pub const GSA_PATH: &str = "/grandslam/GsService2";
+pub const LIST_TEAMS_PATH: &str = "/services/QH65B2/listTeams.action";
+
+const SALT: &[u8] = b"fake apple salt!";
+const ITERATIONS: u32 = 1000;
+const COOKIE: &str = "srp-cookie";
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+type Tamper = Box<dyn Fn(Vec<u8>) -> Vec<u8> + Send>;
+type TamperSpd = Box<dyn Fn(&mut Dictionary) + Send>;
+
+#[derive(Default)]
+struct State {
+    // b, B and A from `init`, `complete` needs them to finish the handshake
+    handshake: Option<(BigUint, BigUint, BigUint)>,
+    sk: Vec<u8>,
+    // The portal turns the app token down until a new one is fetched
+    token_expired: bool,
+    // Rewrites the body answering one GSA operation before it is sent
+    tamper: Option<(String, Tamper)>,
+    // Rewrites the session data `complete` hands out before it is encrypted
+    tamper_spd: Option<TamperSpd>,
+    requests: Vec<String>,
+}
+
+pub struct FakeApple {
+    pub base: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeApple {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        let state = Arc::new(Mutex::new(State {
+            sk: rand::random::<[u8; 32]>().to_vec(),
+            ..Default::default()
+        }));
+
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, server_state.clone()));
+            }
+        });
+
+        Self { base, state }
+    }
+
+    /// An account that isn't logged in and sends everything here.
+    pub async fn account(&self) -> Account {
+        self.account_with(Arc::new(MockProvider::new("fake-apple"))).await
+    }
+
+    /// Like `account`, with its anisette headers coming from `provider`.
+    pub async fn account_with(&self, provider: Arc<dyn AnisetteProvider>) -> Account {
+        let anisette = AnisetteData::with_provider(provider).await.unwrap();
+
+        Account::with_transport(anisette, Endpoints::with_base(&self.base), reqwest::Client::new())
+    }
+
+    /// Passes every body answering GSA's `operation` through `tamper` from now on.
+    pub fn tamper(&self, operation: &str, tamper: impl Fn(Vec<u8>) -> Vec<u8> + Send + 'static) {
+        self.state.lock().unwrap().tamper = Some((operation.to_string(), Box::new(tamper)));
+    }
+
+    /// Passes the session data of every login through `tamper` from now on.
+    pub fn tamper_spd(&self, tamper: impl Fn(&mut Dictionary) + Send + 'static) {
+        self.state.lock().unwrap().tamper_spd = Some(Box::new(tamper));
+    }
+
+    /// Makes the portal reject the app token handed out so far.
+    pub fn expire_token(&self) {
+        self.state.lock().unwrap().token_expired = true;
+    }
+
+    /// `METHOD path` of every request served so far, in order.
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+// MARK: - HTTP
+
+// One request per connection, `Connection: close` keeps the client from reusing it
+async fn serve(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    let Some(request) = read_request(&mut stream).await else {
+        return;
+    };
+
+    state.lock().unwrap().requests.push(format!("{} {}", request.method, request.path));
+
+    let (status, body) = match (request.method.as_str(), request.path.as_str()) {
+        ("POST", GSA_PATH) => (200, gsa(&state, &request)),
+        ("POST", LIST_TEAMS_PATH) => (200, list_teams(&state, &request)),
+        _ => (404, Vec::new()),
+    };
+
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/x-xml-plist\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        if status == 200 { "OK" } else { "Not Found" },
+        body.len()
+    );
+
+    stream.write_all(head.as_bytes()).await.ok();
+    stream.write_all(&body).await.ok();
+    stream.shutdown().await.ok();
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let head_end = loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut start = lines.next()?.split(' ');
+    let method = start.next()?.to_string();
+    let path = start.next()?.to_string();
+
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+        .collect();
+
+    let len = headers
+        .get("content-length")
+        .and_then(|len| len.parse().ok())
+        .unwrap_or(0);
+
+    let mut body = buf[head_end + 4..].to_vec();
+    while body.len() < len {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+
+    Some(Request { method, path, headers, body })
+}
+
+fn to_xml(value: &Value) -> Vec<u8> {
+    let mut buf = Vec::new();
+    plist::to_writer_xml(&mut buf, value).unwrap();
+    buf
+}
+
+fn dict(entries: Vec<(&str, Value)>) -> Value {
+    Value::Dictionary(entries.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+// MARK: - GSA
+
+fn gsa(state: &Mutex<State>, request: &Request) -> Vec<u8> {
+    let packet: Dictionary = plist::from_bytes(&request.body).expect("GSA request is not a plist");
+    let body = packet
+        .get("Request")
+        .and_then(Value::as_dictionary)
+        .expect("GSA request has no Request dictionary");
+
+    let mut state = state.lock().unwrap();
+    let operation = string(body, "o");
+    let response = match operation {
+        "init" => srp_init(&mut state, body),
+        "complete" => srp_complete(&mut state, body),
+        "apptokens" => app_tokens(&mut state, body),
+        o => gsa_error(-22406, &format!("unknown operation {:?}", o)),
+    };
+
+    let xml = to_xml(&dict(vec![("Response", response)]));
+    match &state.tamper {
+        Some((tampered, tamper)) if tampered == operation => tamper(xml),
+        _ => xml,
+    }
+}
+
+fn gsa_status(ec: i64, em: &str) -> Value {
+    dict(vec![("ec", Value::Integer(ec.into())), ("em", Value::String(em.to_string()))])
+}
+
+fn gsa_error(ec: i64, em: &str) -> Value {
+    dict(vec![("Status", gsa_status(ec, em))])
+}
+
+fn string<'a>(body: &'a Dictionary, key: &str) -> &'a str {
+    body.get(key).and_then(Value::as_string).unwrap_or_default()
+}
+
+fn data<'a>(body: &'a Dictionary, key: &str) -> &'a [u8] {
+    body.get(key).and_then(Value::as_data).unwrap_or_default()
+}
+
+fn srp_init(state: &mut State, body: &Dictionary) -> Value {
+    if string(body, "u") != APPLE_ID {
+        return gsa_error(-20101, "Your Apple ID or password was incorrect.");
+    }
+
+    let a_pub = BigUint::from_bytes_be(data(body, "A2k"));
+    let b = BigUint::from_bytes_be(&rand::random::<[u8; 32]>());
+    let b_pub = (multiplier() * verifier() + G_2048.g.modpow(&b, &G_2048.n)) % &G_2048.n;
+
+    let response = dict(vec![
+        ("s", Value::Data(SALT.to_vec())),
+        ("B", Value::Data(b_pub.to_bytes_be())),
+        ("i", Value::Integer(ITERATIONS.into())),
+        ("c", Value::String(COOKIE.to_string())),
+        ("sp", Value::String("s2k".to_string())),
+        ("Status", gsa_status(0, "")),
+    ]);
+
+    state.handshake = Some((b, b_pub, a_pub));
+    response
+}
+
+// Takes M1 on trust, the client checking M2 is what proves both sides got the same key
+fn srp_complete(state: &mut State, body: &Dictionary) -> Value {
+    let Some((b, b_pub, a_pub)) = state.handshake.take() else {
+        return gsa_error(-22406, "complete without init");
+    };
+    if string(body, "c") != COOKIE {
+        return gsa_error(-22406, "wrong cookie");
+    }
+
+    let n = &G_2048.n;
+    let u = BigUint::from_bytes_be(&sha256(&[&a_pub.to_bytes_be(), &b_pub.to_bytes_be()]));
+    let premaster = (&a_pub * verifier().modpow(&u, n)).modpow(&b, n);
+    let key = sha256(&[&premaster.to_bytes_be()]);
+    let m2 = sha256(&[&a_pub.to_bytes_be(), data(body, "M1"), &key]);
+
+    let mut spd = spd(&state.sk);
+    if let (Some(tamper), Value::Dictionary(spd)) = (&state.tamper_spd, &mut spd) {
+        tamper(spd);
+    }
+
+    let spd = to_xml(&spd);
+    let spd = cbc::Encryptor::<aes::Aes256>::new_from_slices(
+        &hmac(&key, "extra data key:"),
+        &hmac(&key, "extra data iv:")[..16],
+    )
+    .unwrap()
+    .encrypt_padded_vec_mut::<Pkcs7>(&spd);
+
+    dict(vec![
+        ("M2", Value::Data(m2)),
+        ("spd", Value::Data(spd)),
+        ("Status", gsa_status(0, "")),
+    ])
+}
+
+fn spd(sk: &[u8]) -> Value {
+    dict(vec![
+        ("adsid", Value::String(ADSID.to_string())),
+        ("acname", Value::String(APPLE_ID.to_string())),
+        ("GsIdmsToken", Value::String(GS_TOKEN.to_string())),
+        ("sk", Value::Data(sk.to_vec())),
+        ("c", Value::Data(b"continuation".to_vec())),
+        ("fn", Value::String("Test".to_string())),
+        ("ln", Value::String("User".to_string())),
+        (
+            "t",
+            dict(vec![(
+                "com.apple.gs.idms.pet",
+                dict(vec![("token", Value::String(PET.to_string()))]),
+            )]),
+        ),
+    ])
+}
+
+fn app_tokens(state: &mut State, body: &Dictionary) -> Value {
+    let app = body
+        .get("app")
+        .and_then(Value::as_array)
+        .and_then(|apps| apps.first())
+        .and_then(Value::as_string)
+        .unwrap_or_default();
+
+    if string(body, "u") != ADSID || string(body, "t") != GS_TOKEN {
+        return gsa_error(-22406, "unknown session");
+    }
+
+    let checksum = Hmac::<Sha256>::new_from_slice(&state.sk)
+        .unwrap()
+        .chain_update("apptokens")
+        .chain_update(ADSID)
+        .chain_update(app)
+        .finalize()
+        .into_bytes();
+    if data(body, "checksum") != checksum.as_slice() {
+        return gsa_error(-22406, "bad checksum");
+    }
+
+    let token = dict(vec![(
+        "t",
+        dict(vec![(
+            app,
+            dict(vec![
+                ("token", Value::String(XCODE_TOKEN.to_string())),
+                ("duration", Value::Integer(3600.into())),
+            ]),
+        )]),
+    )]);
+
+    let iv: [u8; 16] = rand::random();
+    let mut cipher = Cipher::new("AES-256/GCM", CipherDirection::Encrypt).unwrap();
+    cipher.set_key(&state.sk).unwrap();
+    cipher.set_associated_data(b"XYZ").unwrap();
+    cipher.start(&iv).unwrap();
+    let sealed = cipher.finish(&to_xml(&token)).unwrap();
+
+    let et = [b"XYZ".as_slice(), iv.as_slice(), sealed.as_slice()].concat();
+    state.token_expired = false;
+
+    dict(vec![("et", Value::Data(et)), ("Status", gsa_status(0, ""))])
+}
+
+// MARK: - SRP
+
+// Apple's variant of SRP-6a: the username is left out of x
+fn verifier() -> BigUint {
+    let hashed_password = Sha256::digest(PASSWORD.as_bytes());
+    let mut password = [0u8; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(&hashed_password, SALT, ITERATIONS, &mut password);
+
+    let x = BigUint::from_bytes_be(&sha256(&[SALT, &sha256(&[b":", &password])]));
+    G_2048.g.modpow(&x, &G_2048.n)
+}
+
+fn multiplier() -> BigUint {
+    let n = G_2048.n.to_bytes_be();
+    let g = G_2048.g.to_bytes_be();
+
+    let mut padded_g = vec![0u8; n.len() - g.len()];
+    padded_g.extend_from_slice(&g);
+
+    BigUint::from_bytes_be(&sha256(&[&n, &padded_g]))
+}
+
+fn sha256(parts: &[&[u8]]) -> Vec<u8> {
+    let mut digest = Sha256::new();
+    for part in parts {
+        digest.update(part);
+    }
+    digest.finalize().to_vec()
+}
+
+fn hmac(key: &[u8], name: &str) -> Vec<u8> {
+    Hmac::<Sha256>::new_from_slice(key)
+        .unwrap()
+        .chain_update(name.as_bytes())
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+// MARK: - Developer portal
+
+fn list_teams(state: &Mutex<State>, request: &Request) -> Vec<u8> {
+    let body: Dictionary = plist::from_bytes(&request.body).unwrap_or_default();
+    let request_id = string(&body, "requestId").to_string();
+
+    let header = |name: &str| request.headers.get(name).map(String::as_str);
+    let expired = state.lock().unwrap().token_expired;
+    if expired || header("x-apple-gs-token") != Some(XCODE_TOKEN) || header("x-apple-i-identity-id") != Some(ADSID) {
+        return to_xml(&portal_response(&request_id, 1100, vec![
+            ("resultString", Value::String("Your session has expired. Please log in.".to_string())),
+        ]));
+    }
+
+    let member = dict(vec![
+        ("teamMemberId", Value::String("MEMBER1234".to_string())),
+        ("personId", Value::Integer(1234567890.into())),
+        ("firstName", Value::String("Test".to_string())),
+        ("lastName", Value::String("User".to_string())),
+        ("email", Value::String(APPLE_ID.to_string())),
+    ]);
+
+    let team = dict(vec![
+        ("status", Value::String("active".to_string())),
+        ("name", Value::String(TEAM_NAME.to_string())),
+        ("teamId", Value::String(TEAM_ID.to_string())),
+        ("type", Value::String("Individual".to_string())),
+        ("memberships", Value::Array(Vec::new())),
+        ("currentTeamMember", member),
+        ("xcodeFreeOnly", Value::Boolean(true)),
+        (
+            "teamProvisioningSettings",
+            dict(vec![
+                ("canDeveloperRoleRegisterDevices", Value::Boolean(true)),
+                ("canDeveloperRoleAddAppIds", Value::Boolean(true)),
+                ("canDeveloperRoleUpdateAppIds", Value::Boolean(true)),
+            ]),
+        ),
+    ]);
+
+    to_xml(&portal_response(&request_id, 0, vec![("teams", Value::Array(vec![team]))]))
+}
+
+fn portal_response(request_id: &str, result_code: i64, entries: Vec<(&str, Value)>) -> Value {
+    let mut response = vec![
+        ("creationTimestamp", Value::String("2024-01-01T00:00:00Z".to_string())),
+        ("resultCode", Value::Integer(result_code.into())),
+        ("userLocale", Value::String("en_US".to_string())),
+        ("protocolVersion", Value::String("QH65B2".to_string())),
+        ("requestId", Value::String(request_id.to_string())),
+        ("responseId", Value::String("5f0e1a2b-0000-4000-8000-000000000000".to_string())),
+    ];
+    response.extend(entries);
+
+    dict(response)
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use plume_core::auth::{LoginFlow, LoginState};
+use plume_core::developer::DeveloperSession;
+
+use common::*;
+
+#[tokio::test]
+async fn logs_in_fetches_a_token_and_lists_teams() {
+    let apple = FakeApple::start().await;
+
+    let flow = LoginFlow::start_with_account(apple.account().await, APPLE_ID, PASSWORD)
+        .await
+        .unwrap();
+    assert!(matches!(flow.state(), LoginState::LoggedIn));
+
+    let account = flow.finish().unwrap();
+    assert_eq!(account.username(), Some(APPLE_ID));
+    assert_eq!(account.get_pet().as_deref(), Some(PET));
+
+    let token = account.get_app_token(XCODE_APP).await.unwrap();
+    assert_eq!(token.auth_token, XCODE_TOKEN);
+    assert!(!token.is_expired());
+
+    let session = DeveloperSession::with(account);
+    let teams = session.qh_list_teams().await.unwrap();
+    assert_eq!(teams.teams.len(), 1);
+    assert_eq!(teams.teams[0].team_id, TEAM_ID);
+    assert_eq!(teams.teams[0].name, TEAM_NAME);
+
+    // The second listing reuses the cached token instead of asking GSA again
+    session.qh_list_teams().await.unwrap();
+
+    let gsa = format!("POST {}", GSA_PATH);
+    let list_teams = format!("POST {}", LIST_TEAMS_PATH);
+    assert_eq!(apple.requests(), vec![gsa.clone(), gsa.clone(), gsa, list_teams.clone(), list_teams]);
+}
+
+#[tokio::test]
+async fn expired_tokens_are_fetched_again() {
+    let apple = FakeApple::start().await;
+
+    let flow = LoginFlow::start_with_account(apple.account().await, APPLE_ID, PASSWORD)
+        .await
+        .unwrap();
+    let session = DeveloperSession::with(flow.finish().unwrap());
+    session.qh_list_teams().await.unwrap();
+
+    apple.expire_token();
+
+    // Turned down once, then a new token is fetched instead of reusing the cached one
+    assert!(session.qh_list_teams().await.is_err());
+    session.qh_list_teams().await.unwrap();
+
+    let gsa = format!("POST {}", GSA_PATH);
+    let list_teams = format!("POST {}", LIST_TEAMS_PATH);
+    assert_eq!(
+        apple.requests(),
+        vec![gsa.clone(), gsa.clone(), gsa.clone(), list_teams.clone(), list_teams.clone(), gsa, list_teams],
+    );
+}
+
+#[tokio::test]
+async fn unknown_apple_id_is_rejected() {
+    let apple = FakeApple::start().await;
+
+    let result = LoginFlow::start_with_account(apple.account().await, "someone@example.com", PASSWORD).await;
+
+    assert!(matches!(result, Err(plume_core::Error::AuthSrpWithMessage(-20101, _))));
+    assert_eq!(apple.requests(), vec![format!("POST {}", GSA_PATH)]);
+}
+
+#[tokio::test]
+async fn portal_requests_need_a_login() {
+    let apple = FakeApple::start().await;
+
+    let session = DeveloperSession::with(apple.account().await);
+
+    assert!(session.qh_list_teams().await.is_err());
+    assert!(apple.requests().is_empty());
+}
@@ -0,0 +1,209 @@
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use plist::{Dictionary, Value};
+use plume_core::StaticProvider;
+use plume_core::auth::LoginState;
+
+use common::*;
+
+type Mutation = Arc<dyn Fn(Vec<u8>) -> Vec<u8> + Send + Sync>;
+
+// Rewrites the `Response` dictionary of a GSA body
+fn edit_response(edit: impl Fn(&mut Dictionary) + Send + Sync + 'static) -> Mutation {
+    Arc::new(move |body: Vec<u8>| {
+        let mut root: Dictionary = plist::from_bytes(&body).unwrap();
+        if let Some(Value::Dictionary(response)) = root.get_mut("Response") {
+            edit(response);
+        }
+
+        let mut buf = Vec::new();
+        plist::to_writer_xml(&mut buf, &root).unwrap();
+        buf
+    })
+}
+
+fn replace_body(body: &'static [u8]) -> Mutation {
+    Arc::new(move |_: Vec<u8>| body.to_vec())
+}
+
+fn replace_root(root: Dictionary) -> Mutation {
+    Arc::new(move |_: Vec<u8>| {
+        let mut buf = Vec::new();
+        plist::to_writer_xml(&mut buf, &root).unwrap();
+        buf
+    })
+}
+
+// Deterministic so a failure can be reproduced
+fn garbage(len: usize) -> Vec<u8> {
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+// Ways of breaking a response that every operation should turn into an error,
+// with `keys` being the ones the client needs out of it
+fn mutations(keys: &[&'static str]) -> Vec<(String, Mutation)> {
+    let mut mutations: Vec<(String, Mutation)> = vec![
+        ("an empty body".to_string(), replace_body(b"")),
+        ("an HTML page".to_string(), replace_body(b"<html>Service Unavailable</html>")),
+        ("garbage".to_string(), Arc::new(|_: Vec<u8>| garbage(512))),
+        ("no Response".to_string(), replace_root(Dictionary::new())),
+        (
+            "a Response that is a string".to_string(),
+            replace_root(Dictionary::from_iter([("Response".to_string(), Value::String("nope".to_string()))])),
+        ),
+        ("an empty Response".to_string(), edit_response(|response| response.clear())),
+        ("ec that is a string".to_string(), edit_response(|response| {
+            let mut status = Dictionary::new();
+            status.insert("ec".to_string(), Value::String("0".to_string()));
+            response.insert("Status".to_string(), Value::Dictionary(status));
+        })),
+    ];
+
+    for eighths in 1..8 {
+        mutations.push((
+            format!("{}/8 of the body", eighths),
+            Arc::new(move |body: Vec<u8>| body[..body.len() * eighths / 8].to_vec()),
+        ));
+    }
+
+    for &key in keys {
+        mutations.push((format!("no {}", key), edit_response(move |response| {
+            response.remove(key);
+        })));
+        mutations.push((format!("{} that is a bool", key), edit_response(move |response| {
+            response.insert(key.to_string(), Value::Boolean(true));
+        })));
+    }
+
+    mutations
+}
+
+fn set(key: &'static str, value: Value) -> Mutation {
+    edit_response(move |response| {
+        response.insert(key.to_string(), value.clone());
+    })
+}
+
+async fn assert_login_fails(apple: &FakeApple, operation: &str, mutations: Vec<(String, Mutation)>) {
+    for (name, mutation) in mutations {
+        apple.tamper(operation, move |body| mutation(body));
+
+        let mut account = apple.account().await;
+        let result = account.login_email_pass(APPLE_ID, PASSWORD).await;
+
+        assert!(result.is_err(), "{} with {}: {:?}", operation, name, result);
+        assert!(account.get_pet().is_none(), "{} with {} left a session behind", operation, name);
+    }
+}
+
+#[tokio::test]
+async fn malformed_srp_init_responses_are_errors() {
+    let apple = FakeApple::start().await;
+
+    let mut mutations = mutations(&["s", "B", "i", "c", "Status"]);
+    mutations.extend([
+        ("a negative iteration count".to_string(), set("i", Value::Integer((-1).into()))),
+        ("too many iterations".to_string(), set("i", Value::Integer((1u64 << 40).into()))),
+        ("a zero SRP key".to_string(), set("B", Value::Data(vec![0; 256]))),
+    ]);
+
+    assert_login_fails(&apple, "init", mutations).await;
+}
+
+#[tokio::test]
+async fn malformed_srp_complete_responses_are_errors() {
+    let apple = FakeApple::start().await;
+
+    let mut mutations = mutations(&["M2", "spd", "Status"]);
+    mutations.extend([
+        ("the wrong server proof".to_string(), set("M2", Value::Data(vec![0; 32]))),
+        ("spd cut short of a block".to_string(), set("spd", Value::Data(vec![1; 15]))),
+        ("spd that isn't encrypted".to_string(), set("spd", Value::Data(garbage(64)))),
+    ]);
+
+    assert_login_fails(&apple, "complete", mutations).await;
+}
+
+#[tokio::test]
+async fn malformed_session_data_is_an_error_when_read() {
+    let apple = FakeApple::start().await;
+
+    let tampers: [(&str, fn(&mut Dictionary)); 5] = [
+        ("no names", |spd| {
+            spd.remove("fn");
+            spd.remove("ln");
+        }),
+        ("names that are numbers", |spd| {
+            spd.insert("fn".to_string(), Value::Integer(1.into()));
+        }),
+        ("tokens that are a string", |spd| {
+            spd.insert("t".to_string(), Value::String("t".to_string()));
+        }),
+        ("a PET that is a list", |spd| {
+            let mut t = Dictionary::new();
+            t.insert("com.apple.gs.idms.pet".to_string(), Value::Array(Vec::new()));
+            spd.insert("t".to_string(), Value::Dictionary(t));
+            spd.remove("ln");
+        }),
+        ("a session key that is a string", |spd| {
+            spd.insert("sk".to_string(), Value::String("sk".to_string()));
+            spd.remove("t");
+            spd.remove("fn");
+        }),
+    ];
+
+    for (name, tamper) in tampers {
+        apple.tamper_spd(tamper);
+
+        let mut account = apple.account().await;
+        let state = account.login_email_pass(APPLE_ID, PASSWORD).await.unwrap();
+        assert!(matches!(state, LoginState::LoggedIn), "{}", name);
+
+        // Every case breaks the names or the PET, and they're read on demand
+        let name_result = account.get_name();
+        let pet = account.get_pet();
+        assert!(name_result.is_err() || pet.is_none(), "{}: {:?} {:?}", name, name_result, pet);
+    }
+
+    // The last case also broke the session key app tokens are fetched with
+    let mut account = apple.account().await;
+    account.login_email_pass(APPLE_ID, PASSWORD).await.unwrap();
+    assert!(account.get_app_token(XCODE_APP).await.is_err());
+}
+
+#[tokio::test]
+async fn malformed_anisette_headers_never_reach_gsa() {
+    let apple = FakeApple::start().await;
+
+    let client_infos: [Option<&str>; 4] = [
+        None,
+        Some(""),
+        Some("<only> <two>"),
+        Some("<MacBookPro13,2> <macOS;13.1;22C65> <com.apple.AuthKit/1>\r\nX-Injected: 1"),
+    ];
+
+    for client_info in client_infos {
+        let mut headers = HashMap::from([("X-Apple-I-MD".to_string(), "md".to_string())]);
+        if let Some(client_info) = client_info {
+            headers.insert("X-Mme-Client-Info".to_string(), client_info.to_string());
+        }
+
+        let mut account = apple.account_with(Arc::new(StaticProvider::new(headers))).await;
+        let result = account.login_email_pass(APPLE_ID, PASSWORD).await;
+
+        assert!(result.is_err(), "{:?}: {:?}", client_info, result);
+    }
+
+    assert!(apple.requests().is_empty());
+}
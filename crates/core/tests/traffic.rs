@@ -0,0 +1,55 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use plume_core::auth::LoginFlow;
+use plume_core::developer::DeveloperSession;
+
+use common::*;
+
+fn fixture_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("plume-traffic-{}", uuid::Uuid::new_v4()))
+}
+
+#[tokio::test]
+async fn recorded_teams_replay_without_a_server() {
+    let apple = FakeApple::start().await;
+    let dir = fixture_dir();
+
+    let flow = LoginFlow::start_with_account(apple.account().await, APPLE_ID, PASSWORD)
+        .await
+        .unwrap();
+    let mut account = flow.finish().unwrap();
+    account.record_traffic(&dir).unwrap();
+
+    let recorded = DeveloperSession::with(account).qh_list_teams().await.unwrap();
+
+    let fixtures: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+    assert_eq!(fixtures.len(), 1);
+    assert!(fixtures[0].contains(TEAM_ID));
+    assert!(fixtures[0].contains(TEAM_NAME));
+    for secret in [APPLE_ID, "1234567890"] {
+        assert!(!fixtures[0].contains(secret), "{} was recorded", secret);
+    }
+
+    let served = apple.requests().len();
+
+    // Nothing logged in this time, the fixture is all there is
+    let mut account = apple.account().await;
+    account.replay_traffic(&dir).unwrap();
+    let session = DeveloperSession::with(account);
+
+    let replayed = session.qh_list_teams().await.unwrap();
+    assert_eq!(replayed.teams.len(), recorded.teams.len());
+    assert_eq!(replayed.teams[0].team_id, recorded.teams[0].team_id);
+    assert_eq!(apple.requests().len(), served);
+
+    // Each fixture answers once
+    assert!(matches!(session.qh_list_teams().await, Err(plume_core::Error::Traffic(_))));
+
+    fs::remove_dir_all(&dir).ok();
+}
@@ -72,6 +72,16 @@ impl Bundle {
         Ok(())
     }
     
+    pub fn remove_info_plist_key(&self, key: &str) -> Result<(), Error> {
+        let mut plist = Value::from_file(&self.info_plist_file)?;
+        if let Some(dict) = plist.as_dictionary_mut() {
+            dict.remove(key);
+        }
+        plist.to_file_xml(&self.info_plist_file)?;
+
+        Ok(())
+    }
+    
     // TODO: we need to support changing lproj infoplist strings so localized names change as well
     pub fn set_name(&self, new_name: &str) -> Result<(), Error> {
         self.set_info_plist_key("CFBundleDisplayName", new_name)?;
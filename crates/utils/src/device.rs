@@ -56,6 +56,28 @@ impl Device {
         Ok(get_dict_string!(values, "DeviceName"))
     }
 
+    pub async fn get_product_version(&self) -> Result<String, Error> {
+        let Some(usbmuxd_device) = &self.usbmuxd_device else {
+            return Err(Error::Other("Device is not connected via usbmuxd".to_string()));
+        };
+
+        let mut lockdown = LockdownClient::connect(&usbmuxd_device.to_provider(UsbmuxdAddr::default(), CONNECTION_LABEL)).await?;
+        let values = lockdown.get_value(None, None).await?;
+        Ok(get_dict_string!(values, "ProductVersion"))
+    }
+
+    pub fn connection_type(&self) -> &'static str {
+        match &self.usbmuxd_device {
+            Some(device) => match &device.connection_type {
+                Connection::Usb => "USB",
+                Connection::Network(_) => "WiFi",
+                Connection::Unknown(_) => "Unknown",
+            },
+            None => "LOCAL",
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(udid = %self.udid, %identifier))]
     pub async fn install_pairing_record(&self, identifier: &String, path: &str) -> Result<(), Error> {
         if self.usbmuxd_device.is_none() {
             return Err(Error::Other("Device is not connected via USB".to_string()));
@@ -89,10 +111,12 @@ impl Device {
         let mut f = ac.open(path, AfcFopenMode::Wr).await?;
 
         f.write(&pairing_file.serialize().unwrap()).await?;
+        tracing::info!(path, "pairing record written");
 
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(udid = %self.udid, app = %app_path.display()))]
     pub async fn install_app<F, Fut>(&self, app_path: &PathBuf, progress_callback: F) -> Result<(), Error>
     where
         F: FnMut(i32) -> Fut + Send + Clone + 'static,
@@ -116,6 +140,7 @@ impl Device {
 
         let state = ();
 
+        tracing::info!("uploading and installing");
         installation::install_package_with_callback(
             &provider,
             app_path,
@@ -123,11 +148,13 @@ impl Device {
             callback,
             state,
         ).await?;
+        tracing::info!("installed");
 
         Ok(())
     }
 
     #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+    #[tracing::instrument(skip_all, fields(app = %app_path.display()))]
     pub async fn install_app_mac(&self, app_path: &PathBuf) -> Result<(), Error>{
         use std::env;
         use tokio::fs;
@@ -151,12 +178,14 @@ impl Device {
         fs::create_dir_all(&wrapper_dir).await?;
         
         let wrapped_app_path = wrapper_dir.join(app_name);
+        tracing::info!(stage = %stage_dir.display(), "staging wrapped bundle");
         Self::copy_dir_recursively(app_path, &wrapped_app_path).await?;
 
         let wrapped_bundle_path = outer_app_dir.join("WrappedBundle");
         fs::symlink(PathBuf::from("Wrapper").join(app_name), &wrapped_bundle_path).await?;
         
         let applications_dir = PathBuf::from("/Applications").join(app_name);
+        tracing::info!(destination = %applications_dir.display(), "moving into place");
         fs::rename(&outer_app_dir, &applications_dir).await
             .map_err(|_| Error::BundleFailedToCopy(applications_dir.to_string_lossy().into_owned()))?;
 
@@ -188,30 +217,30 @@ impl Device {
 
 impl fmt::Display for Device {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "[{}] {}",
-            match &self.usbmuxd_device {
-                Some(device) => match &device.connection_type {
-                    Connection::Usb => "USB",
-                    Connection::Network(_) => "WiFi",
-                    Connection::Unknown(_) => "Unknown",
-                },
-                None => "LOCAL",
-            },
-            self.name
-        )
+        write!(f, "[{}] {}", self.connection_type(), self.name)
     }
 }
 
+/// Finds a connected device by its usbmuxd device ID or UDID.
 pub async fn get_device_for_id(device_id: &str) -> Result<Device, Error> {
     let mut usbmuxd = UsbmuxdConnection::default().await?;
     let usbmuxd_device = usbmuxd
         .get_devices()
         .await?
         .into_iter()
-        .find(|d| d.device_id.to_string() == device_id)
+        .find(|d| d.device_id.to_string() == device_id || d.udid == device_id)
         .ok_or_else(|| Error::Other(format!("Device ID {device_id} not found")))?;
     
     Ok(Device::new(usbmuxd_device).await)
 }
+
+pub async fn get_devices() -> Result<Vec<Device>, Error> {
+    let mut usbmuxd = UsbmuxdConnection::default().await?;
+    let mut devices = Vec::new();
+
+    for usbmuxd_device in usbmuxd.get_devices().await? {
+        devices.push(Device::new(usbmuxd_device).await);
+    }
+
+    Ok(devices)
+}
@@ -3,6 +3,7 @@ mod package;
 mod bundle;
 mod device;
 mod signer;
+mod paths;
 
 pub use options::{
     SignerOptions, // Main
@@ -13,8 +14,9 @@ pub use options::{
 };
 pub use package::Package; // Package helper
 pub use bundle::{Bundle, BundleType}; // Bundle helper
-pub use device::{Device, get_device_for_id}; // Device helper
+pub use device::{Device, get_device_for_id, get_devices}; // Device helper
 pub use signer::Signer; // Signer
+pub use paths::get_data_path; // App data directory
 
 use thiserror::Error as ThisError;
 #[derive(Debug, ThisError)]
@@ -40,6 +42,8 @@ pub enum Error {
     Idevice(#[from] idevice::IdeviceError),
     #[error("Codesign error: {0}")]
     Codesign(#[from] plume_core::AppleCodesignError),
+    #[error("No home directory to keep app data in")]
+    DataPathMissing,
     #[error("Other error: {0}")]
     Other(String),
 }
@@ -1,12 +1,12 @@
 use std::{
     env, 
     fs, 
-    io::Read
+    io::{self, Read}
 };
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use plist::Dictionary;
 use uuid::Uuid;
-use zip::ZipArchive;
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
 use super::{Bundle, PlistInfoTrait};
 use crate::{Error, SignerApp, SignerOptions};
 
@@ -80,6 +80,87 @@ impl Package {
     pub fn remove_package_stage(self) {
         fs::remove_dir_all(&self.stage_dir).ok();
     }
+
+    /// Archives a bundle into an IPA at `output_file`, as `Payload/<name>.app`.
+    pub fn create_package_from_bundle(bundle: &Bundle, output_file: &PathBuf) -> Result<(), Error> {
+        Self::write_package(bundle, output_file, None)
+    }
+
+    /// Like `create_package_from_bundle`, but keeps what this package has
+    /// next to `Payload/`, e.g. `SwiftSupport/`, `Symbols/` or `iTunesMetadata.plist`.
+    pub fn repackage_with_bundle(&self, bundle: &Bundle, output_file: &PathBuf) -> Result<(), Error> {
+        Self::write_package(bundle, output_file, Some(&self.package_file))
+    }
+
+    fn write_package(bundle: &Bundle, output_file: &PathBuf, original: Option<&Path>) -> Result<(), Error> {
+        let bundle_name = bundle.bundle_dir()
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| Error::Other("Invalid bundle path".to_string()))?;
+
+        let file = fs::File::create(output_file)?;
+        let mut archive = ZipWriter::new(file);
+
+        archive.add_directory("Payload/", SimpleFileOptions::default())?;
+        Self::add_dir_to_archive(&mut archive, bundle.bundle_dir(), &format!("Payload/{bundle_name}"))?;
+
+        if let Some(original) = original {
+            let mut original = ZipArchive::new(fs::File::open(original)?)?;
+
+            for i in 0..original.len() {
+                let entry = original.by_index_raw(i)?;
+                if entry.name() == "Payload" || entry.name().starts_with("Payload/") {
+                    continue;
+                }
+                // Copied as is, these aren't touched by signing
+                archive.raw_copy_file(entry)?;
+            }
+        }
+
+        archive.finish()?;
+
+        Ok(())
+    }
+
+    fn add_dir_to_archive(
+        archive: &mut ZipWriter<fs::File>,
+        dir: &Path,
+        prefix: &str,
+    ) -> Result<(), Error> {
+        archive.add_directory(format!("{prefix}/"), SimpleFileOptions::default())?;
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = format!("{prefix}/{}", entry.file_name().to_string_lossy());
+            let metadata = fs::symlink_metadata(&path)?;
+
+            if metadata.file_type().is_symlink() {
+                let target = fs::read_link(&path)?;
+                archive.add_symlink(name, target.to_string_lossy(), SimpleFileOptions::default())?;
+            } else if metadata.is_dir() {
+                Self::add_dir_to_archive(archive, &path, &name)?;
+            } else {
+                // Executables need to keep their mode, installd won't fix it for us
+                #[cfg(unix)]
+                let mode = {
+                    use std::os::unix::fs::PermissionsExt;
+                    metadata.permissions().mode()
+                };
+                #[cfg(not(unix))]
+                let mode = 0o755;
+
+                let options = SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Deflated)
+                    .unix_permissions(mode);
+
+                archive.start_file(name, options)?;
+                io::copy(&mut fs::File::open(&path)?, archive)?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 // TODO: make bundle and package share a common trait for plist info access
@@ -131,3 +212,69 @@ impl Package {
         *settings = new_settings;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_ipa(path: &Path, entries: &[(&str, &str)]) {
+        let mut archive = ZipWriter::new(fs::File::create(path).unwrap());
+        for (name, contents) in entries {
+            archive.start_file(*name, SimpleFileOptions::default()).unwrap();
+            io::Write::write_all(&mut archive, contents.as_bytes()).unwrap();
+        }
+        archive.finish().unwrap();
+    }
+
+    fn entry_names(path: &Path) -> Vec<String> {
+        let archive = ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+        archive.file_names().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn repackaging_keeps_what_is_next_to_the_payload() {
+        let dir = env::temp_dir().join(format!("plume-package-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut info = Dictionary::new();
+        info.insert("CFBundleIdentifier".to_string(), "com.example.app".into());
+        let mut info_plist = Vec::new();
+        plist::to_writer_xml(&mut info_plist, &info).unwrap();
+        let info_plist = String::from_utf8(info_plist).unwrap();
+
+        let original = dir.join("original.ipa");
+        write_ipa(&original, &[
+            ("Payload/Example.app/Info.plist", info_plist.as_str()),
+            ("SwiftSupport/iphoneos/libswiftCore.dylib", "swift"),
+            ("Symbols/ABCD.symbols", "symbols"),
+            ("iTunesMetadata.plist", "metadata"),
+        ]);
+
+        let package = Package::new(original).unwrap();
+        let bundle = package.get_package_bundle().unwrap();
+        let output = dir.join("output.ipa");
+        package.repackage_with_bundle(&bundle, &output).unwrap();
+
+        let names = entry_names(&output);
+        for name in [
+            "Payload/Example.app/Info.plist",
+            "SwiftSupport/iphoneos/libswiftCore.dylib",
+            "Symbols/ABCD.symbols",
+            "iTunesMetadata.plist",
+        ] {
+            assert_eq!(names.iter().filter(|n| *n == name).count(), 1, "{} in {:?}", name, names);
+        }
+
+        let mut archive = ZipArchive::new(fs::File::open(&output).unwrap()).unwrap();
+        let mut metadata = String::new();
+        archive.by_name("iTunesMetadata.plist").unwrap().read_to_string(&mut metadata).unwrap();
+        assert_eq!(metadata, "metadata");
+
+        // Bundles that weren't an IPA have nothing to keep
+        Package::create_package_from_bundle(&bundle, &output).unwrap();
+        assert!(entry_names(&output).iter().all(|name| name.starts_with("Payload/")));
+
+        package.remove_package_stage();
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,22 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::Error;
+
+const DATA_DIR: &str = "PlumeImpactor";
+
+/// Where the GUI and plumesign keep accounts, certificates and settings,
+/// `%APPDATA%\PlumeImpactor` on Windows and `~/.config/PlumeImpactor` elsewhere.
+pub fn get_data_path() -> Result<PathBuf, Error> {
+    // Not `dirs::config_dir` everywhere, that would move macOS data to Application Support
+    let base = if cfg!(windows) {
+        dirs::config_dir()
+    } else {
+        dirs::home_dir().map(|home| home.join(".config"))
+    };
+
+    let dir = base.ok_or(Error::DataPathMissing)?.join(DATA_DIR);
+    fs::create_dir_all(&dir)?;
+
+    Ok(dir)
+}
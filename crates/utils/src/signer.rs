@@ -5,6 +5,7 @@ use std::sync::Arc;
 
 use plume_core::{
     CertificateIdentity,
+    MachO,
     MobileProvision,
     SettingsScope,
     SigningSettings,
@@ -24,7 +25,7 @@ use crate::{
 pub struct Signer {
     certificate: Option<CertificateIdentity>,
     pub options: SignerOptions,
-    provisioning_files: Vec<MobileProvision>,
+    pub provisioning_files: Vec<MobileProvision>,
 }
 
 impl Signer {
@@ -53,6 +54,7 @@ impl Signer {
         }
     }
 
+    #[tracing::instrument(skip_all, fields(bundle = %bundle.bundle_dir().display()))]
     pub async fn modify_bundle(&mut self, bundle: &Bundle, team_id: &Option<String>) -> Result<(), Error> {
         let bundles = bundle.collect_bundles_sorted()?;
 
@@ -85,6 +87,10 @@ impl Signer {
             bundle.set_info_plist_key("CADisableMinimumFrameDurationOnPhone", true)?;
         }
 
+        if self.options.features.remove_url_schemes {
+            bundle.remove_info_plist_key("CFBundleURLTypes")?;
+        }
+
         let identifier = bundle.get_bundle_identifier();
 
         if self.options.mode != SignerMode::Export && self.options.custom_identifier.is_none() {
@@ -134,6 +140,7 @@ impl Signer {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(bundle = %bundle.bundle_dir().display()))]
     pub async fn register_bundle(
         &mut self, 
         bundle: &Bundle,
@@ -167,10 +174,11 @@ impl Signer {
                     .ok_or_else(|| Error::Other("Failed to get bundle executable name.".into()))?;
                 let bundle_executable_path = sub_bundle.bundle_dir().join(&bundle_executable_name);
 
-                let macho = plume_core::MachO::new(&bundle_executable_path)?;
+                let macho = MachO::new(&bundle_executable_path)?;
 
                 let id = sub_bundle.get_bundle_identifier()
                     .ok_or_else(|| Error::Other("Failed to get bundle identifier.".into()))?;
+                tracing::info!(identifier = %id, "registering app id");
 
                 session.qh_ensure_app_id(&team_id, &sub_bundle.get_name().unwrap_or_default(), &id).await?;
 
@@ -216,6 +224,7 @@ impl Signer {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(bundle = %bundle.bundle_dir().display()))]
     pub async fn sign_bundle(&self, bundle: &Bundle) -> Result<(), Error> {
         let bundles = bundle.collect_bundles_sorted()?;
 
@@ -236,6 +245,51 @@ impl Signer {
         Ok(())
     }
 
+    /// Removes the code signature and `embedded.mobileprovision` from `bundle`
+    /// and every bundle nested in it.
+    #[tracing::instrument(skip_all, fields(bundle = %bundle.bundle_dir().display()))]
+    pub async fn unsign_bundle(bundle: &Bundle) -> Result<(), Error> {
+        let bundles = bundle.collect_bundles_sorted()?;
+
+        for bundle in &bundles {
+            let bundle_dir = bundle.bundle_dir();
+
+            let signature_dir = bundle_dir.join("_CodeSignature");
+            if signature_dir.exists() {
+                fs::remove_dir_all(&signature_dir).await?;
+            }
+
+            let provision_path = bundle_dir.join("embedded.mobileprovision");
+            if provision_path.exists() {
+                fs::remove_file(&provision_path).await?;
+            }
+
+            let mut binaries = Vec::new();
+
+            if let Some(bundle_executable) = bundle.get_executable() {
+                binaries.push(bundle_dir.join(bundle_executable));
+            }
+
+            // Loose dylibs aren't bundles, so they won't show up on their own
+            let frameworks_dir = bundle_dir.join("Frameworks");
+            if frameworks_dir.is_dir() {
+                for entry in std::fs::read_dir(&frameworks_dir)? {
+                    let path = entry?.path();
+                    if path.extension().and_then(|e| e.to_str()) == Some("dylib") {
+                        binaries.push(path);
+                    }
+                }
+            }
+
+            for binary_path in binaries.iter().filter(|p| p.is_file()) {
+                MachO::strip_code_signature(binary_path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "sign", skip_all, fields(bundle = %bundle.bundle_dir().display()))]
     fn sign_single_bundle(
         bundle: &Bundle,
         certificate: Option<&CertificateIdentity>,
@@ -288,11 +342,24 @@ impl Signer {
                     entitlements_xml = String::from_utf8_lossy(&ent_xml).to_string();
                 }
             }
+        } else if certificate.is_none_or(|c| c.cert.is_none()) {
+            // Ad-hoc signatures have no profile to take entitlements from, so
+            // keep whatever the binary was signed with before
+            if let Some(bundle_executable) = bundle.get_executable() {
+                let binary_path = bundle.bundle_dir().join(bundle_executable);
+
+                if let Some(entitlements) = MachO::new(&binary_path).ok().and_then(|m| m.entitlements) {
+                    let mut ent_xml = Vec::new();
+                    Value::Dictionary(entitlements).to_writer_xml(&mut ent_xml)?;
+                    entitlements_xml = String::from_utf8_lossy(&ent_xml).to_string();
+                }
+            }
         }
 
         settings.set_entitlements_xml(SettingsScope::Main, entitlements_xml)?;
 
         UnifiedSigner::new(settings).sign_path_in_place(bundle.bundle_dir())?;
+        tracing::info!("signed");
 
         Ok(())
     }
@@ -302,7 +369,9 @@ impl Signer {
     ) -> Result<SigningSettings<'_>, Error> {
         let mut settings = SigningSettings::default();
 
-        if let Some(cert) = certificate {
+        // `Signer::adhoc` passes an identity without a certificate, leaving
+        // the settings without a signing key makes apple-codesign sign ad-hoc
+        if let Some(cert) = certificate.filter(|c| c.cert.is_some()) {
             cert.load_into_signing_settings(&mut settings)?;
             settings.set_team_id_from_signing_certificate();
         }
@@ -314,3 +383,45 @@ impl Signer {
         Ok(settings)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app_with_url_schemes() -> Bundle {
+        let app_dir = std::env::temp_dir()
+            .join(format!("plume-signer-{}", uuid::Uuid::new_v4()))
+            .join("Example.app");
+        std::fs::create_dir_all(&app_dir).unwrap();
+
+        let mut url_type = plist::Dictionary::new();
+        url_type.insert("CFBundleURLSchemes".to_string(), Value::Array(vec!["example".into()]));
+
+        let mut info = plist::Dictionary::new();
+        info.insert("CFBundleIdentifier".to_string(), "com.example.app".into());
+        info.insert("CFBundleURLTypes".to_string(), Value::Array(vec![Value::Dictionary(url_type)]));
+        Value::Dictionary(info).to_file_xml(app_dir.join("Info.plist")).unwrap();
+
+        Bundle::new(app_dir).unwrap()
+    }
+
+    async fn url_types_after_modifying(remove_url_schemes: bool) -> Option<Value> {
+        let bundle = app_with_url_schemes();
+
+        let mut options = SignerOptions::default();
+        options.mode = SignerMode::Export;
+        options.features.remove_url_schemes = remove_url_schemes;
+        Signer::new(None, options).modify_bundle(&bundle, &None).await.unwrap();
+
+        let info = Value::from_file(bundle.bundle_dir().join("Info.plist")).unwrap();
+        std::fs::remove_dir_all(bundle.bundle_dir().parent().unwrap()).ok();
+
+        info.as_dictionary().unwrap().get("CFBundleURLTypes").cloned()
+    }
+
+    #[tokio::test]
+    async fn url_schemes_are_only_removed_when_asked() {
+        assert!(url_types_after_modifying(true).await.is_none());
+        assert!(url_types_after_modifying(false).await.is_some());
+    }
+}